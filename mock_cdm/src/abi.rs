@@ -0,0 +1,240 @@
+use std::os::raw::{c_char, c_void};
+
+// Layouts of the `cdm` types of `content_decryption_module.h` that the mock
+// uses. Classes are a pointer to their vtable, laid out per the Itanium C++
+// ABI: one slot per virtual method in declaration order, then two for the
+// destructor.
+
+pub const INTERFACE_VERSION: i32 = 10;
+
+pub type GetCdmHostFunc = extern "C" fn(i32, *mut c_void) -> *mut c_void;
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    Success,
+    NeedMoreData,
+    NoKey,
+    InitializationError,
+    DecryptError,
+    DecodeError,
+    DeferredInitialization,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Exception {
+    TypeError,
+    NotSupportedError,
+    InvalidStateError,
+    QuotaExceededError,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyStatus {
+    Usable,
+    InternalError,
+    Expired,
+    OutputRestricted,
+    OutputDownscaled,
+    StatusPending,
+    Released,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MessageType {
+    LicenseRequest,
+    LicenseRenewal,
+    LicenseRelease,
+    IndividualizationRequest,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FileStatus {
+    Success,
+    InUse,
+    Error,
+}
+
+// Enums received from the host are kept as integers, an unknown value
+// mustn't be undefined behavior.
+pub const SESSION_TYPE_TEMPORARY: u32 = 0;
+pub const SESSION_TYPE_PERSISTENT_LICENSE: u32 = 1;
+
+pub const INIT_DATA_TYPE_CENC: u32 = 0;
+pub const INIT_DATA_TYPE_KEY_IDS: u32 = 1;
+pub const INIT_DATA_TYPE_WEBM: u32 = 2;
+
+pub const ENCRYPTION_SCHEME_UNENCRYPTED: u32 = 0;
+pub const ENCRYPTION_SCHEME_CENC: u32 = 1;
+pub const ENCRYPTION_SCHEME_CBCS: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SubsampleEntry {
+    pub clear_bytes: u32,
+    pub cipher_bytes: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Pattern {
+    pub crypt_byte_block: u32,
+    pub skip_byte_block: u32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct InputBuffer2 {
+    pub data: *const u8,
+    pub data_size: u32,
+    pub encryption_scheme: u32,
+    pub key_id: *const u8,
+    pub key_id_size: u32,
+    _padding_1: u32,
+    pub iv: *const u8,
+    pub iv_size: u32,
+    _padding_2: u32,
+    pub subsamples: *const SubsampleEntry,
+    pub num_subsamples: u32,
+    _padding_3: u32,
+    pub pattern: Pattern,
+    pub timestamp: i64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct KeyInformation {
+    pub key_id: *const u8,
+    pub key_id_size: u32,
+    pub status: KeyStatus,
+    pub system_code: u32,
+}
+
+#[repr(C)]
+pub struct Host10 {
+    pub vtable: *const HostVtable,
+}
+
+#[repr(C)]
+pub struct HostVtable {
+    pub allocate: unsafe extern "C" fn(*mut Host10, u32) -> *mut Buffer,
+    pub set_timer: unsafe extern "C" fn(*mut Host10, i64, *mut c_void),
+    pub get_current_wall_time: unsafe extern "C" fn(*mut Host10) -> f64,
+    pub on_initialized: unsafe extern "C" fn(*mut Host10, bool),
+    pub on_resolve_key_status_promise: unsafe extern "C" fn(*mut Host10, u32, KeyStatus),
+    pub on_resolve_new_session_promise: unsafe extern "C" fn(*mut Host10, u32, *const c_char, u32),
+    pub on_resolve_promise: unsafe extern "C" fn(*mut Host10, u32),
+    pub on_reject_promise:
+        unsafe extern "C" fn(*mut Host10, u32, Exception, u32, *const c_char, u32),
+    pub on_session_message:
+        unsafe extern "C" fn(*mut Host10, *const c_char, u32, MessageType, *const c_char, u32),
+    pub on_session_keys_change:
+        unsafe extern "C" fn(*mut Host10, *const c_char, u32, bool, *const KeyInformation, u32),
+    pub on_expiration_change: unsafe extern "C" fn(*mut Host10, *const c_char, u32, f64),
+    pub on_session_closed: unsafe extern "C" fn(*mut Host10, *const c_char, u32),
+    pub send_platform_challenge:
+        unsafe extern "C" fn(*mut Host10, *const c_char, u32, *const c_char, u32),
+    pub enable_output_protection: unsafe extern "C" fn(*mut Host10, u32),
+    pub query_output_protection_status: unsafe extern "C" fn(*mut Host10),
+    pub on_deferred_initialization_done: unsafe extern "C" fn(*mut Host10, u32, Status),
+    pub create_file_io: unsafe extern "C" fn(*mut Host10, *mut c_void) -> *mut FileIO,
+    pub request_storage_id: unsafe extern "C" fn(*mut Host10, u32),
+}
+
+#[repr(C)]
+pub struct Buffer {
+    pub vtable: *const BufferVtable,
+}
+
+#[repr(C)]
+pub struct BufferVtable {
+    pub destroy: unsafe extern "C" fn(*mut Buffer),
+    pub capacity: unsafe extern "C" fn(*const Buffer) -> u32,
+    pub data: unsafe extern "C" fn(*mut Buffer) -> *mut u8,
+    pub set_size: unsafe extern "C" fn(*mut Buffer, u32),
+    pub size: unsafe extern "C" fn(*const Buffer) -> u32,
+}
+
+#[repr(C)]
+pub struct DecryptedBlock {
+    pub vtable: *const DecryptedBlockVtable,
+}
+
+#[repr(C)]
+pub struct DecryptedBlockVtable {
+    pub set_decrypted_buffer: unsafe extern "C" fn(*mut DecryptedBlock, *mut Buffer),
+    pub decrypted_buffer: unsafe extern "C" fn(*mut DecryptedBlock) -> *mut Buffer,
+    pub set_timestamp: unsafe extern "C" fn(*mut DecryptedBlock, i64),
+    pub timestamp: unsafe extern "C" fn(*const DecryptedBlock) -> i64,
+}
+
+#[repr(C)]
+pub struct FileIO {
+    pub vtable: *const FileIOVtable,
+}
+
+#[repr(C)]
+pub struct FileIOVtable {
+    pub open: unsafe extern "C" fn(*mut FileIO, *const c_char, u32),
+    pub read: unsafe extern "C" fn(*mut FileIO),
+    pub write: unsafe extern "C" fn(*mut FileIO, *const u8, u32),
+    pub close: unsafe extern "C" fn(*mut FileIO),
+}
+
+/// Implemented by the mock, the object handed to `Host_10::CreateFileIO`.
+#[repr(C)]
+pub struct FileIOClientVtable<T> {
+    pub on_open_complete: unsafe extern "C" fn(*mut T, FileStatus),
+    pub on_read_complete: unsafe extern "C" fn(*mut T, FileStatus, *const u8, u32),
+    pub on_write_complete: unsafe extern "C" fn(*mut T, FileStatus),
+    pub destructor: unsafe extern "C" fn(*mut T),
+    pub deleting_destructor: unsafe extern "C" fn(*mut T),
+}
+
+/// Implemented by the mock, the object returned by `CreateCdmInstance`.
+#[repr(C)]
+pub struct CdmVtable<T> {
+    pub initialize: unsafe extern "C" fn(*mut T, bool, bool, bool),
+    pub get_status_for_policy: unsafe extern "C" fn(*mut T, u32, *const c_void),
+    pub set_server_certificate: unsafe extern "C" fn(*mut T, u32, *const u8, u32),
+    pub create_session_and_generate_request:
+        unsafe extern "C" fn(*mut T, u32, u32, u32, *const u8, u32),
+    pub load_session: unsafe extern "C" fn(*mut T, u32, u32, *const c_char, u32),
+    pub update_session: unsafe extern "C" fn(*mut T, u32, *const c_char, u32, *const u8, u32),
+    pub close_session: unsafe extern "C" fn(*mut T, u32, *const c_char, u32),
+    pub remove_session: unsafe extern "C" fn(*mut T, u32, *const c_char, u32),
+    pub timer_expired: unsafe extern "C" fn(*mut T, *mut c_void),
+    pub decrypt: unsafe extern "C" fn(*mut T, *const InputBuffer2, *mut DecryptedBlock) -> Status,
+    pub initialize_audio_decoder: unsafe extern "C" fn(*mut T, *const c_void) -> Status,
+    pub initialize_video_decoder: unsafe extern "C" fn(*mut T, *const c_void) -> Status,
+    pub deinitialize_decoder: unsafe extern "C" fn(*mut T, u32),
+    pub reset_decoder: unsafe extern "C" fn(*mut T, u32),
+    pub decrypt_and_decode_frame:
+        unsafe extern "C" fn(*mut T, *const InputBuffer2, *mut c_void) -> Status,
+    pub decrypt_and_decode_samples:
+        unsafe extern "C" fn(*mut T, *const InputBuffer2, *mut c_void) -> Status,
+    pub on_platform_challenge_response: unsafe extern "C" fn(*mut T, *const c_void),
+    pub on_query_output_protection_status: unsafe extern "C" fn(*mut T, u32, u32, u32),
+    pub on_storage_id: unsafe extern "C" fn(*mut T, u32, *const u8, u32),
+    pub destroy: unsafe extern "C" fn(*mut T),
+    pub destructor: unsafe extern "C" fn(*mut T),
+    pub deleting_destructor: unsafe extern "C" fn(*mut T),
+}
+
+/// Views a pointer and size pair from the host, which may be null when empty.
+///
+/// # Safety
+///
+/// A non-null `data` must point to `size` readable bytes outliving `'a`.
+pub unsafe fn slice<'a, T>(data: *const T, size: u32) -> &'a [T] {
+    if data.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, size as usize)
+    }
+}
@@ -0,0 +1,563 @@
+use crate::abi::{
+    slice, DecryptedBlock, Exception, InputBuffer2, KeyStatus, MessageType, Status,
+    ENCRYPTION_SCHEME_CBCS, ENCRYPTION_SCHEME_CENC, ENCRYPTION_SCHEME_UNENCRYPTED,
+    SESSION_TYPE_PERSISTENT_LICENSE, SESSION_TYPE_TEMPORARY,
+};
+use crate::decrypt::{decrypt, Scheme};
+use crate::host::Host;
+use crate::license::{self, License};
+use crate::script::{Action, Call, Script};
+use crate::storage::FileClient;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::ptr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What sessions send when the script has them individualize the device.
+const INDIVIDUALIZATION_REQUEST: &[u8] = b"individualization request";
+
+/// Work scheduled with a host timer.
+enum Task {
+    Renew(String),
+    Expire(String),
+}
+
+struct Session {
+    persistent: bool,
+    key_ids: Vec<Vec<u8>>,
+    license: Option<License>,
+    status: KeyStatus,
+    removed: bool,
+    /// Waits for a provisioning response before its license request.
+    individualizing: bool,
+}
+
+impl Session {
+    fn new(persistent: bool, key_ids: Vec<Vec<u8>>) -> Self {
+        Self {
+            persistent,
+            key_ids,
+            license: None,
+            status: KeyStatus::StatusPending,
+            removed: false,
+            individualizing: false,
+        }
+    }
+
+    fn key_statuses(&self) -> Vec<(Vec<u8>, KeyStatus)> {
+        self.license
+            .iter()
+            .flat_map(|license| license.keys.iter())
+            .map(|(key_id, _)| (key_id.clone(), self.status))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    script: Script,
+    sessions: HashMap<String, Session>,
+    session_count: u32,
+    file_clients: Vec<*mut FileClient>,
+}
+
+/// A ClearKey-like CDM: licenses are JSON Web Key sets handing out the
+/// content keys, see `License`.
+///
+/// The state is only borrowed between calls to the host, which may call back
+/// into the CDM before returning.
+pub struct MockCdm {
+    host: Host,
+    state: RefCell<State>,
+}
+
+impl MockCdm {
+    pub fn new(host: Host) -> Self {
+        Self {
+            host,
+            state: RefCell::new(State::default()),
+        }
+    }
+
+    pub fn initialize(&self) {
+        self.scripted(Call::Initialize, None, |cdm, _| {
+            cdm.host.on_initialized(true)
+        });
+    }
+
+    /// Replaces the failures to inject with a JSON script, see `Script`.
+    /// Returns whether the script is valid.
+    pub fn set_script(&self, json: &[u8]) -> bool {
+        let script = serde_json::from_slice::<Value>(json)
+            .ok()
+            .and_then(|json| Script::parse(&json));
+        match script {
+            Some(script) => {
+                self.state.borrow_mut().script = script;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Any certificate but an empty one is accepted.
+    pub fn set_server_certificate(&self, promise_id: u32, certificate: &[u8]) {
+        let empty = certificate.is_empty();
+        self.scripted(
+            Call::SetServerCertificate,
+            Some(promise_id),
+            move |cdm, _| {
+                if empty {
+                    cdm.reject(promise_id, Exception::TypeError, "empty certificate");
+                } else {
+                    cdm.host.resolve(promise_id);
+                }
+            },
+        );
+    }
+
+    pub fn create_session(
+        &self,
+        promise_id: u32,
+        session_type: u32,
+        init_data_type: u32,
+        init_data: Vec<u8>,
+    ) {
+        self.scripted(Call::CreateSession, Some(promise_id), move |cdm, action| {
+            let persistent = match session_type {
+                SESSION_TYPE_TEMPORARY => false,
+                SESSION_TYPE_PERSISTENT_LICENSE => true,
+                _ => {
+                    let message = "unsupported session type";
+                    return cdm.reject(promise_id, Exception::NotSupportedError, message);
+                }
+            };
+            let key_ids = match license::key_ids(init_data_type, &init_data) {
+                Some(key_ids) => key_ids,
+                None => return cdm.reject(promise_id, Exception::TypeError, "no key IDs"),
+            };
+
+            let session_id = cdm.new_session_id();
+            let request = license::request(&key_ids, session_type_name(persistent));
+            let mut session = Session::new(persistent, key_ids);
+            session.individualizing = action == Some(Action::Individualize);
+            let (message_type, message) = if session.individualizing {
+                (
+                    MessageType::IndividualizationRequest,
+                    INDIVIDUALIZATION_REQUEST.to_vec(),
+                )
+            } else {
+                (MessageType::LicenseRequest, request)
+            };
+            cdm.state
+                .borrow_mut()
+                .sessions
+                .insert(session_id.clone(), session);
+            cdm.host.resolve_new_session(promise_id, Some(&session_id));
+            cdm.host
+                .session_message(&session_id, message_type, &message);
+        });
+    }
+
+    pub fn load_session(&self, promise_id: u32, session_type: u32, session_id: String) {
+        self.free_file_clients();
+        self.scripted(Call::LoadSession, Some(promise_id), move |cdm, _| {
+            if session_type != SESSION_TYPE_PERSISTENT_LICENSE {
+                let message = "only persistent licenses can be loaded";
+                return cdm.reject(promise_id, Exception::TypeError, message);
+            }
+            if cdm.state.borrow().sessions.contains_key(&session_id) {
+                let message = "session already loaded";
+                return cdm.reject(promise_id, Exception::InvalidStateError, message);
+            }
+
+            let name = session_id.clone();
+            cdm.read_file(&name, move |cdm, result| {
+                let license = match result {
+                    Ok(ref data) if data.is_empty() => {
+                        return cdm.host.resolve_new_session(promise_id, None)
+                    }
+                    Ok(data) => License::parse(&data),
+                    Err(()) => None,
+                };
+                let license = match license {
+                    Some(license) => license,
+                    None => {
+                        let message = "failed to read the license";
+                        return cdm.reject(promise_id, Exception::InvalidStateError, message);
+                    }
+                };
+
+                let key_ids = license.keys.iter().map(|(key_id, _)| key_id.clone());
+                let session = Session::new(true, key_ids.collect());
+                cdm.state
+                    .borrow_mut()
+                    .sessions
+                    .insert(session_id.clone(), session);
+                cdm.apply_license(&session_id, license);
+                cdm.host.resolve_new_session(promise_id, Some(&session_id));
+            });
+        });
+    }
+
+    pub fn update_session(&self, promise_id: u32, session_id: String, response: Vec<u8>) {
+        self.free_file_clients();
+        self.scripted(Call::UpdateSession, Some(promise_id), move |cdm, _| {
+            let session = cdm
+                .state
+                .borrow()
+                .sessions
+                .get(&session_id)
+                .map(|session| (session.persistent, session.removed, session.individualizing));
+            let persistent = match session {
+                // Acknowledges the release of the license.
+                Some((_, true, _)) => return cdm.host.resolve(promise_id),
+                Some((_, false, true)) => {
+                    return cdm.individualize(promise_id, &session_id, &response)
+                }
+                Some((persistent, false, false)) => persistent,
+                None => return cdm.reject(promise_id, Exception::InvalidStateError, "no session"),
+            };
+            let license = match License::parse(&response) {
+                Some(license) => license,
+                None => return cdm.reject(promise_id, Exception::TypeError, "invalid license"),
+            };
+
+            let license = cdm.apply_license(&session_id, license);
+            if !persistent {
+                return cdm.host.resolve(promise_id);
+            }
+            let record = license.to_json().to_string().into_bytes();
+            cdm.write_file(&session_id, record, move |cdm, result| match result {
+                Ok(()) => cdm.host.resolve(promise_id),
+                Err(()) => {
+                    let message = "failed to store the license";
+                    cdm.reject(promise_id, Exception::InvalidStateError, message)
+                }
+            });
+        });
+    }
+
+    pub fn close_session(&self, promise_id: u32, session_id: String) {
+        self.scripted(Call::CloseSession, Some(promise_id), move |cdm, _| {
+            let session = cdm.state.borrow_mut().sessions.remove(&session_id);
+            if session.is_none() {
+                return cdm.reject(promise_id, Exception::InvalidStateError, "no session");
+            }
+            cdm.host.resolve(promise_id);
+            cdm.host.session_closed(&session_id);
+        });
+    }
+
+    /// Releases the keys of a session. Persistent licenses are deleted and a
+    /// `LicenseRelease` message sent, answered with any `update_session`.
+    pub fn remove_session(&self, promise_id: u32, session_id: String) {
+        self.free_file_clients();
+        self.scripted(Call::RemoveSession, Some(promise_id), move |cdm, _| {
+            let session = cdm
+                .state
+                .borrow_mut()
+                .sessions
+                .get_mut(&session_id)
+                .map(|session| {
+                    session.removed = true;
+                    session.status = KeyStatus::Released;
+                    (
+                        session.persistent,
+                        session.key_ids.clone(),
+                        session.key_statuses(),
+                    )
+                });
+            let (persistent, key_ids, keys) = match session {
+                Some(session) => session,
+                None => return cdm.reject(promise_id, Exception::InvalidStateError, "no session"),
+            };
+
+            cdm.host.keys_change(&session_id, false, &keys);
+            if !persistent {
+                return cdm.host.resolve(promise_id);
+            }
+            let name = session_id.clone();
+            cdm.write_file(&name, Vec::new(), move |cdm, result| match result {
+                Ok(()) => {
+                    let message = license::request(&key_ids, session_type_name(true));
+                    cdm.host
+                        .session_message(&session_id, MessageType::LicenseRelease, &message);
+                    cdm.host.resolve(promise_id);
+                }
+                Err(()) => {
+                    let message = "failed to delete the license";
+                    cdm.reject(promise_id, Exception::InvalidStateError, message)
+                }
+            });
+        });
+    }
+
+    /// # Safety
+    ///
+    /// `context` must come from one of the CDM's timers.
+    pub unsafe fn timer_expired(&self, context: *mut c_void) {
+        match *Box::from_raw(context as *mut Task) {
+            Task::Renew(session_id) => {
+                let request = self
+                    .state
+                    .borrow()
+                    .sessions
+                    .get(&session_id)
+                    .and_then(|session| match session.status {
+                        KeyStatus::Usable => {
+                            let session_type = session_type_name(session.persistent);
+                            Some(license::request(&session.key_ids, session_type))
+                        }
+                        _ => None,
+                    });
+                if let Some(request) = request {
+                    self.host
+                        .session_message(&session_id, MessageType::LicenseRenewal, &request);
+                }
+            }
+            Task::Expire(session_id) => {
+                let keys = self
+                    .state
+                    .borrow_mut()
+                    .sessions
+                    .get_mut(&session_id)
+                    .map(|session| {
+                        session.status = KeyStatus::Expired;
+                        session.key_statuses()
+                    });
+                if let Some(keys) = keys {
+                    self.host.keys_change(&session_id, false, &keys);
+                }
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The buffers of `input` must be valid and `block` a
+    /// `cdm::DecryptedBlock`.
+    pub unsafe fn decrypt(&self, input: &InputBuffer2, block: *mut DecryptedBlock) -> Status {
+        let injected = self.state.borrow_mut().script.take(Call::Decrypt);
+        match injected {
+            Some(Action::Status(status)) => return status,
+            Some(Action::Reject { .. }) => return Status::DecryptError,
+            _ => {}
+        }
+
+        let data = slice(input.data, input.data_size);
+        let scheme = match input.encryption_scheme {
+            ENCRYPTION_SCHEME_UNENCRYPTED => None,
+            ENCRYPTION_SCHEME_CENC => Some(Scheme::Cenc),
+            ENCRYPTION_SCHEME_CBCS => Some(Scheme::Cbcs {
+                crypt_byte_block: input.pattern.crypt_byte_block,
+                skip_byte_block: input.pattern.skip_byte_block,
+            }),
+            _ => return Status::DecryptError,
+        };
+        let decrypted = match scheme {
+            Some(scheme) => {
+                let key = match self.usable_key(slice(input.key_id, input.key_id_size)) {
+                    Some(key) => key,
+                    None => return Status::NoKey,
+                };
+                let subsamples: Vec<(u32, u32)> = slice(input.subsamples, input.num_subsamples)
+                    .iter()
+                    .map(|subsample| (subsample.clear_bytes, subsample.cipher_bytes))
+                    .collect();
+                match decrypt(
+                    scheme,
+                    &key,
+                    slice(input.iv, input.iv_size),
+                    data,
+                    &subsamples,
+                ) {
+                    Ok(decrypted) => decrypted,
+                    Err(()) => return Status::DecryptError,
+                }
+            }
+            None => data.to_vec(),
+        };
+
+        let size = decrypted.len() as u32;
+        let buffer = self.host.allocate(size);
+        if buffer.is_null() {
+            return Status::DecryptError;
+        }
+        let buffer_vtable = &*(*buffer).vtable;
+        if (buffer_vtable.capacity)(buffer) < size {
+            (buffer_vtable.destroy)(buffer);
+            return Status::DecryptError;
+        }
+        ptr::copy_nonoverlapping(
+            decrypted.as_ptr(),
+            (buffer_vtable.data)(buffer),
+            decrypted.len(),
+        );
+        (buffer_vtable.set_size)(buffer, size);
+        let block_vtable = &*(*block).vtable;
+        (block_vtable.set_decrypted_buffer)(block, buffer);
+        (block_vtable.set_timestamp)(block, input.timestamp);
+        Status::Success
+    }
+
+    pub fn unsupported(&self, promise_id: u32, message: &str) {
+        self.reject(promise_id, Exception::NotSupportedError, message);
+    }
+
+    pub fn destroy(&self) {
+        for client in self.state.borrow_mut().file_clients.drain(..) {
+            unsafe { FileClient::free(client) };
+        }
+    }
+
+    /// Carries out a call, unless the script says otherwise. Actions the
+    /// call carries out itself are passed to it.
+    fn scripted(
+        &self,
+        call: Call,
+        promise_id: Option<u32>,
+        run: impl FnOnce(&Self, Option<Action>),
+    ) {
+        let action = self.state.borrow_mut().script.take(call);
+        match action {
+            Some(Action::Reject {
+                exception,
+                system_code,
+                message,
+            }) => match promise_id {
+                Some(promise_id) => self
+                    .host
+                    .reject(promise_id, exception, system_code, &message),
+                None => self.host.on_initialized(false),
+            },
+            Some(Action::Drop) => {}
+            action => run(self, action),
+        }
+    }
+
+    /// Takes any response but an empty one as provisioning the device, then
+    /// sends the license request the session held back.
+    fn individualize(&self, promise_id: u32, session_id: &str, response: &[u8]) {
+        if response.is_empty() {
+            let message = "invalid provisioning response";
+            return self.reject(promise_id, Exception::TypeError, message);
+        }
+        let request = {
+            let mut state = self.state.borrow_mut();
+            let session = match state.sessions.get_mut(session_id) {
+                Some(session) => session,
+                None => return,
+            };
+            session.individualizing = false;
+            license::request(&session.key_ids, session_type_name(session.persistent))
+        };
+        self.host.resolve(promise_id);
+        self.host
+            .session_message(session_id, MessageType::LicenseRequest, &request);
+    }
+
+    fn reject(&self, promise_id: u32, exception: Exception, message: &str) {
+        self.host.reject(promise_id, exception, 0, message);
+    }
+
+    fn set_timer(&self, delay_ms: i64, task: Task) {
+        let context = Box::into_raw(Box::new(task)) as *mut c_void;
+        self.host.set_timer(delay_ms, context);
+    }
+
+    /// Merges the keys of a license into its session, reports them and
+    /// schedules its events. Returns the merged license.
+    fn apply_license(&self, session_id: &str, license: License) -> License {
+        let (merged, keys) = {
+            let mut state = self.state.borrow_mut();
+            let session = match state.sessions.get_mut(session_id) {
+                Some(session) => session,
+                None => return license,
+            };
+            let mut merged = session.license.take().unwrap_or_default();
+            for (key_id, key) in &license.keys {
+                merged.keys.retain(|(id, _)| id != key_id);
+                merged.keys.push((key_id.clone(), *key));
+            }
+            merged.expiration = license.expiration.or(merged.expiration);
+            session.license = Some(merged.clone());
+            session.status = KeyStatus::Usable;
+            (merged, session.key_statuses())
+        };
+
+        self.host.keys_change(session_id, true, &keys);
+        if let Some(expiration) = license.expiration {
+            self.host.expiration_change(session_id, expiration);
+        }
+        if let Some(delay_ms) = license.renew_after_ms {
+            self.set_timer(delay_ms, Task::Renew(session_id.to_string()));
+        }
+        if let Some(delay_ms) = license.expire_after_ms {
+            self.set_timer(delay_ms, Task::Expire(session_id.to_string()));
+        }
+        merged
+    }
+
+    fn usable_key(&self, key_id: &[u8]) -> Option<[u8; 16]> {
+        let state = self.state.borrow();
+        state
+            .sessions
+            .values()
+            .filter(|session| session.status == KeyStatus::Usable)
+            .flat_map(|session| session.license.iter())
+            .flat_map(|license| license.keys.iter())
+            .find(|(id, _)| id == key_id)
+            .map(|(_, key)| *key)
+    }
+
+    fn new_session_id(&self) -> String {
+        let mut state = self.state.borrow_mut();
+        state.session_count += 1;
+        // Unique across instances, as persistent sessions outlive them.
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        format!("{:x}-{}", time.as_nanos(), state.session_count)
+    }
+
+    fn read_file(&self, name: &str, completion: impl FnOnce(&Self, Result<Vec<u8>, ()>) + 'static) {
+        let client = FileClient::read(self, &self.host, name, completion);
+        self.state.borrow_mut().file_clients.push(client);
+    }
+
+    fn write_file(
+        &self,
+        name: &str,
+        data: Vec<u8>,
+        completion: impl FnOnce(&Self, Result<(), ()>) + 'static,
+    ) {
+        let client = FileClient::write(self, &self.host, name, data, completion);
+        self.state.borrow_mut().file_clients.push(client);
+    }
+
+    /// Only called when entered from the host, so no client is in the
+    /// middle of a callback.
+    fn free_file_clients(&self) {
+        self.state
+            .borrow_mut()
+            .file_clients
+            .retain(|client| unsafe {
+                let done = FileClient::is_done(*client);
+                if done {
+                    FileClient::free(*client);
+                }
+                !done
+            });
+    }
+}
+
+fn session_type_name(persistent: bool) -> &'static str {
+    if persistent {
+        "persistent-license"
+    } else {
+        "temporary"
+    }
+}
@@ -0,0 +1,184 @@
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Block};
+
+const BLOCK_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scheme {
+    /// AES-CTR, encrypted ranges forming a single keystream.
+    Cenc,
+    /// AES-CBC following a pattern of encrypted and clear blocks, with the
+    /// IV reset for every subsample.
+    Cbcs {
+        crypt_byte_block: u32,
+        skip_byte_block: u32,
+    },
+}
+
+/// Decrypts a sample. `subsamples` are `(clear_bytes, cipher_bytes)` pairs
+/// covering the whole sample, no subsamples meaning it's all encrypted.
+pub fn decrypt(
+    scheme: Scheme,
+    key: &[u8; 16],
+    iv: &[u8],
+    data: &[u8],
+    subsamples: &[(u32, u32)],
+) -> Result<Vec<u8>, ()> {
+    let iv = match iv.len() {
+        8 if scheme == Scheme::Cenc => {
+            let mut padded = [0; BLOCK_SIZE];
+            padded[..8].copy_from_slice(iv);
+            padded
+        }
+        BLOCK_SIZE => {
+            let mut full = [0; BLOCK_SIZE];
+            full.copy_from_slice(iv);
+            full
+        }
+        _ => return Err(()),
+    };
+    let whole = [(0, data.len() as u32)];
+    let subsamples = if subsamples.is_empty() {
+        &whole
+    } else {
+        subsamples
+    };
+    let ranges = encrypted_ranges(data.len(), subsamples)?;
+    let cipher = Aes128::new(key.into());
+    let mut output = data.to_vec();
+
+    match scheme {
+        Scheme::Cenc => {
+            let mut encrypted: Vec<u8> = ranges
+                .iter()
+                .flat_map(|range| data[range.clone()].iter().copied())
+                .collect();
+            ctr(&cipher, iv, &mut encrypted);
+            let mut decrypted = encrypted.iter();
+            for range in ranges {
+                for byte in &mut output[range] {
+                    *byte = *decrypted.next().unwrap();
+                }
+            }
+        }
+        Scheme::Cbcs {
+            crypt_byte_block,
+            skip_byte_block,
+        } => {
+            // A pattern of 0:0 encrypts every block.
+            let (crypt, skip) = match (crypt_byte_block, skip_byte_block) {
+                (0, 0) => (1, 0),
+                pattern => (pattern.0 as usize, pattern.1 as usize),
+            };
+            for range in ranges {
+                cbc_pattern(&cipher, iv, &mut output[range], crypt, skip);
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn encrypted_ranges(
+    size: usize,
+    subsamples: &[(u32, u32)],
+) -> Result<Vec<std::ops::Range<usize>>, ()> {
+    let mut ranges = Vec::with_capacity(subsamples.len());
+    let mut offset = 0;
+    for &(clear, cipher) in subsamples {
+        let start = offset + clear as usize;
+        offset = start + cipher as usize;
+        ranges.push(start..offset);
+    }
+    if offset == size {
+        Ok(ranges)
+    } else {
+        Err(())
+    }
+}
+
+fn ctr(cipher: &Aes128, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    let mut counter = u128::from_be_bytes(iv);
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        let mut keystream = Block::from(counter.to_be_bytes());
+        cipher.encrypt_block(&mut keystream);
+        for (byte, key) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= key;
+        }
+        counter = counter.wrapping_add(1);
+    }
+}
+
+/// Trailing partial blocks are left in the clear.
+fn cbc_pattern(cipher: &Aes128, iv: [u8; BLOCK_SIZE], data: &mut [u8], crypt: usize, skip: usize) {
+    let mut chain = Block::from(iv);
+    let blocks: Vec<&mut [u8]> = data.chunks_exact_mut(BLOCK_SIZE).collect();
+    for (index, block) in blocks.into_iter().enumerate() {
+        if index % (crypt + skip) >= crypt {
+            continue;
+        }
+        let encrypted = Block::clone_from_slice(block);
+        let mut decrypted = encrypted;
+        cipher.decrypt_block(&mut decrypted);
+        for ((byte, plain), chained) in block.iter_mut().zip(decrypted.iter()).zip(chain.iter()) {
+            *byte = plain ^ chained;
+        }
+        chain = encrypted;
+    }
+}
+
+#[test]
+fn test_decrypt() {
+    // NIST SP 800-38A, F.5.1 and F.2.1.
+    let key = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    let plaintext = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a, 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf,
+        0x8e, 0x51,
+    ];
+    let ctr_iv = [
+        0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe,
+        0xff,
+    ];
+    let ctr_ciphertext = [
+        0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6,
+        0xce, 0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff, 0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff,
+        0xfd, 0xff,
+    ];
+    let cbc_iv: Vec<u8> = (0..16).collect();
+    let cbc_ciphertext = [
+        0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19,
+        0x7d, 0x50, 0x86, 0xcb, 0x9b, 0x50, 0x72, 0x19, 0xee, 0x95, 0xdb, 0x11, 0x3a, 0x91, 0x76,
+        0x78, 0xb2,
+    ];
+
+    // The keystream carries on from one subsample to the next.
+    let mut sample = b"clear".to_vec();
+    sample.extend_from_slice(&ctr_ciphertext[..7]);
+    sample.extend_from_slice(b"header");
+    sample.extend_from_slice(&ctr_ciphertext[7..]);
+    let mut expected = b"clear".to_vec();
+    expected.extend_from_slice(&plaintext[..7]);
+    expected.extend_from_slice(b"header");
+    expected.extend_from_slice(&plaintext[7..]);
+    let subsamples = [(5, 7), (6, 25)];
+    assert_eq!(
+        decrypt(Scheme::Cenc, &key, &ctr_iv, &sample, &subsamples),
+        Ok(expected)
+    );
+
+    let cbcs = Scheme::Cbcs {
+        crypt_byte_block: 0,
+        skip_byte_block: 0,
+    };
+    let mut sample = cbc_ciphertext.to_vec();
+    sample.extend_from_slice(b"tail");
+    let mut expected = plaintext.to_vec();
+    expected.extend_from_slice(b"tail");
+    assert_eq!(decrypt(cbcs, &key, &cbc_iv, &sample, &[]), Ok(expected));
+
+    assert!(decrypt(Scheme::Cenc, &key, &ctr_iv, &sample, &[(1, 1)]).is_err());
+    assert!(decrypt(cbcs, &key, &cbc_iv[..8], &sample, &[]).is_err());
+}
@@ -0,0 +1,128 @@
+use crate::abi::{
+    Buffer, Exception, FileIO, Host10, HostVtable, KeyInformation, KeyStatus, MessageType,
+};
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+/// The `cdm::Host_10` given to `CreateCdmInstance`, alive as long as the CDM.
+pub struct Host(*mut Host10);
+
+impl Host {
+    /// # Safety
+    ///
+    /// `host` must be a `cdm::Host_10` outliving the returned value.
+    pub unsafe fn new(host: *mut c_void) -> Self {
+        Self(host as *mut Host10)
+    }
+
+    fn vtable(&self) -> &HostVtable {
+        unsafe { &*(*self.0).vtable }
+    }
+
+    pub fn on_initialized(&self, success: bool) {
+        unsafe { (self.vtable().on_initialized)(self.0, success) }
+    }
+
+    pub fn resolve(&self, promise_id: u32) {
+        unsafe { (self.vtable().on_resolve_promise)(self.0, promise_id) }
+    }
+
+    /// `None` resolves with a null session ID, which is how `LoadSession`
+    /// reports a missing session.
+    pub fn resolve_new_session(&self, promise_id: u32, session_id: Option<&str>) {
+        let (pointer, size) = match session_id {
+            Some(session_id) => (c_str(session_id), session_id.len() as u32),
+            None => (ptr::null(), 0),
+        };
+        unsafe { (self.vtable().on_resolve_new_session_promise)(self.0, promise_id, pointer, size) }
+    }
+
+    pub fn reject(&self, promise_id: u32, exception: Exception, system_code: u32, message: &str) {
+        unsafe {
+            (self.vtable().on_reject_promise)(
+                self.0,
+                promise_id,
+                exception,
+                system_code,
+                c_str(message),
+                message.len() as u32,
+            )
+        }
+    }
+
+    pub fn session_message(&self, session_id: &str, message_type: MessageType, message: &[u8]) {
+        unsafe {
+            (self.vtable().on_session_message)(
+                self.0,
+                c_str(session_id),
+                session_id.len() as u32,
+                message_type,
+                message.as_ptr() as *const c_char,
+                message.len() as u32,
+            )
+        }
+    }
+
+    pub fn keys_change(
+        &self,
+        session_id: &str,
+        has_additional_usable_key: bool,
+        keys: &[(Vec<u8>, KeyStatus)],
+    ) {
+        let keys_info: Vec<KeyInformation> = keys
+            .iter()
+            .map(|(key_id, status)| KeyInformation {
+                key_id: key_id.as_ptr(),
+                key_id_size: key_id.len() as u32,
+                status: *status,
+                system_code: 0,
+            })
+            .collect();
+        unsafe {
+            (self.vtable().on_session_keys_change)(
+                self.0,
+                c_str(session_id),
+                session_id.len() as u32,
+                has_additional_usable_key,
+                keys_info.as_ptr(),
+                keys_info.len() as u32,
+            )
+        }
+    }
+
+    /// `expiration` is in seconds since the epoch, 0 or NaN meaning never.
+    pub fn expiration_change(&self, session_id: &str, expiration: f64) {
+        unsafe {
+            (self.vtable().on_expiration_change)(
+                self.0,
+                c_str(session_id),
+                session_id.len() as u32,
+                expiration,
+            )
+        }
+    }
+
+    pub fn session_closed(&self, session_id: &str) {
+        unsafe {
+            (self.vtable().on_session_closed)(self.0, c_str(session_id), session_id.len() as u32)
+        }
+    }
+
+    pub fn set_timer(&self, delay_ms: i64, context: *mut c_void) {
+        unsafe { (self.vtable().set_timer)(self.0, delay_ms, context) }
+    }
+
+    pub fn allocate(&self, capacity: u32) -> *mut Buffer {
+        unsafe { (self.vtable().allocate)(self.0, capacity) }
+    }
+
+    pub fn create_file_io(&self, client: *mut c_void) -> *mut FileIO {
+        unsafe { (self.vtable().create_file_io)(self.0, client) }
+    }
+}
+
+/// Strings are passed with their size and without a NUL terminator, which
+/// `cdm::Host` doesn't promise, so that hosts reading past them are caught.
+fn c_str(string: &str) -> *const c_char {
+    string.as_ptr() as *const c_char
+}
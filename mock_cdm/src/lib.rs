@@ -0,0 +1,240 @@
+#[allow(dead_code)]
+mod abi;
+mod cdm;
+mod decrypt;
+mod host;
+mod license;
+mod script;
+mod storage;
+
+use abi::{
+    slice, CdmVtable, DecryptedBlock, GetCdmHostFunc, InputBuffer2, Status, INTERFACE_VERSION,
+};
+use cdm::MockCdm;
+use host::Host;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+/// The `cdm::ContentDecryptionModule_10` handed to the host.
+#[repr(C)]
+struct CdmObject {
+    vtable: &'static CdmVtable<CdmObject>,
+    cdm: MockCdm,
+}
+
+static VTABLE: CdmVtable<CdmObject> = CdmVtable {
+    initialize,
+    get_status_for_policy,
+    set_server_certificate,
+    create_session_and_generate_request,
+    load_session,
+    update_session,
+    close_session,
+    remove_session,
+    timer_expired,
+    decrypt,
+    initialize_audio_decoder: initialize_decoder,
+    initialize_video_decoder: initialize_decoder,
+    deinitialize_decoder: reset_decoder,
+    reset_decoder,
+    decrypt_and_decode_frame: decrypt_and_decode,
+    decrypt_and_decode_samples: decrypt_and_decode,
+    on_platform_challenge_response,
+    on_query_output_protection_status,
+    on_storage_id,
+    destroy,
+    destructor,
+    deleting_destructor: destructor,
+};
+
+#[no_mangle]
+pub extern "C" fn InitializeCdmModule_4() {}
+
+#[no_mangle]
+pub extern "C" fn DeinitializeCdmModule() {}
+
+#[no_mangle]
+pub extern "C" fn GetCdmVersion() -> *const c_char {
+    concat!("mock-", env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Key systems the mock stands in for.
+const KEY_SYSTEMS: &[&str] = &[
+    "com.widevine.alpha",
+    "org.w3.clearkey",
+    "org.chromium.externalclearkey",
+];
+
+/// Other key systems are turned down before asking for a host, like real
+/// CDMs do.
+///
+/// # Safety
+///
+/// The host returned by `get_cdm_host` must outlive the CDM.
+#[no_mangle]
+pub unsafe extern "C" fn CreateCdmInstance(
+    cdm_interface_version: i32,
+    key_system: *const c_char,
+    key_system_size: u32,
+    get_cdm_host: GetCdmHostFunc,
+    user_data: *mut c_void,
+) -> *mut c_void {
+    let key_system = string(key_system, key_system_size);
+    if cdm_interface_version != INTERFACE_VERSION || !KEY_SYSTEMS.contains(&key_system.as_str()) {
+        return ptr::null_mut();
+    }
+    let host = get_cdm_host(INTERFACE_VERSION, user_data);
+    if host.is_null() {
+        return ptr::null_mut();
+    }
+
+    let object = CdmObject {
+        vtable: &VTABLE,
+        cdm: MockCdm::new(Host::new(host)),
+    };
+    Box::into_raw(Box::new(object)) as *mut c_void
+}
+
+/// Replaces the failures a CDM injects with a JSON script, see `Script`.
+/// Tests look it up with `dlsym`, the CDM interface has no room for it.
+///
+/// # Safety
+///
+/// `cdm` must have been returned by `CreateCdmInstance`.
+#[no_mangle]
+pub unsafe extern "C" fn MockCdmSetScript(
+    cdm: *mut c_void,
+    script: *const u8,
+    script_size: u32,
+) -> bool {
+    let object = cdm as *mut CdmObject;
+    (*object).cdm.set_script(slice(script, script_size))
+}
+
+unsafe extern "C" fn initialize(this: *mut CdmObject, _: bool, _: bool, _: bool) {
+    (*this).cdm.initialize();
+}
+
+unsafe extern "C" fn get_status_for_policy(
+    this: *mut CdmObject,
+    promise_id: u32,
+    _: *const c_void,
+) {
+    let message = "policies are not supported";
+    (*this).cdm.unsupported(promise_id, message);
+}
+
+unsafe extern "C" fn set_server_certificate(
+    this: *mut CdmObject,
+    promise_id: u32,
+    certificate: *const u8,
+    certificate_size: u32,
+) {
+    let certificate = slice(certificate, certificate_size);
+    (*this).cdm.set_server_certificate(promise_id, certificate);
+}
+
+unsafe extern "C" fn create_session_and_generate_request(
+    this: *mut CdmObject,
+    promise_id: u32,
+    session_type: u32,
+    init_data_type: u32,
+    init_data: *const u8,
+    init_data_size: u32,
+) {
+    let init_data = slice(init_data, init_data_size).to_vec();
+    (*this)
+        .cdm
+        .create_session(promise_id, session_type, init_data_type, init_data);
+}
+
+unsafe extern "C" fn load_session(
+    this: *mut CdmObject,
+    promise_id: u32,
+    session_type: u32,
+    session_id: *const c_char,
+    session_id_size: u32,
+) {
+    let session_id = string(session_id, session_id_size);
+    (*this)
+        .cdm
+        .load_session(promise_id, session_type, session_id);
+}
+
+unsafe extern "C" fn update_session(
+    this: *mut CdmObject,
+    promise_id: u32,
+    session_id: *const c_char,
+    session_id_size: u32,
+    response: *const u8,
+    response_size: u32,
+) {
+    let session_id = string(session_id, session_id_size);
+    let response = slice(response, response_size).to_vec();
+    (*this).cdm.update_session(promise_id, session_id, response);
+}
+
+unsafe extern "C" fn close_session(
+    this: *mut CdmObject,
+    promise_id: u32,
+    session_id: *const c_char,
+    session_id_size: u32,
+) {
+    let session_id = string(session_id, session_id_size);
+    (*this).cdm.close_session(promise_id, session_id);
+}
+
+unsafe extern "C" fn remove_session(
+    this: *mut CdmObject,
+    promise_id: u32,
+    session_id: *const c_char,
+    session_id_size: u32,
+) {
+    let session_id = string(session_id, session_id_size);
+    (*this).cdm.remove_session(promise_id, session_id);
+}
+
+unsafe extern "C" fn timer_expired(this: *mut CdmObject, context: *mut c_void) {
+    (*this).cdm.timer_expired(context);
+}
+
+unsafe extern "C" fn decrypt(
+    this: *mut CdmObject,
+    encrypted_buffer: *const InputBuffer2,
+    decrypted_block: *mut DecryptedBlock,
+) -> Status {
+    (*this).cdm.decrypt(&*encrypted_buffer, decrypted_block)
+}
+
+/// Decoding is not supported.
+unsafe extern "C" fn initialize_decoder(_: *mut CdmObject, _: *const c_void) -> Status {
+    Status::InitializationError
+}
+
+unsafe extern "C" fn reset_decoder(_: *mut CdmObject, _: u32) {}
+
+unsafe extern "C" fn decrypt_and_decode(
+    _: *mut CdmObject,
+    _: *const InputBuffer2,
+    _: *mut c_void,
+) -> Status {
+    Status::DecodeError
+}
+
+unsafe extern "C" fn on_platform_challenge_response(_: *mut CdmObject, _: *const c_void) {}
+
+unsafe extern "C" fn on_query_output_protection_status(_: *mut CdmObject, _: u32, _: u32, _: u32) {}
+
+unsafe extern "C" fn on_storage_id(_: *mut CdmObject, _: u32, _: *const u8, _: u32) {}
+
+unsafe extern "C" fn destroy(this: *mut CdmObject) {
+    let object = Box::from_raw(this);
+    object.cdm.destroy();
+}
+
+/// Never called, the destructor is protected.
+unsafe extern "C" fn destructor(_: *mut CdmObject) {}
+
+unsafe fn string(data: *const c_char, size: u32) -> String {
+    String::from_utf8_lossy(slice(data as *const u8, size)).into_owned()
+}
@@ -0,0 +1,188 @@
+use crate::abi::{INIT_DATA_TYPE_CENC, INIT_DATA_TYPE_KEY_IDS, INIT_DATA_TYPE_WEBM};
+use serde_json::{json, Value};
+use std::convert::TryInto;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const CLEAR_KEY_SYSTEM_ID: [u8; 16] = [
+    0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02, 0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb, 0x4b,
+];
+const WIDEVINE_SYSTEM_ID: [u8; 16] = [
+    0xed, 0xef, 0x8b, 0xa9, 0x79, 0xd6, 0x4a, 0xce, 0xa3, 0xc8, 0x27, 0xdc, 0xd5, 0x1d, 0x21, 0xed,
+];
+
+/// Unpadded base64url, as used by ClearKey.
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+            bits | (*byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..=chunk.len() {
+            encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+pub fn decode(data: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for character in data.trim_end_matches('=').bytes() {
+        let value = ALPHABET.iter().position(|c| *c == character)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// The key IDs of init data, from ClearKey or Widevine PSSH boxes, a key IDs
+/// JSON object or a WebM key ID.
+pub fn key_ids(init_data_type: u32, data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let key_ids = match init_data_type {
+        INIT_DATA_TYPE_CENC => pssh_key_ids(data)?,
+        INIT_DATA_TYPE_KEY_IDS => {
+            let json: Value = serde_json::from_slice(data).ok()?;
+            json["kids"]
+                .as_array()?
+                .iter()
+                .map(|key_id| decode(key_id.as_str()?))
+                .collect::<Option<_>>()?
+        }
+        INIT_DATA_TYPE_WEBM => vec![data.to_vec()],
+        _ => return None,
+    };
+    if key_ids.is_empty() || key_ids.iter().any(Vec::is_empty) {
+        None
+    } else {
+        Some(key_ids)
+    }
+}
+
+fn pssh_key_ids(mut data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut key_ids = Vec::new();
+    while !data.is_empty() {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let pssh = data.get(..size)?;
+        data = &data[size..];
+        if size < 32 || &pssh[4..8] != b"pssh" {
+            return None;
+        }
+        let version = pssh[8];
+        let system_id = &pssh[12..28];
+        let mut body = &pssh[28..];
+        if version > 0 {
+            let count = u32::from_be_bytes(body.get(..4)?.try_into().ok()?) as usize;
+            let ids = body.get(4..4 + count * 16)?;
+            if system_id == CLEAR_KEY_SYSTEM_ID || system_id == WIDEVINE_SYSTEM_ID {
+                key_ids.extend(ids.chunks(16).map(<[u8]>::to_vec));
+            }
+            body = &body[4 + count * 16..];
+        }
+        if version == 0 && system_id == WIDEVINE_SYSTEM_ID {
+            let size = u32::from_be_bytes(body.get(..4)?.try_into().ok()?) as usize;
+            key_ids.extend(widevine_key_ids(body.get(4..4 + size)?)?);
+        }
+    }
+    Some(key_ids)
+}
+
+/// `WidevinePsshData.key_id`, field 2.
+fn widevine_key_ids(mut data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut key_ids = Vec::new();
+    while !data.is_empty() {
+        let key = varint(&mut data)?;
+        let length = match key & 7 {
+            0 => {
+                varint(&mut data)?;
+                0
+            }
+            1 => 8,
+            2 => varint(&mut data)? as usize,
+            5 => 4,
+            _ => return None,
+        };
+        let value = data.get(..length)?;
+        if key == (2 << 3 | 2) {
+            key_ids.push(value.to_vec());
+        }
+        data = &data[length..];
+    }
+    Some(key_ids)
+}
+
+fn varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The message asking for the keys of a session.
+pub fn request(key_ids: &[Vec<u8>], session_type: &str) -> Vec<u8> {
+    let key_ids: Vec<String> = key_ids.iter().map(|key_id| encode(key_id)).collect();
+    json!({ "kids": key_ids, "type": session_type })
+        .to_string()
+        .into_bytes()
+}
+
+/// A ClearKey JSON Web Key set, with a few extensions for scheduling the
+/// mock's own events:
+///
+/// - `expiration`: seconds since the epoch, reported as the session's
+///   expiration
+/// - `renew_after_ms`: delay before a `LicenseRenewal` message is sent
+/// - `expire_after_ms`: delay before the keys expire
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct License {
+    pub keys: Vec<(Vec<u8>, [u8; 16])>,
+    pub expiration: Option<f64>,
+    pub renew_after_ms: Option<i64>,
+    pub expire_after_ms: Option<i64>,
+}
+
+impl License {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let json: Value = serde_json::from_slice(data).ok()?;
+        let keys = json["keys"]
+            .as_array()?
+            .iter()
+            .map(|key| {
+                if key["kty"] != "oct" {
+                    return None;
+                }
+                let key_id = decode(key["kid"].as_str()?)?;
+                let key = decode(key["k"].as_str()?)?;
+                Some((key_id, key.as_slice().try_into().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if keys.is_empty() {
+            return None;
+        }
+        Some(Self {
+            keys,
+            expiration: json["expiration"].as_f64(),
+            renew_after_ms: json["renew_after_ms"].as_i64(),
+            expire_after_ms: json["expire_after_ms"].as_i64(),
+        })
+    }
+
+    /// Only the keys and expiration, the timers are not persisted.
+    pub fn to_json(&self) -> Value {
+        let keys: Vec<Value> = self
+            .keys
+            .iter()
+            .map(|(key_id, key)| json!({ "kty": "oct", "kid": encode(key_id), "k": encode(key) }))
+            .collect();
+        json!({ "keys": keys, "expiration": self.expiration })
+    }
+}
@@ -0,0 +1,149 @@
+use crate::abi::{Exception, Status};
+use serde_json::Value;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Call {
+    Initialize,
+    SetServerCertificate,
+    CreateSession,
+    LoadSession,
+    UpdateSession,
+    CloseSession,
+    RemoveSession,
+    Decrypt,
+}
+
+impl Call {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "initialize" => Call::Initialize,
+            "set_server_certificate" => Call::SetServerCertificate,
+            "create_session" => Call::CreateSession,
+            "load_session" => Call::LoadSession,
+            "update_session" => Call::UpdateSession,
+            "close_session" => Call::CloseSession,
+            "remove_session" => Call::RemoveSession,
+            "decrypt" => Call::Decrypt,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Rejects the call's promise. `initialize` reports a failure instead.
+    Reject {
+        exception: Exception,
+        system_code: u32,
+        message: String,
+    },
+    /// Returned by `decrypt`.
+    Status(Status),
+    /// Never settles the call's promise.
+    Drop,
+    /// Makes `create_session` send an `IndividualizationRequest` first. Its
+    /// license request follows the provisioning response.
+    Individualize,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    call: Call,
+    skip: u32,
+    /// `None` applies the rule to every call.
+    times: Option<u32>,
+    action: Action,
+}
+
+/// Failures to inject, set with `MockCdmSetScript` as a JSON object with a
+/// `script` array of rules:
+///
+/// ```json
+/// {"script": [
+///   {"call": "create_session", "reject": "quota_exceeded", "system_code": 7},
+///   {"call": "decrypt", "status": "no_key", "skip": 1, "times": 0},
+///   {"call": "close_session", "drop": true},
+///   {"call": "create_session", "individualize": true}
+/// ]}
+/// ```
+///
+/// The first rule left for a call handles it, after letting `skip` calls
+/// through, for `times` calls (1 by default, 0 meaning all of them).
+/// Rejections take an optional `system_code` and `message`.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    rules: Vec<Rule>,
+}
+
+impl Script {
+    pub fn parse(json: &Value) -> Option<Self> {
+        let rules = json["script"]
+            .as_array()?
+            .iter()
+            .map(parse_rule)
+            .collect::<Option<_>>()?;
+        Some(Self { rules })
+    }
+
+    /// The action to take for a call, if any.
+    pub fn take(&mut self, call: Call) -> Option<Action> {
+        let index = self.rules.iter().position(|rule| rule.call == call)?;
+        let rule = &mut self.rules[index];
+        if rule.skip > 0 {
+            rule.skip -= 1;
+            return None;
+        }
+
+        let action = rule.action.clone();
+        match rule.times {
+            Some(1) => {
+                self.rules.remove(index);
+            }
+            Some(ref mut times) => *times -= 1,
+            None => {}
+        }
+        Some(action)
+    }
+}
+
+fn parse_rule(json: &Value) -> Option<Rule> {
+    let action = if let Some(exception) = json["reject"].as_str() {
+        Action::Reject {
+            exception: match exception {
+                "type_error" => Exception::TypeError,
+                "not_supported" => Exception::NotSupportedError,
+                "invalid_state" => Exception::InvalidStateError,
+                "quota_exceeded" => Exception::QuotaExceededError,
+                _ => return None,
+            },
+            system_code: json["system_code"].as_u64().unwrap_or(0) as u32,
+            message: json["message"]
+                .as_str()
+                .unwrap_or("injected failure")
+                .to_string(),
+        }
+    } else if let Some(status) = json["status"].as_str() {
+        Action::Status(match status {
+            "no_key" => Status::NoKey,
+            "decrypt_error" => Status::DecryptError,
+            "initialization_error" => Status::InitializationError,
+            _ => return None,
+        })
+    } else if json["drop"] == true {
+        Action::Drop
+    } else if json["individualize"] == true {
+        Action::Individualize
+    } else {
+        return None;
+    };
+
+    Some(Rule {
+        call: Call::parse(json["call"].as_str()?)?,
+        skip: json["skip"].as_u64().unwrap_or(0) as u32,
+        times: match json["times"].as_u64() {
+            Some(0) => None,
+            times => Some(times.unwrap_or(1) as u32),
+        },
+        action,
+    })
+}
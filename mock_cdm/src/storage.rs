@@ -0,0 +1,167 @@
+use crate::abi::{FileIO, FileIOClientVtable, FileIOVtable, FileStatus};
+use crate::cdm::MockCdm;
+use crate::host::Host;
+use std::os::raw::c_void;
+use std::ptr;
+
+type Completion<T> = Box<dyn FnOnce(&MockCdm, Result<T, ()>)>;
+
+enum Operation {
+    Read(Completion<Vec<u8>>),
+    Write(Completion<()>),
+}
+
+/// A `cdm::FileIOClient` carrying out a single operation on a file, then
+/// calling back the CDM. An operation the host completes before its call to
+/// the `FileIO` returns fails, as the interface forbids it.
+#[repr(C)]
+pub struct FileClient {
+    vtable: &'static FileIOClientVtable<FileClient>,
+    cdm: *const MockCdm,
+    file_io: *mut FileIO,
+    data: Vec<u8>,
+    completion: Option<Operation>,
+    /// Set during calls to the `FileIO`.
+    calling: bool,
+}
+
+static VTABLE: FileIOClientVtable<FileClient> = FileIOClientVtable {
+    on_open_complete,
+    on_read_complete,
+    on_write_complete,
+    destructor,
+    deleting_destructor: destructor,
+};
+
+impl FileClient {
+    /// Reads a file, a missing one reading as empty. The returned client has
+    /// to be kept until it's done, then freed with `free`.
+    pub fn read(
+        cdm: &MockCdm,
+        host: &Host,
+        name: &str,
+        completion: impl FnOnce(&MockCdm, Result<Vec<u8>, ()>) + 'static,
+    ) -> *mut Self {
+        let operation = Operation::Read(Box::new(completion));
+        Self::start(cdm, host, name, Vec::new(), operation)
+    }
+
+    /// Replaces the contents of a file, writing nothing deleting it. Same as
+    /// for `read`, the client has to be kept until it's done.
+    pub fn write(
+        cdm: &MockCdm,
+        host: &Host,
+        name: &str,
+        data: Vec<u8>,
+        completion: impl FnOnce(&MockCdm, Result<(), ()>) + 'static,
+    ) -> *mut Self {
+        let operation = Operation::Write(Box::new(completion));
+        Self::start(cdm, host, name, data, operation)
+    }
+
+    fn start(
+        cdm: &MockCdm,
+        host: &Host,
+        name: &str,
+        data: Vec<u8>,
+        operation: Operation,
+    ) -> *mut Self {
+        let client = Box::into_raw(Box::new(Self {
+            vtable: &VTABLE,
+            cdm,
+            file_io: ptr::null_mut(),
+            data,
+            completion: Some(operation),
+            calling: false,
+        }));
+        unsafe {
+            let file_io = host.create_file_io(client as *mut c_void);
+            if file_io.is_null() {
+                complete(client, Err(()));
+            } else {
+                (*client).file_io = file_io;
+                call(client, |file_io, vtable| {
+                    (vtable.open)(file_io, name.as_ptr() as *const _, name.len() as u32)
+                });
+            }
+        }
+        client
+    }
+
+    /// # Safety
+    ///
+    /// `client` must come from `start` and not have been freed.
+    pub unsafe fn is_done(client: *mut Self) -> bool {
+        (*client).completion.is_none()
+    }
+
+    /// # Safety
+    ///
+    /// `client` must come from `start` and not be in the middle of a callback.
+    pub unsafe fn free(client: *mut Self) {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Calls the client's `FileIO`. No reference to the client is held, as a
+/// misbehaving host may call it back before returning.
+unsafe fn call(client: *mut FileClient, call: impl FnOnce(*mut FileIO, &FileIOVtable)) {
+    let file_io = (*client).file_io;
+    (*client).calling = true;
+    call(file_io, &*(*file_io).vtable);
+    (*client).calling = false;
+}
+
+unsafe extern "C" fn on_open_complete(client: *mut FileClient, status: FileStatus) {
+    if (*client).calling {
+        return complete(client, Err(()));
+    }
+    let writing = matches!((*client).completion, Some(Operation::Write(_)));
+    let (data, data_size) = ((*client).data.as_ptr(), (*client).data.len() as u32);
+    match status {
+        FileStatus::Success if writing => call(client, |file_io, vtable| {
+            (vtable.write)(file_io, data, data_size)
+        }),
+        FileStatus::Success => call(client, |file_io, vtable| (vtable.read)(file_io)),
+        _ => complete(client, Err(())),
+    }
+}
+
+unsafe extern "C" fn on_read_complete(
+    client: *mut FileClient,
+    status: FileStatus,
+    data: *const u8,
+    data_size: u32,
+) {
+    let result = match status {
+        _ if (*client).calling => Err(()),
+        FileStatus::Success => Ok(crate::abi::slice(data, data_size).to_vec()),
+        _ => Err(()),
+    };
+    complete(client, result);
+}
+
+unsafe extern "C" fn on_write_complete(client: *mut FileClient, status: FileStatus) {
+    let result = match status {
+        _ if (*client).calling => Err(()),
+        FileStatus::Success => Ok(Vec::new()),
+        _ => Err(()),
+    };
+    complete(client, result);
+}
+
+/// Never called, the host doesn't own clients.
+unsafe extern "C" fn destructor(_: *mut FileClient) {}
+
+unsafe fn complete(client: *mut FileClient, result: Result<Vec<u8>, ()>) {
+    let file_io = std::mem::replace(&mut (*client).file_io, ptr::null_mut());
+    if !file_io.is_null() {
+        ((*(*file_io).vtable).close)(file_io);
+    }
+    let cdm = &*(*client).cdm;
+    match (*client).completion.take() {
+        Some(Operation::Read(completion)) => completion(cdm, result),
+        Some(Operation::Write(completion)) => completion(cdm, result.map(|_| ())),
+        None => {}
+    }
+}
@@ -0,0 +1,130 @@
+use std::mem;
+
+// Compile-time layout checks for the types shared with the CDM, in the
+// spirit of the `CHECK_TYPE` macro of `content_decryption_module.h`.
+// Sizes, alignments and offsets are given for 32 and 64 bit targets, and
+// `layout_check.cc` asserts the same values against `cdm_headers`.
+
+macro_rules! check_type {
+    ($type:ty, $size_32:expr, $size_64:expr, $align_32:expr, $align_64:expr) => {
+        const _: () = assert!(
+            std::mem::size_of::<$type>() == crate::abi::by_width($size_32, $size_64),
+            concat!(stringify!($type), " size mismatch")
+        );
+        const _: () = assert!(
+            std::mem::align_of::<$type>() == crate::abi::by_width($align_32, $align_64),
+            concat!(stringify!($type), " alignment mismatch")
+        );
+    };
+}
+
+macro_rules! check_offset {
+    ($type:ty, $field:ident, $offset_32:expr, $offset_64:expr) => {
+        const _: () = assert!(
+            std::mem::offset_of!($type, $field) == crate::abi::by_width($offset_32, $offset_64),
+            concat!(
+                stringify!($type),
+                "::",
+                stringify!($field),
+                " offset mismatch"
+            )
+        );
+    };
+}
+
+// Enums received from the CDM. They are passed as `u32`, since a value
+// outside of a Rust enum's variants is undefined behavior, and converted with
+// `TryFrom`. Values this crate doesn't know, e.g. from a newer CDM, are kept
+// as `Unknown`.
+macro_rules! cdm_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident = $value:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+            Unknown(u32),
+        }
+
+        impl std::convert::TryFrom<u32> for $name {
+            type Error = u32;
+
+            fn try_from(value: u32) -> Result<Self, u32> {
+                match value {
+                    $($value => Ok($name::$variant),)*
+                    _ => Err(value),
+                }
+            }
+        }
+
+        impl $name {
+            pub(crate) fn from_cdm(value: u32) -> Self {
+                use std::convert::TryFrom;
+                Self::try_from(value).unwrap_or_else(|value| {
+                    log::warn!(concat!("unknown ", stringify!($name), " {} from the CDM"), value);
+                    $name::Unknown(value)
+                })
+            }
+        }
+    };
+}
+
+/// `int64_t` fields are only 4-byte aligned on some 32-bit targets, like x86.
+pub const INT64_ALIGN: usize = mem::align_of::<i64>();
+
+pub const fn by_width(value_32: usize, value_64: usize) -> usize {
+    if cfg!(target_pointer_width = "64") {
+        value_64
+    } else {
+        value_32
+    }
+}
+
+/// A buffer given by the CDM as a pointer and a length. The pointer may be
+/// null when the length is zero, and the data isn't NUL-terminated.
+pub unsafe fn slice<'a, T>(data: *const T, size: u32) -> &'a [T] {
+    if data.is_null() || size == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, size as usize)
+    }
+}
+
+/// Needs a C++ compiler, `$CXX` or `c++`, so it only runs when asked for with
+/// `cargo test -- --ignored`. Vtable slots are read from pointers to virtual
+/// methods, whose encoding is the one of the Itanium C++ ABI on x86.
+#[cfg(all(unix, any(target_arch = "x86", target_arch = "x86_64")))]
+#[test]
+#[ignore]
+fn test_layouts_match_headers() {
+    use std::env;
+    use std::process::Command;
+
+    let root = env!("CARGO_MANIFEST_DIR");
+    let compiler = env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+    let program = env::temp_dir().join(format!("widevine_layout_check_{}", std::process::id()));
+    let output = Command::new(&compiler)
+        .args(["-std=c++11", "-I"])
+        .arg(format!("{}/cdm_headers", root))
+        .arg(format!("{}/src/layout_check.cc", root))
+        .arg("-o")
+        .arg(&program)
+        .output()
+        .unwrap_or_else(|error| panic!("can't run {}: {}", compiler, error));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new(&program).output().unwrap();
+    let _ = std::fs::remove_file(&program);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
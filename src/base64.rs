@@ -0,0 +1,85 @@
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Base64Error {
+    InvalidCharacter(char),
+    InvalidLength,
+}
+
+fn encode_with(data: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let indices = [
+            bytes[0] >> 2,
+            (bytes[0] & 0x03) << 4 | bytes[1] >> 4,
+            (bytes[1] & 0x0f) << 2 | bytes[2] >> 6,
+            bytes[2] & 0x3f,
+        ];
+        let length = chunk.len() + 1;
+        for index in &indices[..length] {
+            encoded.push(alphabet[*index as usize] as char);
+        }
+        if pad {
+            for _ in length..4 {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Standard alphabet with padding.
+pub fn encode(data: &[u8]) -> String {
+    encode_with(data, STANDARD, true)
+}
+
+/// URL-safe alphabet without padding, as used by the W3C `keyids` format.
+pub fn encode_url(data: &[u8]) -> String {
+    encode_with(data, URL_SAFE, false)
+}
+
+/// Decodes both the standard and the URL-safe alphabets, with or without
+/// padding. ASCII whitespace is ignored.
+pub fn decode(encoded: &str) -> Result<Vec<u8>, Base64Error> {
+    let mut values = Vec::with_capacity(encoded.len());
+    for character in encoded.trim_end_matches('=').chars() {
+        let value = match character {
+            'A'..='Z' => character as u8 - b'A',
+            'a'..='z' => character as u8 - b'a' + 26,
+            '0'..='9' => character as u8 - b'0' + 52,
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            _ if character.is_ascii_whitespace() => continue,
+            _ => return Err(Base64Error::InvalidCharacter(character)),
+        };
+        values.push(value);
+    }
+    if values.len() % 4 == 1 {
+        return Err(Base64Error::InvalidLength);
+    }
+
+    let mut decoded = Vec::with_capacity(values.len() * 3 / 4);
+    for chunk in values.chunks(4) {
+        let mut group: u32 = 0;
+        for (index, value) in chunk.iter().enumerate() {
+            group |= u32::from(*value) << (18 - 6 * index);
+        }
+        let bytes = group.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Ok(decoded)
+}
+
+#[test]
+fn test_base64_round_trip() {
+    let data = b"\xfb\xffwidevine";
+    assert_eq!(encode_url(data), "-_93aWRldmluZQ");
+    assert_eq!(decode("+/93aWRldmluZQ==").unwrap(), data);
+    assert_eq!(decode("-_93aWRldmluZQ").unwrap(), data);
+}
@@ -0,0 +1,238 @@
+use crate::certificate::{CertificateError, CertificateManager, ServiceCertificate};
+use crate::init_data::InitData;
+use crate::key_system::KeySystem;
+use crate::license::{LicenseTransport, TransportError};
+use crate::offline::{OfflineError, OfflineLicense, OfflineLicenseManager};
+use crate::session::{SessionDriver, SessionError};
+use crate::types::{SessionEvent, SessionId, SessionMessage, SessionType};
+use crate::{CdmError, CreateSessionError, InitializeCDMError, LoadSessionError, WidevineAPI};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread, parking it while the
+/// future waits.
+///
+/// No runtime is entered. `HttpTransport` runs its requests on a tokio
+/// runtime of its own, so it can still be driven this way, but other futures
+/// needing a runtime can't.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match Pin::as_mut(&mut future).poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+impl WidevineAPI {
+    pub fn initialize_cdm_blocking(&mut self) -> Result<(), InitializeCDMError> {
+        block_on(self.initialize_cdm())
+    }
+
+    pub fn set_server_certificate_blocking(&mut self, certificate: &[u8]) -> Result<(), CdmError> {
+        block_on(self.set_server_certificate(certificate))
+    }
+
+    pub fn create_session_blocking(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+        sender: Sender<SessionEvent>,
+    ) -> Result<SessionId, CreateSessionError> {
+        block_on(self.create_session(session_type, init_data, sender))
+    }
+
+    pub fn load_session_blocking(
+        &mut self,
+        session_type: SessionType,
+        session_id: &SessionId,
+        sender: Sender<SessionEvent>,
+    ) -> Result<(), LoadSessionError> {
+        block_on(self.load_session(session_type, session_id, sender))
+    }
+
+    pub fn update_session_blocking(
+        &mut self,
+        session_id: &SessionId,
+        response: &[u8],
+    ) -> Result<(), CdmError> {
+        block_on(self.update_session(session_id, response))
+    }
+
+    pub fn close_session_blocking(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        block_on(self.close_session(session_id))
+    }
+
+    pub fn remove_session_blocking(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        block_on(self.remove_session(session_id))
+    }
+}
+
+impl CertificateManager {
+    pub fn certificate_blocking(&mut self) -> Result<&ServiceCertificate, CertificateError> {
+        block_on(self.certificate())
+    }
+
+    pub fn apply_blocking(&mut self, api: &mut WidevineAPI) -> Result<(), CertificateError> {
+        block_on(self.apply(api))
+    }
+}
+
+impl<T: LicenseTransport> SessionDriver<T> {
+    pub fn open_session_blocking(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+    ) -> Result<SessionId, SessionError> {
+        block_on(self.open_session(session_type, init_data))
+    }
+
+    pub fn load_session_blocking(
+        &mut self,
+        session_type: SessionType,
+        session_id: &SessionId,
+    ) -> Result<(), SessionError> {
+        block_on(self.load_session(session_type, session_id))
+    }
+
+    pub fn close_session_blocking(&mut self, session_id: &SessionId) -> Result<(), SessionError> {
+        block_on(self.close_session(session_id))
+    }
+
+    pub fn process_blocking(&mut self) -> Result<(), SessionError> {
+        block_on(self.process())
+    }
+
+    pub fn release_session_blocking(&mut self, session_id: &SessionId) -> Result<(), SessionError> {
+        block_on(self.release_session(session_id))
+    }
+}
+
+impl OfflineLicenseManager {
+    pub fn acquire_blocking<T: LicenseTransport>(
+        &mut self,
+        driver: &mut SessionDriver<T>,
+        init_data: InitData,
+        metadata: Value,
+    ) -> Result<&OfflineLicense, OfflineError> {
+        block_on(self.acquire(driver, init_data, metadata))
+    }
+
+    pub fn load_blocking<T: LicenseTransport>(
+        &mut self,
+        driver: &mut SessionDriver<T>,
+        session_id: &SessionId,
+    ) -> Result<(), OfflineError> {
+        block_on(self.load(driver, session_id))
+    }
+
+    pub fn release_blocking<T: LicenseTransport>(
+        &mut self,
+        driver: &mut SessionDriver<T>,
+        session_id: &SessionId,
+    ) -> Result<(), OfflineError> {
+        block_on(self.release(driver, session_id))
+    }
+}
+
+/// The methods of `KeySystem`, blocking.
+pub trait BlockingKeySystem: KeySystem {
+    fn create_session_blocking(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+        sender: Sender<SessionEvent>,
+    ) -> Result<SessionId, CreateSessionError> {
+        block_on(self.create_session(session_type, init_data, sender))
+    }
+
+    fn update_session_blocking(
+        &mut self,
+        session_id: &SessionId,
+        response: &[u8],
+    ) -> Result<(), CdmError> {
+        block_on(self.update_session(session_id, response))
+    }
+
+    fn close_session_blocking(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        block_on(self.close_session(session_id))
+    }
+}
+
+impl<K: KeySystem + ?Sized> BlockingKeySystem for K {}
+
+/// The methods of `LicenseTransport`, blocking.
+pub trait BlockingLicenseTransport: LicenseTransport {
+    fn exchange_blocking(&self, message: SessionMessage) -> Result<Vec<u8>, TransportError> {
+        block_on(self.exchange(message))
+    }
+}
+
+impl<T: LicenseTransport + ?Sized> BlockingLicenseTransport for T {}
+
+#[test]
+fn test_blocking_api() {
+    use crate::init_data::KeyIds;
+    use crate::library;
+    use std::sync::mpsc::channel;
+
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.initialize_cdm_blocking().unwrap();
+    let (sender, receiver) = channel();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x11; 16]));
+    let session_id = api
+        .create_session_blocking(SessionType::Temporary, init_data, sender)
+        .unwrap();
+    assert_eq!(receiver.try_recv().unwrap().session_id, session_id);
+
+    let result = BlockingKeySystem::close_session_blocking(&mut api, &session_id);
+    assert!(result.is_ok());
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_blocking_http_transport() {
+    use crate::license::HttpTransport;
+    use crate::types::MessageType;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/license", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"challenge") {
+            let read = stream.read(&mut buffer).unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\nlicense")
+            .unwrap();
+    });
+
+    let message = SessionMessage {
+        message_type: MessageType::LicenseRequest,
+        content: b"challenge".to_vec(),
+    };
+    let license = HttpTransport::new(&url).exchange_blocking(message);
+    assert_eq!(license, Ok(b"license".to_vec()));
+    server.join().unwrap();
+}
@@ -0,0 +1,96 @@
+use std::mem;
+use std::ptr;
+
+/// `cdm::Buffer`, allocated for the CDM by `Host_10::Allocate`. The CDM
+/// either destroys it or hands it back in a `DecryptedBlock`.
+#[repr(C)]
+pub struct Buffer {
+    vtable: &'static BufferVtable,
+    data: Vec<u8>,
+    size: u32,
+}
+
+#[repr(C)]
+struct BufferVtable {
+    destroy: extern "C" fn(*mut Buffer),
+    capacity: extern "C" fn(*const Buffer) -> u32,
+    data: extern "C" fn(*mut Buffer) -> *mut u8,
+    set_size: extern "C" fn(*mut Buffer, u32),
+    size: extern "C" fn(*const Buffer) -> u32,
+    destructor: extern "C" fn(*mut Buffer),
+    deleting_destructor: extern "C" fn(*mut Buffer),
+}
+
+check_type!(BufferVtable, 28, 56, 4, 8);
+
+static VTABLE: BufferVtable = BufferVtable {
+    destroy,
+    capacity,
+    data,
+    set_size,
+    size,
+    destructor,
+    deleting_destructor: destroy,
+};
+
+impl Buffer {
+    /// A zeroed buffer, owned by the CDM from then on.
+    pub fn allocate(capacity: u32) -> *mut Buffer {
+        let buffer = Buffer {
+            vtable: &VTABLE,
+            data: vec![0; capacity as usize],
+            size: 0,
+        };
+        Box::into_raw(Box::new(buffer))
+    }
+
+    /// Takes back a buffer from the CDM, returning its contents.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` has to come from `allocate`, and the CDM can't use it anymore.
+    pub unsafe fn into_data(buffer: *mut Buffer) -> Vec<u8> {
+        let mut buffer = Box::from_raw(buffer);
+        let size = buffer.size as usize;
+        buffer.data.truncate(size);
+        mem::take(&mut buffer.data)
+    }
+}
+
+extern "C" fn destroy(this: *mut Buffer) {
+    drop(unsafe { Box::from_raw(this) });
+}
+
+extern "C" fn capacity(this: *const Buffer) -> u32 {
+    unsafe { (*this).data.len() as u32 }
+}
+
+extern "C" fn data(this: *mut Buffer) -> *mut u8 {
+    unsafe { (*this).data.as_mut_ptr() }
+}
+
+/// Sizes past the capacity are clamped to it.
+extern "C" fn set_size(this: *mut Buffer, size: u32) {
+    let this = unsafe { &mut *this };
+    this.size = size.min(this.data.len() as u32);
+}
+
+extern "C" fn size(this: *const Buffer) -> u32 {
+    unsafe { (*this).size }
+}
+
+extern "C" fn destructor(this: *mut Buffer) {
+    unsafe { ptr::drop_in_place(this) };
+}
+
+#[test]
+fn test_buffer() {
+    let buffer = Buffer::allocate(4);
+    let vtable = unsafe { (*buffer).vtable };
+    assert_eq!((vtable.capacity)(buffer), 4);
+    unsafe { *(vtable.data)(buffer) = 42 };
+    (vtable.set_size)(buffer, 8);
+    assert_eq!((vtable.size)(buffer), 4);
+    (vtable.set_size)(buffer, 1);
+    assert_eq!(unsafe { Buffer::into_data(buffer) }, vec![42]);
+}
@@ -0,0 +1,71 @@
+use std::convert::TryInto;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UnexpectedEnd;
+
+/// Big-endian cursor over a byte slice, shared by the container parsers.
+#[derive(Clone, Debug)]
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.position..];
+        self.position = self.data.len();
+        rest
+    }
+
+    pub fn read(&mut self, length: usize) -> Result<&'a [u8], UnexpectedEnd> {
+        let end = self.position.checked_add(length).ok_or(UnexpectedEnd)?;
+        let slice = self.data.get(self.position..end).ok_or(UnexpectedEnd)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn skip(&mut self, length: usize) -> Result<(), UnexpectedEnd> {
+        self.read(length).map(|_| ())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, UnexpectedEnd> {
+        Ok(self.read(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, UnexpectedEnd> {
+        Ok(u16::from_be_bytes(self.read(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u24(&mut self) -> Result<u32, UnexpectedEnd> {
+        let bytes = self.read(3)?;
+        Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, UnexpectedEnd> {
+        Ok(u32::from_be_bytes(self.read(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, UnexpectedEnd> {
+        Ok(u64::from_be_bytes(self.read(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], UnexpectedEnd> {
+        Ok(self.read(N)?.try_into().unwrap())
+    }
+}
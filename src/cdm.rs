@@ -1,62 +1,116 @@
-use crate::decryption::{CDMInputBuffer, InputBuffer};
-use crate::decryption::{DecryptionResult, Status};
+use crate::decryption::{CDMInputBuffer, DecryptedBlock, InputBuffer, Status};
 use crate::host::Host;
 use crate::timer::Timer;
-use crate::types::{InitDataType, SessionType};
+use crate::types::{InitDataType, SessionId, SessionType};
 use crate::Library;
 use std::convert::TryInto;
-use std::os::raw::{c_uchar, c_uint, c_void};
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
 
-extern "C" {
-    fn GetCDM(library: *mut c_void, host: *mut c_void) -> *mut c_void;
-    fn CDM_Initialize(cdm: *mut c_void);
-    fn CDM_SetServerCertificate(
-        cdm: *mut c_void,
-        promise_id: c_uint,
-        certificate: *const c_uchar,
-        certificate_length: c_uint,
-    );
-    fn CDM_CreateSessionAndGenerateRequest(
-        cdm: *mut c_void,
-        promise_id: c_uint,
-        session_type: SessionType,
-        init_data_type: InitDataType,
-        init_data: *const c_uchar,
-        init_data_size: c_uint,
-    );
-    fn CDM_UpdateSession(
-        cdm: *mut c_void,
-        promise_id: c_uint,
-        session_id: *const c_uchar,
-        session_id_size: c_uint,
-        response: *const c_uchar,
-        response_size: c_uint,
-    );
-    fn CDM_Decrypt(cdm: *mut c_void, encrypted_buffer: CDMInputBuffer) -> DecryptionResult;
-    fn CDM_TimerExpired(cdm: *mut c_void, context: *mut c_void);
-    fn DeinitializeCDM(cdm: *mut c_void);
+const INTERFACE_VERSION: c_int = 10;
+
+/// `cdm::ContentDecryptionModule_10`, as returned by `CreateCdmInstance`.
+#[repr(C)]
+struct CdmObject {
+    vtable: *const CdmVtable,
+}
+
+/// The vtable of `cdm::ContentDecryptionModule_10`, per the Itanium C++ ABI:
+/// one slot per virtual method in declaration order. The slots of the
+/// decoding methods aren't used.
+#[repr(C)]
+struct CdmVtable {
+    initialize: unsafe extern "C" fn(*mut CdmObject, bool, bool, bool),
+    _get_status_for_policy: usize,
+    set_server_certificate: unsafe extern "C" fn(*mut CdmObject, u32, *const u8, u32),
+    create_session_and_generate_request:
+        unsafe extern "C" fn(*mut CdmObject, u32, SessionType, InitDataType, *const u8, u32),
+    load_session: unsafe extern "C" fn(*mut CdmObject, u32, SessionType, *const c_char, u32),
+    update_session: unsafe extern "C" fn(*mut CdmObject, u32, *const c_char, u32, *const u8, u32),
+    close_session: unsafe extern "C" fn(*mut CdmObject, u32, *const c_char, u32),
+    remove_session: unsafe extern "C" fn(*mut CdmObject, u32, *const c_char, u32),
+    timer_expired: unsafe extern "C" fn(*mut CdmObject, *mut c_void),
+    decrypt:
+        unsafe extern "C" fn(*mut CdmObject, *const CDMInputBuffer, *mut DecryptedBlock) -> u32,
+    /// From `InitializeAudioDecoder` to `OnStorageId`.
+    _decoding: [usize; 9],
+    destroy: unsafe extern "C" fn(*mut CdmObject),
+}
+
+check_type!(CdmVtable, 80, 160, 4, 8);
+check_offset!(CdmVtable, set_server_certificate, 8, 16);
+check_offset!(CdmVtable, decrypt, 36, 72);
+check_offset!(CdmVtable, destroy, 76, 152);
+
+pub enum CreationError {
+    /// The module turned the key system down without asking for a host.
+    KeySystemRejected,
+    Failed,
+}
+
+/// The `user_data` of `CreateCdmInstance`. CDMs check the key system before
+/// asking for a host, so `requested` tells an unsupported key system apart
+/// from other failures.
+struct HostRequest {
+    host: *mut Host,
+    requested: bool,
+}
+
+extern "C" fn get_cdm_host(host_interface_version: c_int, user_data: *mut c_void) -> *mut c_void {
+    let request = unsafe { &mut *(user_data as *mut HostRequest) };
+    request.requested = true;
+    if host_interface_version == INTERFACE_VERSION {
+        request.host as *mut c_void
+    } else {
+        ptr::null_mut()
+    }
 }
 
-pub struct CDM(*mut c_void);
+pub struct Cdm(*mut CdmObject);
 
-impl CDM {
-    pub fn initialize(library: &Library, host: &Host) -> Result<Self, ()> {
-        let cdm = unsafe { GetCDM(library.pointer(), host.pointer) };
-        if cdm == ptr::null_mut() {
-            Err(())
+impl Cdm {
+    /// `host` has to outlive the CDM.
+    pub fn initialize(
+        library: &Library,
+        host: &mut Host,
+        key_system: &str,
+    ) -> Result<Self, CreationError> {
+        let mut request = HostRequest {
+            host,
+            requested: false,
+        };
+        let cdm = (library.create_cdm_instance)(
+            INTERFACE_VERSION,
+            key_system.as_ptr() as *const c_char,
+            key_system.len().try_into().unwrap(),
+            get_cdm_host,
+            &mut request as *mut HostRequest as *mut c_void,
+        );
+        if !cdm.is_null() {
+            Ok(Self(cdm as *mut CdmObject))
+        } else if request.requested {
+            Err(CreationError::Failed)
         } else {
-            Ok(Self(cdm))
+            Err(CreationError::KeySystemRejected)
         }
     }
 
+    #[cfg(test)]
+    pub(crate) fn as_ptr(&self) -> *mut c_void {
+        self.0 as *mut c_void
+    }
+
+    fn vtable(&self) -> &CdmVtable {
+        unsafe { &*(*self.0).vtable }
+    }
+
     pub fn request_initialization(&mut self) {
-        unsafe { CDM_Initialize(self.0) };
+        unsafe { (self.vtable().initialize)(self.0, false, false, false) };
     }
 
     pub fn set_server_certificate(&mut self, promise_id: usize, certificate: &[u8]) {
         unsafe {
-            CDM_SetServerCertificate(
+            (self.vtable().set_server_certificate)(
                 self.0,
                 promise_id.try_into().unwrap(),
                 certificate.as_ptr(),
@@ -73,7 +127,7 @@ impl CDM {
         init_data: &[u8],
     ) {
         unsafe {
-            CDM_CreateSessionAndGenerateRequest(
+            (self.vtable().create_session_and_generate_request)(
                 self.0,
                 promise_id.try_into().unwrap(),
                 session_type,
@@ -84,41 +138,88 @@ impl CDM {
         }
     }
 
-    pub fn update_session(&mut self, promise_id: usize, session_id: &str, response: &[u8]) {
+    pub fn load_session(
+        &mut self,
+        promise_id: usize,
+        session_type: SessionType,
+        session_id: &SessionId,
+    ) {
+        let (session_id, session_id_size) = raw_session_id(session_id);
+        unsafe {
+            (self.vtable().load_session)(
+                self.0,
+                promise_id.try_into().unwrap(),
+                session_type,
+                session_id,
+                session_id_size,
+            );
+        }
+    }
+
+    pub fn update_session(&mut self, promise_id: usize, session_id: &SessionId, response: &[u8]) {
+        let (session_id, session_id_size) = raw_session_id(session_id);
         unsafe {
-            CDM_UpdateSession(
+            (self.vtable().update_session)(
                 self.0,
                 promise_id.try_into().unwrap(),
-                session_id.as_ptr(),
-                session_id.len().try_into().unwrap(),
+                session_id,
+                session_id_size,
                 response.as_ptr(),
                 response.len().try_into().unwrap(),
             );
         }
     }
 
+    pub fn close_session(&mut self, promise_id: usize, session_id: &SessionId) {
+        let (session_id, session_id_size) = raw_session_id(session_id);
+        unsafe {
+            (self.vtable().close_session)(
+                self.0,
+                promise_id.try_into().unwrap(),
+                session_id,
+                session_id_size,
+            );
+        }
+    }
+
+    pub fn remove_session(&mut self, promise_id: usize, session_id: &SessionId) {
+        let (session_id, session_id_size) = raw_session_id(session_id);
+        unsafe {
+            (self.vtable().remove_session)(
+                self.0,
+                promise_id.try_into().unwrap(),
+                session_id,
+                session_id_size,
+            );
+        }
+    }
+
     // TODO: not nicely typed because Status::Success exists
     pub fn decrypt(&mut self, input: InputBuffer) -> Result<Vec<u8>, Status> {
-        let result = unsafe { CDM_Decrypt(self.0, input.into()) };
-        if let Status::Success = result.status {
-            let data = unsafe {
-                Vec::from_raw_parts(result.data, result.size as usize, result.capacity as usize)
-            };
-            Ok(data)
-        } else {
-            Err(result.status)
+        let encrypted_buffer = CDMInputBuffer::from(&input);
+        let mut block = DecryptedBlock::default();
+        let status = unsafe { (self.vtable().decrypt)(self.0, &encrypted_buffer, &mut block) };
+        match Status::from_cdm(status) {
+            Status::Success => Ok(block.take_data().unwrap_or_default()),
+            status => Err(status),
         }
     }
 
     pub fn timer_expired(&mut self, timer: Timer) {
-        unsafe { CDM_TimerExpired(self.0, timer.context) }
+        unsafe { (self.vtable().timer_expired)(self.0, timer.context) }
     }
 }
 
-impl Drop for CDM {
+impl Drop for Cdm {
     fn drop(&mut self) {
-        unsafe {
-            DeinitializeCDM(self.0);
-        }
+        unsafe { (self.vtable().destroy)(self.0) }
     }
 }
+
+fn raw_session_id(session_id: &SessionId) -> (*const c_char, u32) {
+    let session_id = session_id.as_bytes();
+    (
+        session_id.as_ptr() as *const c_char,
+        session_id.len().try_into().unwrap(),
+    )
+}
@@ -0,0 +1,258 @@
+use crate::base64;
+use crate::license::{LicenseTransport, TransportError};
+use crate::promise_set::RejectionInfo;
+use crate::protobuf::{DecodeError, Reader, Value, Writer};
+use crate::types::{MessageType, SessionMessage};
+use crate::{CdmError, HostError, Operation, WidevineAPI};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `SignedMessage.type` of a service certificate request.
+const SERVICE_CERTIFICATE_REQUEST: u64 = 4;
+const DEFAULT_WARNING_PERIOD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, Debug)]
+pub enum CertificateError {
+    Io(String),
+    Transport(TransportError),
+    InvalidCertificate(DecodeError),
+    MissingCertificate,
+    Rejected(RejectionInfo),
+    HostFailed(HostError),
+    TimedOut(Operation),
+}
+
+impl From<CdmError> for CertificateError {
+    fn from(error: CdmError) -> Self {
+        match error {
+            CdmError::Rejected(info) => CertificateError::Rejected(info),
+            CdmError::HostFailed(error) => CertificateError::HostFailed(error),
+            CdmError::TimedOut(operation) => CertificateError::TimedOut(operation),
+        }
+    }
+}
+
+impl From<DecodeError> for CertificateError {
+    fn from(error: DecodeError) -> Self {
+        CertificateError::InvalidCertificate(error)
+    }
+}
+
+/// The challenge license servers answer with their service certificate: a
+/// `SignedMessage` of type `SERVICE_CERTIFICATE_REQUEST`.
+pub fn certificate_request() -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.varint(1, SERVICE_CERTIFICATE_REQUEST);
+    writer.into_bytes()
+}
+
+/// A license server's `SignedDrmCertificate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceCertificate {
+    data: Vec<u8>,
+    pub provider_id: Option<String>,
+    pub serial_number: Vec<u8>,
+    pub creation_time: Option<SystemTime>,
+    pub expiration_time: Option<SystemTime>,
+}
+
+impl ServiceCertificate {
+    /// Parses a `SignedDrmCertificate`, or a `SignedMessage` wrapping one as
+    /// returned by license servers.
+    pub fn parse(data: &[u8]) -> Result<Self, CertificateError> {
+        let mut fields = Reader::new(data).collect::<Result<Vec<_>, _>>()?;
+        // `SignedMessage.type` is a varint where `SignedDrmCertificate`
+        // starts with the certificate bytes.
+        if let Some((1, Value::Varint(_))) = fields.first() {
+            let message = find_bytes(&fields, 2).ok_or(CertificateError::MissingCertificate)?;
+            return Self::parse(message);
+        }
+
+        let certificate = find_bytes(&fields, 1).ok_or(CertificateError::MissingCertificate)?;
+        let mut parsed = Self {
+            data: data.to_vec(),
+            provider_id: None,
+            serial_number: Vec::new(),
+            creation_time: None,
+            expiration_time: None,
+        };
+        fields = Reader::new(certificate).collect::<Result<_, _>>()?;
+        for (number, value) in fields {
+            match number {
+                2 => parsed.serial_number = value.as_bytes().unwrap_or_default().to_vec(),
+                3 => parsed.creation_time = value.as_u64().and_then(to_system_time),
+                7 => parsed.provider_id = Some(value.as_string()?),
+                12 => parsed.expiration_time = value.as_u64().and_then(to_system_time),
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Loads a certificate stored either as binary or as base64 text.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CertificateError> {
+        let data = fs::read(path).map_err(|error| CertificateError::Io(error.to_string()))?;
+        Self::parse(&data).or_else(|error| {
+            let decoded = std::str::from_utf8(&data)
+                .ok()
+                .and_then(|text| base64::decode(text).ok())
+                .ok_or(error)?;
+            Self::parse(&decoded)
+        })
+    }
+
+    /// The `SignedDrmCertificate` to pass to `set_server_certificate`.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn expires_within(&self, period: Duration) -> bool {
+        // Every expiration is within a period reaching past `SystemTime`.
+        let limit = SystemTime::now().checked_add(period);
+        self.expiration_time
+            .is_some_and(|expiration| limit.is_none_or(|limit| expiration <= limit))
+    }
+}
+
+fn find_bytes<'a>(fields: &[(u32, Value<'a>)], number: u32) -> Option<&'a [u8]> {
+    fields
+        .iter()
+        .find(|(field, _)| *field == number)
+        .and_then(|(_, value)| value.as_bytes())
+}
+
+/// `None` for times too far out to represent, taken as no time at all.
+fn to_system_time(seconds: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+enum CertificateSource {
+    Transport(Box<dyn LicenseTransport>),
+    File(PathBuf),
+}
+
+/// Fetches or loads a service certificate once and applies it to every CDM
+/// instance it's given.
+pub struct CertificateManager {
+    source: CertificateSource,
+    certificate: Option<ServiceCertificate>,
+    warning_period: Duration,
+}
+
+impl CertificateManager {
+    /// Fetches the certificate by sending `certificate_request` through the
+    /// transport.
+    pub fn from_transport<T: LicenseTransport + 'static>(transport: T) -> Self {
+        Self::new(CertificateSource::Transport(Box::new(transport)))
+    }
+
+    pub fn from_file<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(CertificateSource::File(path.into()))
+    }
+
+    fn new(source: CertificateSource) -> Self {
+        Self {
+            source,
+            certificate: None,
+            warning_period: DEFAULT_WARNING_PERIOD,
+        }
+    }
+
+    /// How long before its expiration a warning is logged when the
+    /// certificate is applied. 30 days by default.
+    pub fn with_warning_period(mut self, warning_period: Duration) -> Self {
+        self.warning_period = warning_period;
+        self
+    }
+
+    /// The cached certificate, fetched or loaded first if there is none or
+    /// it has expired.
+    pub async fn certificate(&mut self) -> Result<&ServiceCertificate, CertificateError> {
+        let expired = self
+            .certificate
+            .as_ref()
+            .is_none_or(|certificate| certificate.expires_within(Duration::from_secs(0)));
+        if expired {
+            let certificate = match self.source {
+                CertificateSource::Transport(ref transport) => {
+                    let message = SessionMessage {
+                        message_type: MessageType::LicenseRequest,
+                        content: certificate_request(),
+                    };
+                    let response = transport
+                        .exchange(message)
+                        .await
+                        .map_err(CertificateError::Transport)?;
+                    ServiceCertificate::parse(&response)?
+                }
+                CertificateSource::File(ref path) => ServiceCertificate::load(path)?,
+            };
+            self.certificate = Some(certificate);
+        }
+        Ok(self.certificate.as_ref().unwrap())
+    }
+
+    pub async fn apply(&mut self, api: &mut WidevineAPI) -> Result<(), CertificateError> {
+        let warning_period = self.warning_period;
+        let certificate = self.certificate().await?;
+        if certificate.expires_within(warning_period) {
+            log::warn!(
+                "service certificate of {} expires soon ({:?})",
+                certificate
+                    .provider_id
+                    .as_deref()
+                    .unwrap_or("unknown provider"),
+                certificate.expiration_time
+            );
+        }
+        api.set_server_certificate(certificate.data())
+            .await
+            .map_err(CertificateError::from)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_fetch_service_certificate() {
+    use crate::license::CallbackTransport;
+
+    let mut drm_certificate = Writer::default();
+    drm_certificate
+        .varint(1, 3)
+        .bytes(2, &[0xab; 16])
+        .varint(3, 1_500_000_000)
+        .bytes(7, b"license.example.com")
+        .varint(12, 4_000_000_000);
+    let mut signed_certificate = Writer::default();
+    signed_certificate
+        .bytes(1, &drm_certificate.into_bytes())
+        .bytes(2, &[0; 256]);
+    let signed_certificate = signed_certificate.into_bytes();
+    let mut signed_message = Writer::default();
+    signed_message.varint(1, 5).bytes(2, &signed_certificate);
+    let response = signed_message.into_bytes();
+
+    let mut manager = CertificateManager::from_transport(CallbackTransport::new(
+        move |message: SessionMessage| {
+            assert_eq!(message.content, certificate_request());
+            let response = response.clone();
+            async move { Ok(response) }
+        },
+    ));
+    let certificate = manager.certificate().await.unwrap();
+    assert_eq!(certificate.data(), &signed_certificate[..]);
+    assert_eq!(
+        certificate.provider_id.as_deref(),
+        Some("license.example.com")
+    );
+    assert_eq!(certificate.serial_number, vec![0xab; 16]);
+    assert_eq!(certificate.expiration_time, to_system_time(4_000_000_000));
+    assert!(!certificate.expires_within(Duration::from_secs(60)));
+    assert!(certificate.expires_within(Duration::MAX));
+    assert_eq!(to_system_time(u64::MAX), None);
+    assert_eq!(
+        &ServiceCertificate::parse(&signed_certificate).unwrap(),
+        certificate
+    );
+}
@@ -0,0 +1,444 @@
+use crate::base64;
+use crate::decryption::{EncryptionScheme, InputBuffer, Status, SubsampleEntry};
+use crate::init_data::InitData;
+use crate::key_system::KeySystem;
+use crate::promise_set::RejectionInfo;
+use crate::pssh::{PsshBox, CLEARKEY_SYSTEM_ID};
+use crate::types::{
+    Exception, KeyInformation, KeyStatus, KeysChange, MessageType, SessionEvent, SessionEventType,
+    SessionId, SessionMessage, SessionType,
+};
+use crate::{CdmError, CreateSessionError};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Block};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::iter;
+use std::ops::Range;
+use std::sync::mpsc::Sender;
+
+pub const CLEARKEY_KEY_SYSTEM: &str = "org.w3.clearkey";
+
+const KEY_SIZE: usize = 16;
+const BLOCK_SIZE: usize = 16;
+
+type Key = [u8; KEY_SIZE];
+
+struct Session {
+    key_ids: Vec<Vec<u8>>,
+    keys: HashMap<Vec<u8>, Key>,
+    sender: Sender<SessionEvent>,
+}
+
+/// The W3C ClearKey key system, implemented without a CDM. Licenses are
+/// JSON Web Key sets carrying the content keys in the clear.
+///
+/// Only temporary sessions are supported.
+#[derive(Default)]
+pub struct ClearKey {
+    sessions: HashMap<SessionId, Session>,
+    session_count: u32,
+}
+
+impl ClearKey {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(&self, key_id: &[u8]) -> Option<&Key> {
+        self.sessions
+            .values()
+            .find_map(|session| session.keys.get(key_id))
+    }
+}
+
+#[async_trait(?Send)]
+impl KeySystem for ClearKey {
+    fn key_system(&self) -> &str {
+        CLEARKEY_KEY_SYSTEM
+    }
+
+    async fn create_session(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+        sender: Sender<SessionEvent>,
+    ) -> Result<SessionId, CreateSessionError> {
+        if !matches!(session_type, SessionType::Temporary) {
+            let info = rejection(
+                Exception::NotSupportedError,
+                "only temporary sessions are supported",
+            );
+            return Err(CreateSessionError::Rejected(info));
+        }
+        let key_ids = key_ids(&init_data).map_err(CreateSessionError::Rejected)?;
+
+        self.session_count += 1;
+        let session_id = SessionId::from(self.session_count.to_string());
+        let kids: Vec<String> = key_ids.iter().map(|id| base64::encode_url(id)).collect();
+        let request = json!({ "kids": kids, "type": "temporary" });
+        let _ = sender.send(SessionEvent {
+            session_id: session_id.clone(),
+            data: SessionEventType::Message(SessionMessage {
+                message_type: MessageType::LicenseRequest,
+                content: request.to_string().into_bytes(),
+            }),
+        });
+
+        let session = Session {
+            key_ids,
+            keys: HashMap::new(),
+            sender,
+        };
+        self.sessions.insert(session_id.clone(), session);
+        Ok(session_id)
+    }
+
+    async fn update_session(
+        &mut self,
+        session_id: &SessionId,
+        response: &[u8],
+    ) -> Result<(), CdmError> {
+        let session = self
+            .sessions
+            .get_mut(session_id)
+            .ok_or_else(|| rejection(Exception::InvalidStateError, "unknown session"))
+            .map_err(CdmError::Rejected)?;
+        let keys = parse_license(response).map_err(CdmError::Rejected)?;
+
+        let has_additional_usable_key = keys.iter().any(|(id, _)| !session.keys.contains_key(id));
+        for (key_id, key) in keys {
+            if !session.key_ids.contains(&key_id) {
+                session.key_ids.push(key_id.clone());
+            }
+            session.keys.insert(key_id, key);
+        }
+
+        let keys_info = session
+            .key_ids
+            .iter()
+            .map(|key_id| KeyInformation {
+                key_id: key_id.clone(),
+                status: if session.keys.contains_key(key_id) {
+                    KeyStatus::Usable
+                } else {
+                    KeyStatus::StatusPending
+                },
+                system_code: 0,
+            })
+            .collect();
+        let _ = session.sender.send(SessionEvent {
+            session_id: session_id.clone(),
+            data: SessionEventType::KeysChange(KeysChange {
+                has_additional_usable_key,
+                keys_info,
+            }),
+        });
+        Ok(())
+    }
+
+    async fn close_session(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        self.sessions.remove(session_id).map(|_| ()).ok_or_else(|| {
+            CdmError::Rejected(rejection(Exception::InvalidStateError, "unknown session"))
+        })
+    }
+
+    fn decrypt(&mut self, input_buffer: InputBuffer) -> Result<Vec<u8>, Status> {
+        if input_buffer.encryption_scheme == EncryptionScheme::Unencrypted {
+            return Ok(input_buffer.data.to_vec());
+        }
+        let key = self.key(input_buffer.key_id).ok_or(Status::NoKey)?;
+        decrypt(key, &input_buffer).map_err(|_| Status::DecryptError)
+    }
+}
+
+fn rejection(exception: Exception, message: &str) -> RejectionInfo {
+    RejectionInfo {
+        exception,
+        system_code: 0,
+        error_message: message.to_string(),
+    }
+}
+
+/// Key IDs from `keyids` and `webm` init data, or from the ClearKey `pssh`
+/// boxes of `cenc` init data.
+fn key_ids(init_data: &InitData) -> Result<Vec<Vec<u8>>, RejectionInfo> {
+    let mut key_ids = match init_data {
+        InitData::KeyIds(key_ids) => key_ids.key_ids.clone(),
+        InitData::WebM(key_id) => vec![key_id.clone()],
+        InitData::Cenc(data) => PsshBox::parse_all(data)
+            .map_err(|_| rejection(Exception::TypeError, "invalid pssh boxes"))?
+            .into_iter()
+            .filter(|pssh| pssh.system_id == CLEARKEY_SYSTEM_ID)
+            .flat_map(|pssh| pssh.key_ids)
+            .map(|key_id| key_id.to_vec())
+            .collect(),
+    };
+    key_ids.dedup();
+    if key_ids.is_empty() {
+        Err(rejection(Exception::TypeError, "no key IDs in init data"))
+    } else {
+        Ok(key_ids)
+    }
+}
+
+/// Parses a `{"keys":[{"kty":"oct","kid":"...","k":"..."}]}` license.
+fn parse_license(response: &[u8]) -> Result<Vec<(Vec<u8>, Key)>, RejectionInfo> {
+    let invalid = |message| rejection(Exception::TypeError, message);
+    let license: Value =
+        serde_json::from_slice(response).map_err(|_| invalid("license is not JSON"))?;
+    let keys = license["keys"]
+        .as_array()
+        .ok_or_else(|| invalid("license has no keys"))?;
+
+    keys.iter()
+        .map(|key| {
+            if key["kty"] != "oct" {
+                return Err(invalid("key is not symmetric"));
+            }
+            let decode = |field: &str| {
+                key[field]
+                    .as_str()
+                    .and_then(|encoded| base64::decode(encoded).ok())
+                    .ok_or_else(|| invalid("invalid key encoding"))
+            };
+            let key_id = decode("kid")?;
+            let mut key = [0; KEY_SIZE];
+            match decode("k")? {
+                ref k if k.len() == KEY_SIZE => key.copy_from_slice(k),
+                _ => return Err(invalid("key is not 128 bits")),
+            }
+            Ok((key_id, key))
+        })
+        .collect()
+}
+
+/// Decrypts a `cenc` or `cbcs` sample.
+fn decrypt(key: &Key, buffer: &InputBuffer) -> Result<Vec<u8>, ()> {
+    let scheme = buffer.encryption_scheme;
+    let iv = match buffer.iv.len() {
+        // 8 byte IVs are only allowed in `cenc`, as the upper half of the
+        // counter.
+        8 if scheme == EncryptionScheme::Cenc => {
+            let mut padded = [0; BLOCK_SIZE];
+            padded[..8].copy_from_slice(buffer.iv);
+            padded
+        }
+        BLOCK_SIZE => {
+            let mut iv = [0; BLOCK_SIZE];
+            iv.copy_from_slice(buffer.iv);
+            iv
+        }
+        _ => return Err(()),
+    };
+    let ranges = encrypted_ranges(buffer.data.len(), &buffer.subsamples)?;
+    let cipher = Aes128::new(key.into());
+    let mut output = buffer.data.to_vec();
+
+    if scheme == EncryptionScheme::Cenc {
+        // The keystream carries on from one subsample to the next.
+        let mut encrypted: Vec<u8> = ranges
+            .iter()
+            .flat_map(|range| buffer.data[range.clone()].iter().copied())
+            .collect();
+        ctr(&cipher, iv, &mut encrypted);
+        let mut decrypted = encrypted.into_iter();
+        for range in ranges {
+            for byte in &mut output[range] {
+                *byte = decrypted.next().ok_or(())?;
+            }
+        }
+    } else {
+        // A 0:0 pattern encrypts every block.
+        let pattern = buffer.pattern;
+        let (crypt, skip) = match (pattern.crypt_byte_block, pattern.skip_byte_block) {
+            (0, 0) => (1, 0),
+            (crypt, skip) => (crypt as usize, skip as usize),
+        };
+        for range in ranges {
+            cbc_pattern(&cipher, iv, &mut output[range], crypt, skip);
+        }
+    }
+    Ok(output)
+}
+
+/// Without subsamples, the whole sample is encrypted.
+fn encrypted_ranges(size: usize, subsamples: &[SubsampleEntry]) -> Result<Vec<Range<usize>>, ()> {
+    if subsamples.is_empty() {
+        return Ok(iter::once(0..size).collect());
+    }
+
+    let mut ranges = Vec::with_capacity(subsamples.len());
+    let mut offset = 0;
+    for subsample in subsamples {
+        let start = offset + subsample.clear_bytes as usize;
+        offset = start + subsample.cipher_bytes as usize;
+        ranges.push(start..offset);
+    }
+    if offset == size {
+        Ok(ranges)
+    } else {
+        Err(())
+    }
+}
+
+fn ctr(cipher: &Aes128, iv: [u8; BLOCK_SIZE], data: &mut [u8]) {
+    let mut counter = u128::from_be_bytes(iv);
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        let mut keystream = Block::from(counter.to_be_bytes());
+        cipher.encrypt_block(&mut keystream);
+        for (byte, key) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= key;
+        }
+        counter = counter.wrapping_add(1);
+    }
+}
+
+/// The IV is reset for every subsample, and partial blocks are left in the
+/// clear.
+fn cbc_pattern(cipher: &Aes128, iv: [u8; BLOCK_SIZE], data: &mut [u8], crypt: usize, skip: usize) {
+    let mut chain = Block::from(iv);
+    for (index, block) in data.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+        if index % (crypt + skip) >= crypt {
+            continue;
+        }
+        let encrypted = Block::clone_from_slice(block);
+        let mut decrypted = encrypted;
+        cipher.decrypt_block(&mut decrypted);
+        for ((byte, plain), chained) in block.iter_mut().zip(decrypted.iter()).zip(chain.iter()) {
+            *byte = plain ^ chained;
+        }
+        chain = encrypted;
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_clearkey_session() {
+    use crate::decryption::Pattern;
+    use crate::init_data::KeyIds;
+    use std::sync::mpsc::channel;
+
+    // NIST SP 800-38A, F.5.1 and F.2.1.
+    let key = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    let plaintext = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a, 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf,
+        0x8e, 0x51,
+    ];
+    let ctr_iv = [
+        0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe,
+        0xff,
+    ];
+    let ctr_ciphertext = [
+        0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6,
+        0xce, 0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff, 0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff,
+        0xfd, 0xff,
+    ];
+    let cbc_iv: Vec<u8> = (0..16).collect();
+    let cbc_ciphertext = [
+        0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19,
+        0x7d, 0x50, 0x86, 0xcb, 0x9b, 0x50, 0x72, 0x19, 0xee, 0x95, 0xdb, 0x11, 0x3a, 0x91, 0x76,
+        0x78, 0xb2,
+    ];
+    let key_id = [0x11; 16];
+
+    let mut clearkey = ClearKey::new();
+    let (sender, receiver) = channel();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&key_id));
+    let session_id = clearkey
+        .create_session(SessionType::Temporary, init_data, sender)
+        .await
+        .unwrap();
+    match receiver.try_recv().unwrap().data {
+        SessionEventType::Message(message) => {
+            let request: Value = serde_json::from_slice(&message.content).unwrap();
+            assert_eq!(request["kids"], json!([base64::encode_url(&key_id)]));
+            assert_eq!(request["type"], "temporary");
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    let license = json!({"keys": [{
+        "kty": "oct",
+        "kid": base64::encode_url(&key_id),
+        "k": base64::encode_url(&key),
+    }]});
+    clearkey
+        .update_session(&session_id, license.to_string().as_bytes())
+        .await
+        .unwrap();
+    match receiver.try_recv().unwrap().data {
+        SessionEventType::KeysChange(change) => {
+            assert!(change.has_additional_usable_key);
+            assert!(matches!(change.keys_info[0].status, KeyStatus::Usable));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    let mut sample = b"clear".to_vec();
+    sample.extend_from_slice(&ctr_ciphertext[..7]);
+    sample.extend_from_slice(b"header");
+    sample.extend_from_slice(&ctr_ciphertext[7..]);
+    let mut expected = b"clear".to_vec();
+    expected.extend_from_slice(&plaintext[..7]);
+    expected.extend_from_slice(b"header");
+    expected.extend_from_slice(&plaintext[7..]);
+    let cenc = InputBuffer {
+        data: &sample,
+        encryption_scheme: EncryptionScheme::Cenc,
+        key_id: &key_id,
+        iv: &ctr_iv,
+        subsamples: vec![
+            SubsampleEntry {
+                clear_bytes: 5,
+                cipher_bytes: 7,
+            },
+            SubsampleEntry {
+                clear_bytes: 6,
+                cipher_bytes: 25,
+            },
+        ],
+        pattern: Pattern::default(),
+        timestamp: 0,
+    };
+    assert_eq!(clearkey.decrypt(cenc).unwrap(), expected);
+
+    // With a 1:1 pattern, the CBC chain skips the clear block.
+    let mut sample = cbc_ciphertext[..16].to_vec();
+    sample.extend_from_slice(&[0xcc; 16]);
+    sample.extend_from_slice(&cbc_ciphertext[16..]);
+    sample.extend_from_slice(b"tail");
+    let mut expected = plaintext[..16].to_vec();
+    expected.extend_from_slice(&[0xcc; 16]);
+    expected.extend_from_slice(&plaintext[16..]);
+    expected.extend_from_slice(b"tail");
+    let cbcs = |key_id| InputBuffer {
+        data: &sample,
+        encryption_scheme: EncryptionScheme::Cbcs,
+        key_id,
+        iv: &cbc_iv,
+        subsamples: Vec::new(),
+        pattern: Pattern {
+            crypt_byte_block: 1,
+            skip_byte_block: 1,
+        },
+        timestamp: 0,
+    };
+    assert_eq!(clearkey.decrypt(cbcs(&key_id)).unwrap(), expected);
+    assert!(matches!(
+        clearkey.decrypt(cbcs(&[0x22; 16])),
+        Err(Status::NoKey)
+    ));
+
+    clearkey.close_session(&session_id).await.unwrap();
+    assert!(matches!(
+        clearkey.decrypt(cbcs(&key_id)),
+        Err(Status::NoKey)
+    ));
+    assert!(clearkey.close_session(&session_id).await.is_err());
+}
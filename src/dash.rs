@@ -0,0 +1,225 @@
+use crate::base64;
+use crate::init_data::InitData;
+use crate::pssh::{PsshBox, PsshError, WidevinePsshData};
+use roxmltree::{Document, Node};
+
+const WIDEVINE_SCHEME_ID: &str = "urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed";
+const MP4_PROTECTION_SCHEME_ID: &str = "urn:mpeg:dash:mp4protection:2011";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DashError {
+    InvalidXml(String),
+    NotAnMpd,
+    InvalidKeyId(String),
+    InvalidBase64,
+    InvalidPssh(PsshError),
+}
+
+impl From<PsshError> for DashError {
+    fn from(error: PsshError) -> Self {
+        DashError::InvalidPssh(error)
+    }
+}
+
+/// Widevine protection of an `AdaptationSet`, merged with that of its
+/// `Representation`s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptationSetProtection {
+    pub id: Option<String>,
+    pub content_type: Option<String>,
+    pub mime_type: Option<String>,
+    /// `value` of the `mp4protection` descriptor, e.g. `cenc` or `cbcs`.
+    pub scheme: Option<String>,
+    pub default_key_ids: Vec<[u8; 16]>,
+    /// Widevine `cenc:pssh` boxes, or one built from the default KIDs when
+    /// the manifest doesn't carry any. `None` for unprotected sets.
+    pub init_data: Option<InitData>,
+    /// From `ms:laurl` or `dashif:laurl`.
+    pub license_url: Option<String>,
+}
+
+impl AdaptationSetProtection {
+    pub fn is_protected(&self) -> bool {
+        self.init_data.is_some()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeriodProtection {
+    pub id: Option<String>,
+    pub adaptation_sets: Vec<AdaptationSetProtection>,
+}
+
+/// Extracts the Widevine `ContentProtection` data of every `Period` and
+/// `AdaptationSet` of a DASH manifest.
+pub fn parse(mpd: &str) -> Result<Vec<PeriodProtection>, DashError> {
+    let document =
+        Document::parse(mpd).map_err(|error| DashError::InvalidXml(error.to_string()))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "MPD" {
+        return Err(DashError::NotAnMpd);
+    }
+
+    children(root, "Period")
+        .map(|period| {
+            let adaptation_sets = children(period, "AdaptationSet")
+                .map(parse_adaptation_set)
+                .collect::<Result<_, _>>()?;
+            Ok(PeriodProtection {
+                id: period.attribute("id").map(str::to_string),
+                adaptation_sets,
+            })
+        })
+        .collect()
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// Looks up an attribute by local name, whatever its namespace prefix is.
+fn attribute<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|attribute| attribute.name() == name)
+        .map(|attribute| attribute.value())
+}
+
+fn parse_adaptation_set(node: Node) -> Result<AdaptationSetProtection, DashError> {
+    let mut protection = AdaptationSetProtection {
+        id: node.attribute("id").map(str::to_string),
+        content_type: node.attribute("contentType").map(str::to_string),
+        mime_type: node.attribute("mimeType").map(str::to_string),
+        scheme: None,
+        default_key_ids: Vec::new(),
+        init_data: None,
+        license_url: None,
+    };
+
+    let mut pssh_boxes = Vec::new();
+    let mut is_widevine = false;
+    let descriptors = children(node, "ContentProtection").chain(
+        children(node, "Representation")
+            .flat_map(|representation| children(representation, "ContentProtection")),
+    );
+
+    for descriptor in descriptors {
+        if let Some(key_ids) = attribute(descriptor, "default_KID") {
+            for key_id in key_ids.split_whitespace() {
+                let key_id = parse_uuid(key_id)?;
+                if !protection.default_key_ids.contains(&key_id) {
+                    protection.default_key_ids.push(key_id);
+                }
+            }
+        }
+
+        let scheme_id = descriptor.attribute("schemeIdUri").unwrap_or_default();
+        if scheme_id.eq_ignore_ascii_case(MP4_PROTECTION_SCHEME_ID) {
+            if protection.scheme.is_none() {
+                protection.scheme = descriptor.attribute("value").map(str::to_string);
+            }
+            continue;
+        }
+        if !scheme_id.eq_ignore_ascii_case(WIDEVINE_SCHEME_ID) {
+            continue;
+        }
+
+        is_widevine = true;
+        for child in descriptor.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "pssh" => {
+                    let encoded = child.text().unwrap_or_default();
+                    let data = base64::decode(encoded).map_err(|_| DashError::InvalidBase64)?;
+                    for pssh in PsshBox::parse_all(&data)? {
+                        if pssh.is_widevine() && !pssh_boxes.contains(&pssh) {
+                            pssh_boxes.push(pssh);
+                        }
+                    }
+                }
+                "laurl" if protection.license_url.is_none() => {
+                    protection.license_url = child
+                        .attribute("licenseUrl")
+                        .or_else(|| child.text())
+                        .map(|url| url.trim().to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !pssh_boxes.is_empty() {
+        let data = pssh_boxes.iter().flat_map(PsshBox::to_bytes).collect();
+        protection.init_data = Some(InitData::Cenc(data));
+    } else if is_widevine && !protection.default_key_ids.is_empty() {
+        let pssh_data = WidevinePsshData {
+            key_ids: protection
+                .default_key_ids
+                .iter()
+                .map(|key_id| key_id.to_vec())
+                .collect(),
+            ..WidevinePsshData::default()
+        };
+        protection.init_data = Some(InitData::from(&pssh_data));
+    }
+
+    Ok(protection)
+}
+
+/// Parses a key ID written as a UUID, with or without dashes.
+pub fn parse_uuid(uuid: &str) -> Result<[u8; 16], DashError> {
+    let invalid = || DashError::InvalidKeyId(uuid.to_string());
+    let digits: Vec<u8> = uuid.bytes().filter(|byte| *byte != b'-').collect();
+    if digits.len() != 32 {
+        return Err(invalid());
+    }
+
+    let mut key_id = [0; 16];
+    for (byte, pair) in key_id.iter_mut().zip(digits.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+    Ok(key_id)
+}
+
+#[test]
+fn test_parse_fixture_manifest() {
+    let periods = parse(include_str!("../fixtures/widevine.mpd")).unwrap();
+    assert_eq!(periods.len(), 1);
+    assert_eq!(periods[0].id.as_deref(), Some("main"));
+
+    let sets = &periods[0].adaptation_sets;
+    assert_eq!(sets.len(), 3);
+
+    let video_key_id = parse_uuid("10000000-1000-1000-1000-100000000001").unwrap();
+    assert_eq!(sets[0].scheme.as_deref(), Some("cenc"));
+    assert_eq!(sets[0].default_key_ids, vec![video_key_id]);
+    assert_eq!(
+        sets[0].license_url.as_deref(),
+        Some("https://license.example.com/widevine")
+    );
+    let init_data = match sets[0].init_data {
+        Some(InitData::Cenc(ref data)) => data,
+        ref other => panic!("unexpected init data {:?}", other),
+    };
+    let pssh_data = PsshBox::parse_all(init_data).unwrap()[0]
+        .widevine_data()
+        .unwrap();
+    assert_eq!(pssh_data.key_ids, vec![video_key_id.to_vec()]);
+    assert_eq!(
+        pssh_data.content_id.as_deref(),
+        Some(&b"fixture-content"[..])
+    );
+
+    // No cenc:pssh, so the init data is built from the default KID.
+    assert_eq!(sets[1].scheme.as_deref(), Some("cbcs"));
+    assert_eq!(
+        sets[1].license_url.as_deref(),
+        Some("https://license.example.com/audio")
+    );
+    assert!(sets[1].is_protected());
+
+    assert!(!sets[2].is_protected());
+}
@@ -1,43 +1,73 @@
-use std::os::raw::{c_uchar, c_uint};
+use crate::abi::INT64_ALIGN;
+use crate::buffer::Buffer;
+use std::ptr;
 
-#[repr(C)]
-#[derive(Debug)]
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum EncryptionScheme {
     Unencrypted,
     Cenc,
     Cbcs,
 }
 
+check_type!(EncryptionScheme, 4, 4, 4, 4);
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct SubsampleEntry {
-    pub clear_bytes: c_uint,
-    pub cipher_bytes: c_uint,
+    pub clear_bytes: u32,
+    pub cipher_bytes: u32,
 }
 
+check_type!(SubsampleEntry, 8, 8, 4, 4);
+check_offset!(SubsampleEntry, clear_bytes, 0, 0);
+check_offset!(SubsampleEntry, cipher_bytes, 4, 4);
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Pattern {
-    pub crypt_byte_block: c_uint,
-    pub skip_byte_block: c_uint,
+    pub crypt_byte_block: u32,
+    pub skip_byte_block: u32,
 }
 
+check_type!(Pattern, 8, 8, 4, 4);
+check_offset!(Pattern, crypt_byte_block, 0, 0);
+check_offset!(Pattern, skip_byte_block, 4, 4);
+
+/// `cdm::InputBuffer_2`, with its padding spelled out so the layout only
+/// differs in alignment between 32 and 64 bit.
 #[repr(C)]
 #[derive(Debug)]
 pub struct CDMInputBuffer {
-    pub data: *const c_uchar,
-    pub data_size: c_uint,
+    pub data: *const u8,
+    pub data_size: u32,
     pub encryption_scheme: EncryptionScheme,
-    pub key_id: *const c_uchar,
-    pub key_id_size: c_uint,
-    pub iv: *const c_uchar,
-    pub iv_size: c_uint,
+    pub key_id: *const u8,
+    pub key_id_size: u32,
+    padding_1: u32,
+    pub iv: *const u8,
+    pub iv_size: u32,
+    padding_2: u32,
     pub subsamples: *const SubsampleEntry,
-    pub num_subsamples: c_uint,
+    pub num_subsamples: u32,
+    padding_3: u32,
     pub pattern: Pattern,
-    pub timestamp: u64,
+    pub timestamp: i64,
 }
 
+check_type!(CDMInputBuffer, 64, 80, INT64_ALIGN, 8);
+check_offset!(CDMInputBuffer, data, 0, 0);
+check_offset!(CDMInputBuffer, data_size, 4, 8);
+check_offset!(CDMInputBuffer, encryption_scheme, 8, 12);
+check_offset!(CDMInputBuffer, key_id, 12, 16);
+check_offset!(CDMInputBuffer, key_id_size, 16, 24);
+check_offset!(CDMInputBuffer, iv, 24, 32);
+check_offset!(CDMInputBuffer, iv_size, 28, 40);
+check_offset!(CDMInputBuffer, subsamples, 36, 48);
+check_offset!(CDMInputBuffer, num_subsamples, 40, 56);
+check_offset!(CDMInputBuffer, pattern, 48, 64);
+check_offset!(CDMInputBuffer, timestamp, 56, 72);
+
 #[derive(Debug)]
 pub struct InputBuffer<'a> {
     pub data: &'a [u8],
@@ -46,44 +76,150 @@ pub struct InputBuffer<'a> {
     pub iv: &'a [u8],
     pub subsamples: Vec<SubsampleEntry>,
     pub pattern: Pattern,
-    pub timestamp: u64,
+    pub timestamp: i64,
 }
 
-impl Into<CDMInputBuffer> for InputBuffer<'_> {
-    fn into(self) -> CDMInputBuffer {
+/// Borrows from the `InputBuffer`, which has to outlive it.
+impl From<&InputBuffer<'_>> for CDMInputBuffer {
+    fn from(buffer: &InputBuffer<'_>) -> Self {
         CDMInputBuffer {
-            data: self.data.as_ptr(),
-            data_size: self.data.len() as u32,
+            data: buffer.data.as_ptr(),
+            data_size: buffer.data.len() as u32,
+            encryption_scheme: buffer.encryption_scheme,
+            key_id: buffer.key_id.as_ptr(),
+            key_id_size: buffer.key_id.len() as u32,
+            padding_1: 0,
+            iv: buffer.iv.as_ptr(),
+            iv_size: buffer.iv.len() as u32,
+            padding_2: 0,
+            subsamples: buffer.subsamples.as_ptr(),
+            num_subsamples: buffer.subsamples.len() as u32,
+            padding_3: 0,
+            pattern: buffer.pattern,
+            timestamp: buffer.timestamp,
+        }
+    }
+}
+
+/// An owned sample produced by the container parsers, with everything needed
+/// to build an `InputBuffer`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// Track ID in MP4, track number in WebM, PID in MPEG-2 TS.
+    pub track_id: u32,
+    pub data: Vec<u8>,
+    /// Presentation timestamp in microseconds.
+    pub timestamp: i64,
+    pub encryption_scheme: EncryptionScheme,
+    pub key_id: Vec<u8>,
+    pub iv: Vec<u8>,
+    pub subsamples: Vec<SubsampleEntry>,
+    pub pattern: Pattern,
+}
+
+impl Sample {
+    pub fn input_buffer(&self) -> InputBuffer<'_> {
+        InputBuffer {
+            data: &self.data,
             encryption_scheme: self.encryption_scheme,
-            key_id: self.key_id.as_ptr(),
-            key_id_size: self.key_id.len() as u32,
-            iv: self.iv.as_ptr(),
-            iv_size: self.iv.len() as u32,
-            subsamples: self.subsamples.as_ptr(),
-            num_subsamples: self.subsamples.len() as u32,
+            key_id: &self.key_id,
+            iv: &self.iv,
+            subsamples: self.subsamples.clone(),
             pattern: self.pattern,
             timestamp: self.timestamp,
         }
     }
 }
 
+cdm_enum! {
+    #[derive(Debug)]
+    pub enum Status {
+        Success = 0,
+        NeedsMoreData = 1,
+        NoKey = 2,
+        InitializationError = 3,
+        DecryptError = 4,
+        DecodeError = 5,
+        DeferredInitialization = 6,
+    }
+}
+
+/// `cdm::DecryptedBlock`, in which `Decrypt` sets a buffer it got from
+/// `Host_10::Allocate`.
 #[repr(C)]
-#[derive(Debug)]
-pub enum Status {
-    Success,
-    NeedsMoreData,
-    NoKey,
-    InitializationError,
-    DecryptError,
-    DecodeError,
-    DeferredInitialization,
+pub struct DecryptedBlock {
+    vtable: &'static DecryptedBlockVtable,
+    buffer: *mut Buffer,
+    timestamp: i64,
 }
 
 #[repr(C)]
-#[derive(Debug)]
-pub struct DecryptionResult {
-    pub status: Status,
-    pub data: *mut c_uchar,
-    pub capacity: c_uint,
-    pub size: c_uint,
+struct DecryptedBlockVtable {
+    set_decrypted_buffer: extern "C" fn(*mut DecryptedBlock, *mut Buffer),
+    decrypted_buffer: extern "C" fn(*mut DecryptedBlock) -> *mut Buffer,
+    set_timestamp: extern "C" fn(*mut DecryptedBlock, i64),
+    timestamp: extern "C" fn(*const DecryptedBlock) -> i64,
+    destructor: extern "C" fn(*mut DecryptedBlock),
+    deleting_destructor: extern "C" fn(*mut DecryptedBlock),
 }
+
+check_type!(DecryptedBlockVtable, 24, 48, 4, 8);
+
+static DECRYPTED_BLOCK_VTABLE: DecryptedBlockVtable = DecryptedBlockVtable {
+    set_decrypted_buffer,
+    decrypted_buffer,
+    set_timestamp,
+    timestamp,
+    // Only ever on the host's stack, the CDM has no business destroying it.
+    destructor: ignore_destructor,
+    deleting_destructor: ignore_destructor,
+};
+
+impl Default for DecryptedBlock {
+    fn default() -> Self {
+        Self {
+            vtable: &DECRYPTED_BLOCK_VTABLE,
+            buffer: ptr::null_mut(),
+            timestamp: 0,
+        }
+    }
+}
+
+impl DecryptedBlock {
+    /// The decrypted data, if the CDM set a buffer.
+    pub fn take_data(&mut self) -> Option<Vec<u8>> {
+        let buffer = std::mem::replace(&mut self.buffer, ptr::null_mut());
+        if buffer.is_null() {
+            None
+        } else {
+            Some(unsafe { Buffer::into_data(buffer) })
+        }
+    }
+}
+
+impl Drop for DecryptedBlock {
+    fn drop(&mut self) {
+        self.take_data();
+    }
+}
+
+extern "C" fn set_decrypted_buffer(this: *mut DecryptedBlock, buffer: *mut Buffer) {
+    let this = unsafe { &mut *this };
+    // A buffer set earlier would leak otherwise.
+    this.take_data();
+    this.buffer = buffer;
+}
+
+extern "C" fn decrypted_buffer(this: *mut DecryptedBlock) -> *mut Buffer {
+    unsafe { (*this).buffer }
+}
+
+extern "C" fn set_timestamp(this: *mut DecryptedBlock, timestamp: i64) {
+    unsafe { (*this).timestamp = timestamp };
+}
+
+extern "C" fn timestamp(this: *const DecryptedBlock) -> i64 {
+    unsafe { (*this).timestamp }
+}
+
+extern "C" fn ignore_destructor(_: *mut DecryptedBlock) {}
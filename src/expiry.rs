@@ -0,0 +1,68 @@
+use crate::types::{Expiration, SessionId};
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/// Tracks the expiration of each session against the host clock.
+#[derive(Debug, Default)]
+pub struct ExpiryWatcher {
+    expirations: HashMap<SessionId, Expiration>,
+    expired: HashSet<SessionId>,
+}
+
+impl ExpiryWatcher {
+    /// Records a new expiration. A session that expired can expire again
+    /// after its license was renewed.
+    pub fn set(&mut self, session_id: &SessionId, expiration: Expiration) {
+        self.expirations.insert(session_id.clone(), expiration);
+        self.expired.remove(session_id);
+    }
+
+    pub fn get(&self, session_id: &SessionId) -> Option<Expiration> {
+        self.expirations.get(session_id).copied()
+    }
+
+    pub fn remove(&mut self, session_id: &SessionId) {
+        self.expirations.remove(session_id);
+        self.expired.remove(session_id);
+    }
+
+    /// The sessions that expired since the last call.
+    pub fn take_expired(&mut self, now: SystemTime) -> Vec<SessionId> {
+        let mut newly_expired = Vec::new();
+        for (session_id, expiration) in &self.expirations {
+            let has_expired = match expiration {
+                Expiration::At(time) => *time <= now,
+                Expiration::Never => false,
+            };
+            if has_expired && self.expired.insert(session_id.clone()) {
+                newly_expired.push(session_id.clone());
+            }
+        }
+        newly_expired
+    }
+}
+
+#[test]
+fn test_sessions_expire_once() {
+    use std::time::Duration;
+
+    let short = SessionId::from("short");
+    let now = SystemTime::now();
+    let mut watcher = ExpiryWatcher::default();
+    watcher.set(&short, Expiration::At(now + Duration::from_secs(10)));
+    watcher.set(
+        &"long".into(),
+        Expiration::At(now + Duration::from_secs(60)),
+    );
+    watcher.set(&"forever".into(), Expiration::from(f64::NAN));
+
+    assert!(watcher.take_expired(now).is_empty());
+    let later = now + Duration::from_secs(30);
+    assert_eq!(watcher.take_expired(later), vec![short.clone()]);
+    assert!(watcher.take_expired(later).is_empty());
+
+    // Renewed, so it can expire again.
+    watcher.set(&short, Expiration::At(now + Duration::from_secs(20)));
+    assert_eq!(watcher.take_expired(later), vec![short.clone()]);
+    assert_eq!(watcher.get(&"forever".into()), Some(Expiration::Never));
+}
@@ -0,0 +1,223 @@
+use crate::abi;
+use crate::host::{guarded, Host};
+use crate::storage::FileStatus;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// `cdm::FileIOClient`, implemented by the CDM.
+#[repr(C)]
+pub struct FileIOClient {
+    vtable: *const FileIOClientVtable,
+}
+
+#[repr(C)]
+struct FileIOClientVtable {
+    on_open_complete: unsafe extern "C" fn(*mut FileIOClient, FileStatus),
+    on_read_complete: unsafe extern "C" fn(*mut FileIOClient, FileStatus, *const u8, u32),
+    on_write_complete: unsafe extern "C" fn(*mut FileIOClient, FileStatus),
+}
+
+check_offset!(FileIOClientVtable, on_write_complete, 8, 16);
+
+/// `cdm::FileIO`, created by `Host_10::CreateFileIO` over the storage of the
+/// host. `Close` destroys it. The client is called back once `Open`, `Read`
+/// and `Write` have returned, as the interface expects, through the
+/// `FileCompletion`s queued in the host.
+#[repr(C)]
+pub struct FileIO {
+    vtable: &'static FileIOVtable,
+    host: *mut Host,
+    client: *mut FileIOClient,
+    /// Set once the file is opened.
+    file_name: Option<String>,
+}
+
+#[repr(C)]
+struct FileIOVtable {
+    open: extern "C" fn(*mut FileIO, *const c_char, u32),
+    read: extern "C" fn(*mut FileIO),
+    write: extern "C" fn(*mut FileIO, *const u8, u32),
+    close: extern "C" fn(*mut FileIO),
+    destructor: extern "C" fn(*mut FileIO),
+    deleting_destructor: extern "C" fn(*mut FileIO),
+}
+
+check_type!(FileIOVtable, 24, 48, 4, 8);
+
+static VTABLE: FileIOVtable = FileIOVtable {
+    open,
+    read,
+    write,
+    close,
+    destructor,
+    deleting_destructor,
+};
+
+impl FileIO {
+    pub fn create(host: *mut Host, client: *mut FileIOClient) -> *mut FileIO {
+        let file_io = FileIO {
+            vtable: &VTABLE,
+            host,
+            client,
+            file_name: None,
+        };
+        Box::into_raw(Box::new(file_io))
+    }
+}
+
+/// The result of a `FileIO` operation, waiting for the host to hand it to
+/// the client.
+pub(crate) struct FileCompletion {
+    file_io: *mut FileIO,
+    client: *mut FileIOClient,
+    result: FileResult,
+}
+
+enum FileResult {
+    Open(FileStatus),
+    Read(Option<Vec<u8>>),
+    Write(FileStatus),
+}
+
+impl FileCompletion {
+    pub(crate) fn file_io(&self) -> *mut FileIO {
+        self.file_io
+    }
+
+    /// Calls the client back. The client may call into the `FileIO` again,
+    /// even close it, so no reference to it is held.
+    ///
+    /// # Safety
+    ///
+    /// The CDM must still be alive and the `FileIO` not closed.
+    pub(crate) unsafe fn deliver(self) {
+        let (client, vtable) = (self.client, &*(*self.client).vtable);
+        match self.result {
+            FileResult::Open(status) => (vtable.on_open_complete)(client, status),
+            FileResult::Read(Some(data)) => (vtable.on_read_complete)(
+                client,
+                FileStatus::Success,
+                data.as_ptr(),
+                data.len() as u32,
+            ),
+            FileResult::Read(None) => {
+                (vtable.on_read_complete)(client, FileStatus::Error, ptr::null(), 0)
+            }
+            FileResult::Write(status) => (vtable.on_write_complete)(client, status),
+        }
+    }
+}
+
+fn complete(this: *mut FileIO, result: FileResult) {
+    unsafe {
+        let completion = FileCompletion {
+            file_io: this,
+            client: (*this).client,
+            result,
+        };
+        (*(*this).host).queue_file_completion(completion);
+    }
+}
+
+extern "C" fn open(this: *mut FileIO, file_name: *const c_char, file_name_size: u32) {
+    let host = unsafe { (*this).host };
+    let status = guarded(host, "open_file", FileStatus::Error, || {
+        let name = unsafe { abi::slice(file_name as *const u8, file_name_size) };
+        let name = match std::str::from_utf8(name) {
+            Ok(name) => name,
+            Err(_) => return FileStatus::Error,
+        };
+        let status = unsafe { (*host).open_file(name) };
+        if status == FileStatus::Success {
+            unsafe { (*this).file_name = Some(name.to_string()) };
+        }
+        status
+    });
+    complete(this, FileResult::Open(status));
+}
+
+extern "C" fn read(this: *mut FileIO) {
+    let host = unsafe { (*this).host };
+    let data = guarded(host, "read_file", None, || {
+        let name = unsafe { (*this).file_name.as_ref()? };
+        unsafe { (*host).read_file(name) }
+    });
+    complete(this, FileResult::Read(data));
+}
+
+extern "C" fn write(this: *mut FileIO, data: *const u8, data_size: u32) {
+    let host = unsafe { (*this).host };
+    let status = guarded(host, "write_file", FileStatus::Error, || {
+        let data = unsafe { abi::slice(data, data_size) };
+        match unsafe { &(*this).file_name } {
+            Some(name) => unsafe { (*host).write_file(name, data) },
+            None => FileStatus::Error,
+        }
+    });
+    complete(this, FileResult::Write(status));
+}
+
+extern "C" fn close(this: *mut FileIO) {
+    let file_io = unsafe { Box::from_raw(this) };
+    guarded(file_io.host, "close_file", (), || {
+        unsafe { (*file_io.host).cancel_file_completions(this) };
+        if let Some(ref name) = file_io.file_name {
+            unsafe { (*file_io.host).close_file(name) };
+        }
+    });
+}
+
+extern "C" fn destructor(this: *mut FileIO) {
+    unsafe { ptr::drop_in_place(this) };
+}
+
+extern "C" fn deleting_destructor(this: *mut FileIO) {
+    drop(unsafe { Box::from_raw(this) });
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_mock_cdm_file_io() {
+    use crate::init_data::{InitData, KeyIds};
+    use crate::types::SessionType;
+    use crate::{base64, library, LoadSessionError, WidevineAPI};
+    use serde_json::json;
+    use std::sync::mpsc::channel;
+
+    let directory =
+        std::env::temp_dir().join(format!("widevine_rs-file-io-{}", std::process::id()));
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.set_storage_directory(&directory);
+    api.initialize_cdm().await.unwrap();
+
+    // The mock fails file operations completed before the `FileIO` call
+    // that started them returned.
+    let (sender, _receiver) = channel();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x55; 16]));
+    let session_type = SessionType::PersistentLicense;
+    let session_id = api
+        .create_session(session_type, init_data, sender.clone())
+        .await
+        .unwrap();
+    let key = json!({
+        "kty": "oct",
+        "kid": base64::encode_url(&[0x55; 16]),
+        "k": base64::encode_url(&[0x44; 16]),
+    });
+    let license = json!({ "keys": [key] }).to_string();
+    api.update_session(&session_id, license.as_bytes())
+        .await
+        .unwrap();
+    let file = std::fs::read(directory.join(session_id.to_string())).unwrap();
+    assert!(!file.is_empty());
+
+    api.close_session(&session_id).await.unwrap();
+    api.load_session(session_type, &session_id, sender.clone())
+        .await
+        .unwrap();
+    let result = api
+        .load_session(session_type, &"missing".into(), sender)
+        .await;
+    assert!(matches!(result, Err(LoadSessionError::NotFound)));
+    std::fs::remove_dir_all(directory).unwrap();
+}
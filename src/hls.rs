@@ -0,0 +1,336 @@
+use crate::base64;
+use crate::decryption::EncryptionScheme;
+use crate::init_data::InitData;
+use crate::pssh::{PsshBox, PsshError, WidevinePsshData};
+use crate::ts::SegmentKey;
+
+const WIDEVINE_KEY_FORMAT: &str = "urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed";
+const DATA_URI_PREFIX: &str = "data:";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HlsError {
+    NotAPlaylist,
+    MissingAttribute(&'static str),
+    InvalidDataUri(String),
+    InvalidHex(String),
+    UnsupportedMethod(String),
+    InvalidPssh(PsshError),
+}
+
+impl From<PsshError> for HlsError {
+    fn from(error: PsshError) -> Self {
+        HlsError::InvalidPssh(error)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyMethod {
+    /// `cbcs`
+    SampleAes,
+    /// `cenc`
+    SampleAesCtr,
+}
+
+impl KeyMethod {
+    pub fn encryption_scheme(self) -> EncryptionScheme {
+        match self {
+            KeyMethod::SampleAes => EncryptionScheme::Cbcs,
+            KeyMethod::SampleAesCtr => EncryptionScheme::Cenc,
+        }
+    }
+}
+
+/// A Widevine `#EXT-X-KEY` or `#EXT-X-SESSION-KEY` entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WidevineKey {
+    pub method: KeyMethod,
+    pub init_data: InitData,
+    /// From the `KEYID` attribute, or the first key ID of the PSSH.
+    pub key_id: Option<Vec<u8>>,
+    pub iv: Option<[u8; 16]>,
+}
+
+impl WidevineKey {
+    pub fn encryption_scheme(&self) -> EncryptionScheme {
+        self.method.encryption_scheme()
+    }
+
+    fn parse(attributes: &[(String, String)]) -> Result<Option<Self>, HlsError> {
+        let key_format = find_attribute(attributes, "KEYFORMAT");
+        if !key_format.is_some_and(|format| format.eq_ignore_ascii_case(WIDEVINE_KEY_FORMAT)) {
+            return Ok(None);
+        }
+
+        let method = match find_attribute(attributes, "METHOD") {
+            Some("NONE") => return Ok(None),
+            Some("SAMPLE-AES") => KeyMethod::SampleAes,
+            Some("SAMPLE-AES-CTR") => KeyMethod::SampleAesCtr,
+            Some(method) => return Err(HlsError::UnsupportedMethod(method.to_string())),
+            None => return Err(HlsError::MissingAttribute("METHOD")),
+        };
+
+        let uri = find_attribute(attributes, "URI").ok_or(HlsError::MissingAttribute("URI"))?;
+        let data = parse_data_uri(uri)?;
+        // The URI carries a whole PSSH box, but some packagers only put the
+        // `WidevinePsshData` in it.
+        let pssh = match PsshBox::parse_all(&data) {
+            Ok(boxes) => boxes
+                .into_iter()
+                .find(PsshBox::is_widevine)
+                .ok_or(HlsError::InvalidDataUri(uri.to_string()))?,
+            Err(_) => PsshBox::widevine(&WidevinePsshData::decode(&data)?),
+        };
+
+        let key_id = match find_attribute(attributes, "KEYID") {
+            Some(key_id) => Some(parse_hex(key_id)?),
+            None => pssh.widevine_data()?.key_ids.into_iter().next(),
+        };
+        let iv = match find_attribute(attributes, "IV") {
+            Some(value) => {
+                let bytes = parse_hex(value)?;
+                let mut iv = [0; 16];
+                if bytes.len() != iv.len() {
+                    return Err(HlsError::InvalidHex(value.to_string()));
+                }
+                iv.copy_from_slice(&bytes);
+                Some(iv)
+            }
+            None => None,
+        };
+
+        Ok(Some(Self {
+            method,
+            init_data: InitData::from(pssh),
+            key_id,
+            iv,
+        }))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub uri: String,
+    pub media_sequence: u64,
+    /// The Widevine key the segment is encrypted with, if any.
+    pub key: Option<WidevineKey>,
+}
+
+impl Segment {
+    /// Key ID and IV to decrypt the segment with. Without an explicit `IV`
+    /// attribute, the media sequence number is used as the IV.
+    pub fn segment_key(&self) -> Option<SegmentKey> {
+        let key = self.key.as_ref()?;
+        let iv = key.iv.unwrap_or_else(|| {
+            let mut iv = [0; 16];
+            iv[8..].copy_from_slice(&self.media_sequence.to_be_bytes());
+            iv
+        });
+        Some(SegmentKey {
+            key_id: key.key_id.clone()?,
+            iv: iv.to_vec(),
+        })
+    }
+}
+
+/// Widevine keys and URIs of a master or media playlist.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Playlist {
+    /// `#EXT-X-SESSION-KEY` entries, found in master playlists.
+    pub session_keys: Vec<WidevineKey>,
+    /// URIs of the `#EXT-X-STREAM-INF` variant streams, found in master
+    /// playlists.
+    pub variants: Vec<String>,
+    /// Media segments, found in media playlists.
+    pub segments: Vec<Segment>,
+}
+
+impl Playlist {
+    pub fn parse(playlist: &str) -> Result<Self, HlsError> {
+        let mut lines = playlist.lines().map(str::trim);
+        if lines.next() != Some("#EXTM3U") {
+            return Err(HlsError::NotAPlaylist);
+        }
+
+        let mut parsed = Self::default();
+        let mut media_sequence = 0;
+        let mut key = None;
+        // Consecutive key tags describe the same segments in several key
+        // formats, a new run replaces the previous one.
+        let mut keys_follow_segment = true;
+        // The next URI is a variant stream's rather than a segment's.
+        let mut variant_follows = false;
+
+        for line in lines {
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                media_sequence = value.parse().unwrap_or(0);
+            } else if let Some(value) = line.strip_prefix("#EXT-X-SESSION-KEY:") {
+                if let Some(session_key) = WidevineKey::parse(&parse_attributes(value))? {
+                    parsed.session_keys.push(session_key);
+                }
+            } else if let Some(value) = line.strip_prefix("#EXT-X-KEY:") {
+                if keys_follow_segment {
+                    key = None;
+                    keys_follow_segment = false;
+                }
+                if let Some(widevine_key) = WidevineKey::parse(&parse_attributes(value))? {
+                    key = Some(widevine_key);
+                }
+            } else if line.starts_with("#EXT-X-STREAM-INF:") {
+                variant_follows = true;
+            } else if variant_follows && !line.starts_with('#') {
+                parsed.variants.push(line.to_string());
+                variant_follows = false;
+            } else if !line.starts_with('#') {
+                parsed.segments.push(Segment {
+                    uri: line.to_string(),
+                    media_sequence,
+                    key: key.clone(),
+                });
+                media_sequence += 1;
+                keys_follow_segment = true;
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Every distinct Widevine key of the playlist, session keys first.
+    pub fn keys(&self) -> Vec<&WidevineKey> {
+        let mut keys: Vec<&WidevineKey> = Vec::new();
+        let segment_keys = self
+            .segments
+            .iter()
+            .filter_map(|segment| segment.key.as_ref());
+        for key in self.session_keys.iter().chain(segment_keys) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
+
+/// Splits an attribute list, keeping commas inside quoted strings.
+fn parse_attributes(list: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = list;
+    while !rest.is_empty() {
+        let equals = match rest.find('=') {
+            Some(index) => index,
+            None => break,
+        };
+        let name = rest[..equals].trim().to_string();
+        rest = &rest[equals + 1..];
+
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let value = &quoted[..end];
+            rest = quoted.get(end + 1..).unwrap_or_default();
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        attributes.push((name, value.trim().to_string()));
+        rest = rest.trim_start_matches(',');
+    }
+    attributes
+}
+
+fn find_attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn parse_data_uri(uri: &str) -> Result<Vec<u8>, HlsError> {
+    let invalid = || HlsError::InvalidDataUri(uri.to_string());
+    let rest = uri.strip_prefix(DATA_URI_PREFIX).ok_or_else(invalid)?;
+    let comma = rest.find(',').ok_or_else(invalid)?;
+    if !rest[..comma].ends_with(";base64") {
+        return Err(invalid());
+    }
+    base64::decode(&rest[comma + 1..]).map_err(|_| invalid())
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, HlsError> {
+    let invalid = || HlsError::InvalidHex(value.to_string());
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+#[test]
+fn test_parse_media_playlist() {
+    let pssh_data = WidevinePsshData::new(b"hls-content", &[&[0x42; 16]]);
+    let uri = format!(
+        "data:text/plain;base64,{}",
+        base64::encode_url(&pssh_data.to_init_data())
+    );
+    let playlist = format!(
+        "#EXTM3U\n\
+         #EXT-X-VERSION:5\n\
+         #EXT-X-MEDIA-SEQUENCE:7\n\
+         #EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"skd://other\",KEYFORMAT=\"com.apple.streamingkeydelivery\"\n\
+         #EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"{}\",KEYFORMAT=\"urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed\",KEYFORMATVERSIONS=\"1\"\n\
+         #EXTINF:6.0,\n\
+         segment-7.ts\n\
+         #EXTINF:6.0,\n\
+         segment-8.ts\n\
+         #EXT-X-KEY:METHOD=NONE\n\
+         #EXTINF:6.0,\n\
+         segment-9.ts\n",
+        uri
+    );
+
+    let playlist = Playlist::parse(&playlist).unwrap();
+    assert_eq!(playlist.segments.len(), 3);
+    assert_eq!(playlist.keys().len(), 1);
+
+    let key = playlist.segments[0].key.as_ref().unwrap();
+    assert_eq!(key.encryption_scheme(), EncryptionScheme::Cbcs);
+    assert_eq!(key.init_data, InitData::from(&pssh_data));
+
+    let segment_key = playlist.segments[1].segment_key().unwrap();
+    assert_eq!(segment_key.key_id, vec![0x42; 16]);
+    assert_eq!(segment_key.iv[15], 8);
+    assert!(playlist.segments[2].segment_key().is_none());
+}
+
+#[test]
+fn test_parse_master_playlist() {
+    let pssh_data = WidevinePsshData::new(b"hls-content", &[&[0x42; 16]]);
+    let playlist = format!(
+        "#EXTM3U\n\
+         #EXT-X-SESSION-KEY:METHOD=SAMPLE-AES-CTR,URI=\"data:text/plain;base64,{}\",KEYFORMAT=\"urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed\"\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=1280000,CODECS=\"avc1.64001f,mp4a.40.2\"\n\
+         low/index.m3u8\n\
+         #EXT-X-STREAM-INF:BANDWIDTH=2560000\n\
+         high/index.m3u8\n",
+        base64::encode(&pssh_data.to_init_data())
+    );
+
+    let playlist = Playlist::parse(&playlist).unwrap();
+    assert_eq!(playlist.variants, ["low/index.m3u8", "high/index.m3u8"]);
+    assert!(playlist.segments.is_empty());
+    assert_eq!(playlist.session_keys.len(), 1);
+    assert_eq!(
+        playlist.session_keys[0].encryption_scheme(),
+        EncryptionScheme::Cenc
+    );
+    assert_eq!(playlist.session_keys[0].key_id, Some(vec![0x42; 16]));
+}
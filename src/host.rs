@@ -1,240 +1,338 @@
+use crate::abi;
+use crate::buffer::Buffer;
+use crate::file_io::{FileCompletion, FileIO, FileIOClient};
 use crate::promise_set::{
-    FuturePromise, PromiseManager, PromiseResult, PromiseResultData, RejectionInfo,
+    self, FuturePromise, PromiseManager, PromiseResult, PromiseResultData, RejectionInfo,
     INITIALIZED_PROMISE_ID,
 };
-use crate::remote_buffer::RemoteBuffer;
+use crate::storage::{FileStatus, FileStorage};
 use crate::timer::{Timer, TimerManager};
 use crate::types::{
     CDMKeyInformation, Exception, KeyInformation, KeysChange, MessageType, SessionEvent,
-    SessionEventType, SessionMessage,
+    SessionEventType, SessionId, SessionMessage,
 };
+use crate::{HostError, Operation, UnsettledPromise};
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryInto;
-use std::ffi::CStr;
 use std::os::raw::{c_char, c_double, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
-use std::slice;
 use std::sync::mpsc::{Sender, TryIter};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-extern "C" {
-    fn CreateHost(
-        target: *mut c_void,
-        callback: *mut HostCallback,
-        remote_buffer: *mut RemoteBuffer,
-    ) -> *mut c_void;
-    fn DeinitializeHost(host: *mut c_void);
+// TODO: buffer pool
+extern "C" fn allocate(this: *mut Host, capacity: c_uint) -> *mut Buffer {
+    guarded(this, "allocate", ptr::null_mut(), || {
+        Buffer::allocate(capacity)
+    })
 }
 
-extern "C" fn on_initialized(success: bool, target: *mut c_void) {
-    let target = target as *mut Host;
-    let result = PromiseResult::Resolved(PromiseResultData::Initialized(success));
-    let promise_id = INITIALIZED_PROMISE_ID;
+extern "C" fn set_timer(this: *mut Host, delay_ms: i64, context: *mut c_void) {
+    guarded(this, "set_timer", (), || {
+        // Negative delays fire right away.
+        let delay_ms = delay_ms.max(0) as u64;
+        unsafe { (*this).timer_manager.new_timer(delay_ms, context) }
+    })
+}
 
-    unsafe { wake_promise(promise_id, result, target) };
+extern "C" fn get_current_wall_time(_: *mut Host) -> c_double {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or(0.0)
 }
 
-extern "C" fn on_resolve(promise_id: c_uint, target: *mut c_void) {
-    let target = target as *mut Host;
-    let result = PromiseResult::Resolved(PromiseResultData::None);
-    let promise_id: usize = promise_id.try_into().unwrap();
+extern "C" fn on_initialized(this: *mut Host, success: bool) {
+    guarded(this, "on_initialized", (), || {
+        let result = PromiseResult::Resolved(PromiseResultData::Initialized(success));
+        let promise_id = INITIALIZED_PROMISE_ID;
 
-    unsafe { wake_promise(promise_id, result, target) };
+        unsafe { wake_promise(promise_id, result, this) };
+    })
 }
 
-extern "C" fn on_reject(
+extern "C" fn on_resolve_new_session(
+    this: *mut Host,
     promise_id: c_uint,
-    exception: Exception,
-    system_code: c_uint,
-    error_message: *const c_char,
-    _: c_uint,
-    target: *mut c_void,
+    session_id: *const c_char,
+    session_id_size: c_uint,
 ) {
-    let target = target as *mut Host;
-    let error_message = unsafe { CStr::from_ptr(error_message) };
-    let info = RejectionInfo {
-        error_message: error_message.to_string_lossy().into_owned(),
-        system_code,
-        exception,
-    };
+    guarded(this, "on_resolve_new_session", (), || {
+        // `LoadSession` resolves with an empty session ID when there is no
+        // such session.
+        let session_id = unsafe { session_id_from(session_id, session_id_size) };
+        let result = PromiseResult::Resolved(PromiseResultData::NewSession(session_id));
+        let promise_id: usize = promise_id.try_into().unwrap();
 
-    let result = PromiseResult::Rejected(info);
-    let promise_id: usize = promise_id.try_into().unwrap();
-    unsafe { wake_promise(promise_id, result, target) };
+        unsafe { wake_promise(promise_id, result, this) };
+    })
 }
 
-extern "C" fn on_resolve_new_session(
+extern "C" fn on_resolve(this: *mut Host, promise_id: c_uint) {
+    guarded(this, "on_resolve", (), || {
+        let result = PromiseResult::Resolved(PromiseResultData::None);
+        let promise_id: usize = promise_id.try_into().unwrap();
+
+        unsafe { wake_promise(promise_id, result, this) };
+    })
+}
+
+extern "C" fn on_reject(
+    this: *mut Host,
     promise_id: c_uint,
-    session_id: *const c_char,
-    _: c_uint,
-    target: *mut c_void,
+    exception: u32,
+    system_code: c_uint,
+    error_message: *const c_char,
+    error_message_size: c_uint,
 ) {
-    let target = target as *mut Host;
-    let session_id = unsafe { CStr::from_ptr(session_id) }.to_string_lossy();
-    let result = PromiseResult::Resolved(PromiseResultData::NewSession(session_id.into_owned()));
-    let promise_id: usize = promise_id.try_into().unwrap();
+    guarded(this, "on_reject", (), || {
+        let error_message = unsafe { abi::slice(error_message as *const u8, error_message_size) };
+        let info = RejectionInfo {
+            error_message: String::from_utf8_lossy(error_message).into_owned(),
+            system_code,
+            exception: Exception::from_cdm(exception),
+        };
 
-    unsafe { wake_promise(promise_id, result, target) };
+        let result = PromiseResult::Rejected(info);
+        let promise_id: usize = promise_id.try_into().unwrap();
+        unsafe { wake_promise(promise_id, result, this) };
+    })
 }
 
 extern "C" fn on_session_message(
+    this: *mut Host,
     session_id: *const c_char,
-    _: c_uint,
-    message_type: MessageType,
+    session_id_size: c_uint,
+    message_type: u32,
     message: *const u8,
     message_length: c_uint,
-    target: *mut c_void,
 ) {
-    let session_id = unsafe { CStr::from_ptr(session_id) }.to_string_lossy();
-    let content: &[u8] = unsafe { slice::from_raw_parts(message, message_length as usize) };
-    let event = SessionEvent {
-        session_id: session_id.to_string(),
-        data: SessionEventType::Message(SessionMessage {
-            message_type,
-            content: content.to_vec(),
-        }),
-    };
+    guarded(this, "on_session_message", (), || {
+        let session_id = unsafe { session_id_from(session_id, session_id_size) };
+        let content = unsafe { abi::slice(message, message_length) };
+        let event = SessionEvent {
+            session_id,
+            data: SessionEventType::Message(SessionMessage {
+                message_type: MessageType::from_cdm(message_type),
+                content: content.to_vec(),
+            }),
+        };
 
-    unsafe { send_event(event, target as *mut Host) }
+        unsafe { send_event(event, this) }
+    })
 }
 
-// TODO: buffer pool
-extern "C" fn allocate(capacity: c_uint) -> *mut c_void {
-    let buffer: Box<Vec<u8>> = Box::new(vec![0; capacity as usize]);
-    Box::into_raw(buffer) as *mut c_void
+extern "C" fn on_session_keys_change(
+    this: *mut Host,
+    session_id: *const c_char,
+    session_id_size: c_uint,
+    has_additional_usable_key: bool,
+    keys_info: *const CDMKeyInformation,
+    keys_info_count: c_uint,
+) {
+    guarded(this, "on_session_keys_change", (), || {
+        let session_id = unsafe { session_id_from(session_id, session_id_size) };
+        let keys_info = unsafe { abi::slice(keys_info, keys_info_count) };
+        let keys_info: Vec<KeyInformation> = keys_info.iter().cloned().map(|x| x.into()).collect();
+
+        let event = SessionEvent {
+            session_id,
+            data: SessionEventType::KeysChange(KeysChange {
+                has_additional_usable_key,
+                keys_info,
+            }),
+        };
+        unsafe { send_event(event, this) }
+    })
 }
 
 extern "C" fn on_expiration_change(
+    this: *mut Host,
     session_id: *const c_char,
-    _session_id_size: c_uint,
+    session_id_size: c_uint,
     new_expiry_time: c_double,
-    target: *mut c_void,
 ) {
-    let session_id = unsafe { CStr::from_ptr(session_id) }.to_string_lossy();
-    let event = SessionEvent {
-        session_id: session_id.to_string(),
-        data: SessionEventType::ExpirationChange(new_expiry_time),
-    };
-    unsafe { send_event(event, target as *mut Host) }
+    guarded(this, "on_expiration_change", (), || {
+        let session_id = unsafe { session_id_from(session_id, session_id_size) };
+        let event = SessionEvent {
+            session_id,
+            data: SessionEventType::ExpirationChange(new_expiry_time.into()),
+        };
+        unsafe { send_event(event, this) }
+    })
 }
 
-extern "C" fn on_session_keys_change(
-    session_id: *const c_char,
-    _session_id_size: c_uint,
-    has_additional_usable_key: bool,
-    keys_info: *const CDMKeyInformation,
-    keys_info_count: c_uint,
-    target: *mut c_void,
+extern "C" fn create_file_io(this: *mut Host, client: *mut FileIOClient) -> *mut FileIO {
+    guarded(this, "create_file_io", ptr::null_mut(), || {
+        FileIO::create(this, client)
+    })
+}
+
+// The rest of `Host_10` is left unimplemented: the methods are only called
+// for features the host doesn't ask for, or they only inform it.
+
+/// `GetStatusForPolicy` is never called.
+extern "C" fn on_resolve_key_status(_: *mut Host, _: c_uint, _: u32) {}
+
+extern "C" fn on_session_closed(_: *mut Host, _: *const c_char, _: c_uint) {}
+
+extern "C" fn send_platform_challenge(
+    _: *mut Host,
+    _: *const c_char,
+    _: c_uint,
+    _: *const c_char,
+    _: c_uint,
 ) {
-    let session_id = unsafe { CStr::from_ptr(session_id) }.to_string_lossy();
-    let keys_info: &[CDMKeyInformation] =
-        unsafe { slice::from_raw_parts(keys_info, keys_info_count as usize) };
-    let keys_info: Vec<KeyInformation> = keys_info.into_iter().cloned().map(|x| x.into()).collect();
-
-    let event = SessionEvent {
-        session_id: session_id.to_string(),
-        data: SessionEventType::KeysChange(KeysChange {
-            has_additional_usable_key,
-            keys_info,
-        }),
-    };
-    unsafe { send_event(event, target as *mut Host) }
 }
 
-extern "C" fn set_timer(delay_ms: u64, context: *mut c_void, target: *mut c_void) {
-    let target = target as *mut Host;
-    unsafe { (*target).timer_manager.new_timer(delay_ms, context) }
+extern "C" fn enable_output_protection(_: *mut Host, _: u32) {}
+
+extern "C" fn query_output_protection_status(_: *mut Host) {}
+
+extern "C" fn on_deferred_initialization_done(_: *mut Host, _: u32, _: u32) {}
+
+extern "C" fn request_storage_id(_: *mut Host, _: u32) {}
+
+/// The host belongs to `WidevineAPI`, never to the CDM.
+extern "C" fn ignore_destructor(_: *mut Host) {}
+
+unsafe fn session_id_from(session_id: *const c_char, session_id_size: c_uint) -> SessionId {
+    SessionId::from(abi::slice(session_id as *const u8, session_id_size))
 }
 
-unsafe fn wake_promise(promise_id: usize, result: PromiseResult, target: *mut Host) {
-    let mut pm = (*target).promise_manager.lock().unwrap();
-    pm.finished_promises.insert(promise_id, result);
-    pm.wake(promise_id);
+unsafe fn wake_promise(promise_id: usize, result: PromiseResult, host: *mut Host) {
+    promise_set::lock(&(*host).promise_manager).settle(promise_id, result);
 }
 
-unsafe fn send_event(event: SessionEvent, target: *mut Host) {
-    if let Some(ref sender) = (*target).event_sender {
-        sender.send(event).unwrap();
+unsafe fn send_event(event: SessionEvent, host: *mut Host) {
+    if let Some(ref sender) = (*host).event_sender {
+        // The application stopped listening, which is no reason to fail.
+        if let Err(error) = sender.send(event) {
+            log::warn!("dropped an event of session {}", error.0.session_id);
+        }
     }
 }
 
+/// Catches a panic before it unwinds into the CDM, returning its message.
+fn catch_panic<R>(callback: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(callback)).map_err(|payload| {
+        match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => String::new(),
+        }
+    })
+}
+
+/// Runs the body of a callback. A panic is recorded as the host's fatal
+/// error, failing its promises, and `fallback` is returned to the CDM.
+pub(crate) fn guarded<R>(
+    host: *mut Host,
+    name: &'static str,
+    fallback: R,
+    body: impl FnOnce() -> R,
+) -> R {
+    catch_panic(body).unwrap_or_else(|message| {
+        let host = unsafe { &*host };
+        let error = HostError::CallbackPanicked {
+            callback: name,
+            message,
+        };
+        host.fail(error);
+        fallback
+    })
+}
+
+/// The vtable of `cdm::Host_10`, per the Itanium C++ ABI: one slot per
+/// virtual method in declaration order, then the complete and deleting
+/// destructors.
 #[repr(C)]
-#[derive(Debug)]
-pub struct HostCallback {
-    on_initialized: extern "C" fn(bool, *mut c_void),
-    on_resolve: extern "C" fn(c_uint, *mut c_void),
-    on_reject: extern "C" fn(c_uint, Exception, c_uint, *const c_char, c_uint, *mut c_void),
-    on_resolve_new_session: extern "C" fn(c_uint, *const c_char, c_uint, *mut c_void),
-    on_session_message:
-        extern "C" fn(*const c_char, c_uint, MessageType, *const u8, c_uint, *mut c_void),
-    allocate: extern "C" fn(c_uint) -> *mut c_void,
-    on_expiration_change: extern "C" fn(*const c_char, c_uint, c_double, *mut c_void),
+struct HostVtable {
+    allocate: extern "C" fn(*mut Host, c_uint) -> *mut Buffer,
+    set_timer: extern "C" fn(*mut Host, i64, *mut c_void),
+    get_current_wall_time: extern "C" fn(*mut Host) -> c_double,
+    on_initialized: extern "C" fn(*mut Host, bool),
+    on_resolve_key_status: extern "C" fn(*mut Host, c_uint, u32),
+    on_resolve_new_session: extern "C" fn(*mut Host, c_uint, *const c_char, c_uint),
+    on_resolve: extern "C" fn(*mut Host, c_uint),
+    on_reject: extern "C" fn(*mut Host, c_uint, u32, c_uint, *const c_char, c_uint),
+    on_session_message: extern "C" fn(*mut Host, *const c_char, c_uint, u32, *const u8, c_uint),
     on_session_keys_change:
-        extern "C" fn(*const c_char, c_uint, bool, *const CDMKeyInformation, c_uint, *mut c_void),
-    set_timer: extern "C" fn(u64, *mut c_void, *mut c_void),
+        extern "C" fn(*mut Host, *const c_char, c_uint, bool, *const CDMKeyInformation, c_uint),
+    on_expiration_change: extern "C" fn(*mut Host, *const c_char, c_uint, c_double),
+    on_session_closed: extern "C" fn(*mut Host, *const c_char, c_uint),
+    send_platform_challenge: extern "C" fn(*mut Host, *const c_char, c_uint, *const c_char, c_uint),
+    enable_output_protection: extern "C" fn(*mut Host, u32),
+    query_output_protection_status: extern "C" fn(*mut Host),
+    on_deferred_initialization_done: extern "C" fn(*mut Host, u32, u32),
+    create_file_io: extern "C" fn(*mut Host, *mut FileIOClient) -> *mut FileIO,
+    request_storage_id: extern "C" fn(*mut Host, u32),
+    destructor: extern "C" fn(*mut Host),
+    deleting_destructor: extern "C" fn(*mut Host),
 }
 
-impl Default for HostCallback {
-    fn default() -> Self {
-        Self {
-            on_initialized,
-            on_resolve,
-            on_reject,
-            on_resolve_new_session,
-            on_session_message,
-            allocate,
-            on_expiration_change,
-            on_session_keys_change,
-            set_timer,
-        }
-    }
-}
+check_type!(HostVtable, 80, 160, 4, 8);
+check_offset!(HostVtable, on_initialized, 12, 24);
+check_offset!(HostVtable, on_reject, 28, 56);
+check_offset!(HostVtable, create_file_io, 64, 128);
+check_offset!(HostVtable, destructor, 72, 144);
+
+static VTABLE: HostVtable = HostVtable {
+    allocate,
+    set_timer,
+    get_current_wall_time,
+    on_initialized,
+    on_resolve_key_status,
+    on_resolve_new_session,
+    on_resolve,
+    on_reject,
+    on_session_message,
+    on_session_keys_change,
+    on_expiration_change,
+    on_session_closed,
+    send_platform_challenge,
+    enable_output_protection,
+    query_output_protection_status,
+    on_deferred_initialization_done,
+    create_file_io,
+    request_storage_id,
+    destructor: ignore_destructor,
+    deleting_destructor: ignore_destructor,
+};
 
+/// `cdm::Host_10`, handed to the CDM by pointer. It has to stay in place,
+/// so it's kept boxed.
 #[repr(C)]
-#[derive(Debug)]
 pub struct Host {
-    pub pointer: *mut c_void,
-    initialized: bool,
-    callback: Box<HostCallback>,
+    vtable: &'static HostVtable,
     promise_manager: Arc<Mutex<PromiseManager>>,
     event_sender: Option<Sender<SessionEvent>>,
-    remote_buffer: Box<RemoteBuffer>,
     timer_manager: TimerManager,
+    storage: Option<FileStorage>,
+    open_files: HashSet<String>,
+    file_completions: VecDeque<FileCompletion>,
 }
 
 impl Default for Host {
     fn default() -> Self {
         Self {
-            pointer: ptr::null_mut(),
-            initialized: false,
-            callback: Box::new(HostCallback::default()),
+            vtable: &VTABLE,
             promise_manager: Arc::new(Mutex::new(PromiseManager::default())),
             event_sender: None,
-            remote_buffer: Box::new(RemoteBuffer::default()),
             timer_manager: TimerManager::default(),
+            storage: None,
+            open_files: HashSet::new(),
+            file_completions: VecDeque::new(),
         }
     }
 }
 
 impl Host {
-    pub fn initialized(self) -> Result<Box<Self>, ()> {
-        let mut host = Box::new(self);
-        let pointer = unsafe {
-            CreateHost(
-                &mut *host as *mut Host as *mut c_void,
-                &mut *host.callback,
-                &mut *host.remote_buffer,
-            )
-        };
-
-        if pointer == ptr::null_mut() {
-            Err(())
-        } else {
-            host.pointer = pointer;
-            Ok(host)
-        }
-    }
-
     pub fn get_future(&mut self, promise_id: usize) -> FuturePromise {
         FuturePromise {
             id: promise_id,
@@ -242,33 +340,183 @@ impl Host {
         }
     }
 
+    pub fn register_promise(&mut self, promise_id: usize, operation: Operation) {
+        promise_set::lock(&self.promise_manager).register(promise_id, operation);
+    }
+
+    pub fn unsettled_promises(&self) -> Vec<UnsettledPromise> {
+        promise_set::lock(&self.promise_manager).unsettled()
+    }
+
+    pub fn fatal_error(&self) -> Option<HostError> {
+        promise_set::lock(&self.promise_manager).fatal_error.clone()
+    }
+
+    /// Fails the promises left, which the CDM won't settle once it's gone.
+    pub fn fail(&self, error: HostError) {
+        promise_set::lock(&self.promise_manager).fail(error);
+    }
+
     pub fn set_event_sender(&mut self, sender: Sender<SessionEvent>) {
         self.event_sender = Some(sender);
     }
 
-    pub fn timer_iter(&mut self) -> TryIter<Timer> {
+    pub fn set_storage(&mut self, storage: FileStorage) {
+        self.storage = Some(storage);
+    }
+
+    pub fn timer_iter(&mut self) -> TryIter<'_, Timer> {
         self.timer_manager.try_iter()
     }
+
+    /// Opens a file for a `FileIO`, which has it to itself until it closes it.
+    pub(crate) fn open_file(&mut self, name: &str) -> FileStatus {
+        if self.storage.is_none() || !FileStorage::is_valid_name(name) {
+            FileStatus::Error
+        } else if self.open_files.insert(name.to_string()) {
+            FileStatus::Success
+        } else {
+            FileStatus::InUse
+        }
+    }
+
+    pub(crate) fn close_file(&mut self, name: &str) {
+        self.open_files.remove(name);
+    }
+
+    pub(crate) fn read_file(&self, name: &str) -> Option<Vec<u8>> {
+        self.storage.as_ref()?.read(name).ok()
+    }
+
+    pub(crate) fn write_file(&self, name: &str, data: &[u8]) -> FileStatus {
+        match self.storage {
+            Some(ref storage) if storage.write(name, data).is_ok() => FileStatus::Success,
+            _ => FileStatus::Error,
+        }
+    }
+
+    pub(crate) fn queue_file_completion(&mut self, completion: FileCompletion) {
+        self.file_completions.push_back(completion);
+    }
+
+    /// Drops the completions of a closed `FileIO`, whose client doesn't
+    /// expect them anymore.
+    pub(crate) fn cancel_file_completions(&mut self, file_io: *mut FileIO) {
+        self.file_completions
+            .retain(|completion| completion.file_io() != file_io);
+    }
+
+    pub(crate) fn next_file_completion(&mut self) -> Option<FileCompletion> {
+        self.file_completions.pop_front()
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_unknown_cdm_values() {
+    use crate::decryption::Status;
+    use crate::types::KeyStatus;
+    use std::convert::TryFrom;
+    use std::sync::mpsc::channel;
+
+    assert!(matches!(KeyStatus::try_from(6), Ok(KeyStatus::Released)));
+    assert_eq!(KeyStatus::try_from(7).unwrap_err(), 7);
+    assert!(matches!(Status::from_cdm(1000), Status::Unknown(1000)));
+
+    let mut host = Host::default();
+    let (sender, receiver) = channel();
+    host.set_event_sender(sender);
+    let this = &mut host as *mut Host;
+    let session_id = b"session\0".as_ptr() as *const c_char;
+    let key_id = [0x11; 16];
+    let key_info = CDMKeyInformation {
+        key_id: key_id.as_ptr(),
+        key_id_size: 16,
+        status: u32::MAX,
+        system_code: 0,
+    };
+    on_session_message(this, session_id, 7, 42, b"message".as_ptr(), 7);
+    on_session_keys_change(this, session_id, 7, false, &key_info, 1);
+    on_reject(this, 1, 9, 0, b"rejected\0".as_ptr() as *const c_char, 8);
+
+    match receiver.try_recv().unwrap().data {
+        SessionEventType::Message(message) => {
+            assert_eq!(message.message_type, MessageType::Unknown(42));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    match receiver.try_recv().unwrap().data {
+        SessionEventType::KeysChange(change) => {
+            let status = change.keys_info[0].status;
+            assert!(matches!(status, KeyStatus::Unknown(u32::MAX)));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    match host.get_future(1).await {
+        PromiseResult::Rejected(info) => assert!(matches!(info.exception, Exception::Unknown(9))),
+        result => panic!("unexpected result {:?}", result),
+    }
 }
 
-impl Drop for Host {
-    fn drop(&mut self) {
-        if self.pointer != ptr::null_mut() {
-            unsafe {
-                DeinitializeHost(self.pointer);
-            }
+#[cfg(test)]
+#[tokio::test]
+async fn test_callback_panic() {
+    let mut host = Host::default();
+    let this = &mut host as *mut Host;
+    // Poisons the promise manager on the way.
+    guarded(this, "on_resolve", (), || {
+        let _manager = host.promise_manager.lock().unwrap();
+        panic!("callback failure");
+    });
+
+    match host.fatal_error() {
+        Some(HostError::CallbackPanicked { callback, message }) => {
+            assert_eq!(callback, "on_resolve");
+            assert_eq!(message, "callback failure");
         }
+        error => panic!("unexpected error {:?}", error),
     }
+    assert!(matches!(
+        host.get_future(1).await,
+        PromiseResult::Failed(HostError::CallbackPanicked { .. })
+    ));
 }
 
-pub struct RemoteHost(*mut c_void);
+#[cfg(test)]
+#[tokio::test]
+async fn test_sized_strings() {
+    use std::sync::mpsc::channel;
+
+    let mut host = Host::default();
+    let (sender, receiver) = channel();
+    host.set_event_sender(sender);
+    let this = &mut host as *mut Host;
+    // Neither NUL-terminated nor UTF-8.
+    let buffer = b"id\xff-trailing message-trailing";
+    let session_id = buffer.as_ptr() as *const c_char;
+    on_session_message(this, session_id, 3, 0, buffer[13..].as_ptr(), 7);
+    on_resolve_new_session(this, 1, session_id, 3);
+    on_resolve_new_session(this, 2, ptr::null(), 0);
+    on_reject(this, 3, 0, 0, buffer[13..].as_ptr() as *const c_char, 7);
 
-impl Drop for RemoteHost {
-    fn drop(&mut self) {
-        if self.0 != ptr::null_mut() {
-            unsafe {
-                DeinitializeHost(self.0);
-            }
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.session_id.as_bytes(), b"id\xff");
+    match event.data {
+        SessionEventType::Message(message) => assert_eq!(message.content, b"message"),
+        event => panic!("unexpected event {:?}", event),
+    }
+    match host.get_future(1).await {
+        PromiseResult::Resolved(PromiseResultData::NewSession(id)) => {
+            assert_eq!(id.to_string(), "id\\xff")
         }
+        result => panic!("unexpected result {:?}", result),
+    }
+    match host.get_future(2).await {
+        PromiseResult::Resolved(PromiseResultData::NewSession(id)) => assert!(id.is_empty()),
+        result => panic!("unexpected result {:?}", result),
+    }
+    match host.get_future(3).await {
+        PromiseResult::Rejected(info) => assert_eq!(info.error_message, "message"),
+        result => panic!("unexpected result {:?}", result),
     }
 }
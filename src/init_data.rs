@@ -0,0 +1,136 @@
+use crate::base64;
+use crate::pssh::{PsshBox, WidevinePsshData};
+use crate::types::InitDataType;
+use serde_json::{json, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InitDataError {
+    InvalidJson,
+    MissingKeyIds,
+    InvalidKeyId(String),
+    EmptyKeyId,
+}
+
+/// Init data passed to `WidevineAPI::create_session`, tagged with its
+/// format so the matching `InitDataType` is always used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InitData {
+    /// One or more concatenated `pssh` boxes.
+    Cenc(Vec<u8>),
+    KeyIds(KeyIds),
+    /// A single raw key ID, as found in a WebM `ContentEncKeyID` element.
+    WebM(Vec<u8>),
+}
+
+impl InitData {
+    pub fn webm(key_id: &[u8]) -> Self {
+        InitData::WebM(key_id.to_vec())
+    }
+
+    pub fn parse(init_data_type: InitDataType, data: &[u8]) -> Result<Self, InitDataError> {
+        match init_data_type {
+            InitDataType::Cenc => Ok(InitData::Cenc(data.to_vec())),
+            InitDataType::KeyIds => Ok(InitData::KeyIds(KeyIds::parse(data)?)),
+            InitDataType::WebM if data.is_empty() => Err(InitDataError::EmptyKeyId),
+            InitDataType::WebM => Ok(InitData::WebM(data.to_vec())),
+        }
+    }
+
+    pub fn init_data_type(&self) -> InitDataType {
+        match self {
+            InitData::Cenc(_) => InitDataType::Cenc,
+            InitData::KeyIds(_) => InitDataType::KeyIds,
+            InitData::WebM(_) => InitDataType::WebM,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            InitData::Cenc(data) | InitData::WebM(data) => data.clone(),
+            InitData::KeyIds(key_ids) => key_ids.to_json().into_bytes(),
+        }
+    }
+}
+
+impl From<PsshBox> for InitData {
+    fn from(pssh: PsshBox) -> Self {
+        InitData::Cenc(pssh.to_bytes())
+    }
+}
+
+impl From<&WidevinePsshData> for InitData {
+    fn from(data: &WidevinePsshData) -> Self {
+        InitData::Cenc(data.to_init_data())
+    }
+}
+
+impl From<KeyIds> for InitData {
+    fn from(key_ids: KeyIds) -> Self {
+        InitData::KeyIds(key_ids)
+    }
+}
+
+/// The W3C `keyids` init data format: `{"kids":["<base64url key id>", ...]}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyIds {
+    pub key_ids: Vec<Vec<u8>>,
+}
+
+impl KeyIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key_id(mut self, key_id: &[u8]) -> Self {
+        self.key_ids.push(key_id.to_vec());
+        self
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, InitDataError> {
+        let json: Value = serde_json::from_slice(data).map_err(|_| InitDataError::InvalidJson)?;
+        let kids = json
+            .get("kids")
+            .and_then(Value::as_array)
+            .ok_or(InitDataError::MissingKeyIds)?;
+
+        let mut key_ids = Vec::with_capacity(kids.len());
+        for kid in kids {
+            let encoded = kid.as_str().ok_or(InitDataError::InvalidJson)?;
+            let key_id = base64::decode(encoded)
+                .map_err(|_| InitDataError::InvalidKeyId(encoded.to_string()))?;
+            if key_id.is_empty() {
+                return Err(InitDataError::EmptyKeyId);
+            }
+            key_ids.push(key_id);
+        }
+        if key_ids.is_empty() {
+            return Err(InitDataError::MissingKeyIds);
+        }
+        Ok(Self { key_ids })
+    }
+
+    pub fn to_json(&self) -> String {
+        let kids: Vec<String> = self
+            .key_ids
+            .iter()
+            .map(|key_id| base64::encode_url(key_id))
+            .collect();
+        json!({ "kids": kids }).to_string()
+    }
+}
+
+#[test]
+fn test_key_ids_round_trip() {
+    let key_ids = KeyIds::new()
+        .with_key_id(&[0xfb; 16])
+        .with_key_id(&[0x01; 16]);
+    let json = key_ids.to_json();
+    assert_eq!(
+        json,
+        r#"{"kids":["-_v7-_v7-_v7-_v7-_v7-w","AQEBAQEBAQEBAQEBAQEBAQ"]}"#
+    );
+
+    let init_data = InitData::parse(InitDataType::KeyIds, json.as_bytes()).unwrap();
+    assert_eq!(init_data, InitData::KeyIds(key_ids));
+    assert!(KeyIds::parse(br#"{"kids":[]}"#).is_err());
+}
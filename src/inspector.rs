@@ -0,0 +1,507 @@
+use crate::base64;
+use crate::protobuf::{DecodeError, Reader, Value};
+use crate::pssh::WidevinePsshData;
+use serde_json::{json, Value as Json};
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SignedMessageType {
+    LicenseRequest,
+    License,
+    ErrorResponse,
+    ServiceCertificateRequest,
+    ServiceCertificate,
+    Unknown(u32),
+}
+
+impl From<u32> for SignedMessageType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => SignedMessageType::LicenseRequest,
+            2 => SignedMessageType::License,
+            3 => SignedMessageType::ErrorResponse,
+            4 => SignedMessageType::ServiceCertificateRequest,
+            5 => SignedMessageType::ServiceCertificate,
+            _ => SignedMessageType::Unknown(value),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RequestType {
+    New,
+    Renewal,
+    Release,
+    Unknown(u32),
+}
+
+impl From<u32> for RequestType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => RequestType::New,
+            2 => RequestType::Renewal,
+            3 => RequestType::Release,
+            _ => RequestType::Unknown(value),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LicenseType {
+    Streaming,
+    Offline,
+    Automatic,
+    Unknown(u32),
+}
+
+impl From<u32> for LicenseType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => LicenseType::Streaming,
+            2 => LicenseType::Offline,
+            3 => LicenseType::Automatic,
+            _ => LicenseType::Unknown(value),
+        }
+    }
+}
+
+/// `LicenseRequest.content_id`: what the license is requested for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContentId {
+    WidevinePsshData {
+        pssh_data: Vec<Vec<u8>>,
+        license_type: Option<LicenseType>,
+    },
+    WebmKeyId {
+        header: Vec<u8>,
+        license_type: Option<LicenseType>,
+    },
+    /// Renewals and releases refer to the license being renewed.
+    ExistingLicense {
+        request_id: Vec<u8>,
+        session_id: Vec<u8>,
+        license_type: Option<LicenseType>,
+    },
+    InitData {
+        init_data_type: Option<u32>,
+        init_data: Vec<u8>,
+        license_type: Option<LicenseType>,
+    },
+}
+
+impl ContentId {
+    fn decode(data: &[u8]) -> Result<Option<Self>, DecodeError> {
+        for field in Reader::new(data) {
+            let (number, value) = field?;
+            let message = match value.as_bytes() {
+                Some(message) => message,
+                None => continue,
+            };
+            let fields = Reader::new(message).collect::<Result<Vec<_>, _>>()?;
+            let content_id = match number {
+                1 => ContentId::WidevinePsshData {
+                    pssh_data: find_all_bytes(&fields, 1),
+                    license_type: find_u32(&fields, 2).map(LicenseType::from),
+                },
+                2 => ContentId::WebmKeyId {
+                    header: find_bytes(&fields, 1),
+                    license_type: find_u32(&fields, 2).map(LicenseType::from),
+                },
+                3 => {
+                    let license_id = fields
+                        .iter()
+                        .find(|(number, _)| *number == 1)
+                        .and_then(|(_, value)| value.as_bytes())
+                        .unwrap_or_default();
+                    let license_id = Reader::new(license_id).collect::<Result<Vec<_>, _>>()?;
+                    ContentId::ExistingLicense {
+                        request_id: find_bytes(&license_id, 1),
+                        session_id: find_bytes(&license_id, 2),
+                        license_type: find_u32(&license_id, 4).map(LicenseType::from),
+                    }
+                }
+                4 => ContentId::InitData {
+                    init_data_type: find_u32(&fields, 1),
+                    init_data: find_bytes(&fields, 2),
+                    license_type: find_u32(&fields, 3).map(LicenseType::from),
+                },
+                _ => continue,
+            };
+            return Ok(Some(content_id));
+        }
+        Ok(None)
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            ContentId::WidevinePsshData {
+                pssh_data,
+                license_type,
+            } => {
+                let pssh_data: Vec<Json> = pssh_data
+                    .iter()
+                    .map(|data| match WidevinePsshData::decode(data) {
+                        Ok(decoded) => json!({
+                            "key_ids": decoded.key_ids.iter().map(|id| to_hex(id)).collect::<Vec<_>>(),
+                            "content_id": decoded.content_id.as_deref().map(base64::encode),
+                            "provider": decoded.provider,
+                        }),
+                        Err(_) => json!(base64::encode(data)),
+                    })
+                    .collect();
+                json!({
+                    "type": "widevine_pssh_data",
+                    "license_type": license_type.map(license_type_name),
+                    "pssh_data": pssh_data,
+                })
+            }
+            ContentId::WebmKeyId {
+                header,
+                license_type,
+            } => json!({
+                "type": "webm_key_id",
+                "license_type": license_type.map(license_type_name),
+                "key_id": to_hex(header),
+            }),
+            ContentId::ExistingLicense {
+                request_id,
+                session_id,
+                license_type,
+            } => json!({
+                "type": "existing_license",
+                "license_type": license_type.map(license_type_name),
+                "request_id": to_hex(request_id),
+                "session_id": to_hex(session_id),
+            }),
+            ContentId::InitData {
+                init_data_type,
+                init_data,
+                license_type,
+            } => json!({
+                "type": "init_data",
+                "license_type": license_type.map(license_type_name),
+                "init_data_type": init_data_type,
+                "init_data": base64::encode(init_data),
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LicenseRequestInfo {
+    pub request_type: Option<RequestType>,
+    pub content_id: Option<ContentId>,
+    /// 20 for version 2.0, 21 for 2.1...
+    pub protocol_version: Option<u32>,
+    /// Seconds since the epoch.
+    pub request_time: Option<i64>,
+    pub has_client_id: bool,
+    pub has_encrypted_client_id: bool,
+}
+
+impl LicenseRequestInfo {
+    fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut info = Self {
+            request_type: None,
+            content_id: None,
+            protocol_version: None,
+            request_time: None,
+            has_client_id: false,
+            has_encrypted_client_id: false,
+        };
+        for field in Reader::new(data) {
+            let (number, value) = field?;
+            match number {
+                1 => info.has_client_id = true,
+                2 => info.content_id = ContentId::decode(value.as_bytes().unwrap_or_default())?,
+                3 => info.request_type = value.as_u32().map(RequestType::from),
+                4 => info.request_time = value.as_i64(),
+                6 => info.protocol_version = value.as_u32(),
+                8 => info.has_encrypted_client_id = true,
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "request_type": self.request_type.map(|request_type| match request_type {
+                RequestType::New => "new".to_string(),
+                RequestType::Renewal => "renewal".to_string(),
+                RequestType::Release => "release".to_string(),
+                RequestType::Unknown(value) => value.to_string(),
+            }),
+            "content_id": self.content_id.as_ref().map(ContentId::to_json),
+            "protocol_version": self.protocol_version.map(|version| format!("{}.{}", version / 10, version % 10)),
+            "request_time": self.request_time,
+            "client_id": if self.has_encrypted_client_id {
+                "encrypted"
+            } else if self.has_client_id {
+                "clear"
+            } else {
+                "none"
+            },
+        })
+    }
+}
+
+/// The non-secret `Policy` of a license response, plus its start time.
+/// Durations are `None` when absent or zero, which means unlimited.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LicensePolicy {
+    pub can_play: bool,
+    pub can_persist: bool,
+    pub can_renew: bool,
+    pub rental_duration: Option<Duration>,
+    pub playback_duration: Option<Duration>,
+    pub license_duration: Option<Duration>,
+    pub renewal_recovery_duration: Option<Duration>,
+    pub renewal_server_url: Option<String>,
+    pub renewal_delay: Option<Duration>,
+    pub renewal_retry_interval: Option<Duration>,
+    /// Seconds since the epoch.
+    pub license_start_time: Option<i64>,
+}
+
+impl LicensePolicy {
+    /// Decodes the policy of a license response, with or without its
+    /// `SignedMessage` wrapper.
+    pub fn decode(response: &[u8]) -> Result<Self, DecodeError> {
+        let fields = Reader::new(response).collect::<Result<Vec<_>, _>>()?;
+        if let Some((1, Value::Varint(_))) = fields.first() {
+            return Self::decode(&find_bytes(&fields, 2));
+        }
+
+        let mut policy = Self {
+            license_start_time: fields
+                .iter()
+                .find(|(number, _)| *number == 4)
+                .and_then(|(_, value)| value.as_i64()),
+            ..Self::default()
+        };
+        for field in Reader::new(&find_bytes(&fields, 2)) {
+            let (number, value) = field?;
+            let duration = value
+                .as_i64()
+                .filter(|seconds| *seconds > 0)
+                .map(|seconds| Duration::from_secs(seconds as u64));
+            match number {
+                1 => policy.can_play = value.as_u64() == Some(1),
+                2 => policy.can_persist = value.as_u64() == Some(1),
+                3 => policy.can_renew = value.as_u64() == Some(1),
+                4 => policy.rental_duration = duration,
+                5 => policy.playback_duration = duration,
+                6 => policy.license_duration = duration,
+                7 => policy.renewal_recovery_duration = duration,
+                8 => policy.renewal_server_url = Some(value.as_string()?),
+                9 => policy.renewal_delay = duration,
+                10 => policy.renewal_retry_interval = duration,
+                _ => {}
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn to_json(&self) -> Json {
+        let seconds = |duration: Option<Duration>| duration.map(|duration| duration.as_secs());
+        json!({
+            "can_play": self.can_play,
+            "can_persist": self.can_persist,
+            "can_renew": self.can_renew,
+            "rental_duration": seconds(self.rental_duration),
+            "playback_duration": seconds(self.playback_duration),
+            "license_duration": seconds(self.license_duration),
+            "renewal_recovery_duration": seconds(self.renewal_recovery_duration),
+            "renewal_server_url": self.renewal_server_url,
+            "renewal_delay": seconds(self.renewal_delay),
+            "renewal_retry_interval": seconds(self.renewal_retry_interval),
+            "license_start_time": self.license_start_time,
+        })
+    }
+}
+
+/// The outer `SignedMessage` of a CDM message or license server response,
+/// for diagnostics. Signatures, session keys and client identification are
+/// only reported as present, never decoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedMessageInfo {
+    pub message_type: Option<SignedMessageType>,
+    pub is_signed: bool,
+    /// Set for `LicenseRequest` messages.
+    pub license_request: Option<LicenseRequestInfo>,
+    /// Set for `License` messages.
+    pub license_policy: Option<LicensePolicy>,
+}
+
+impl SignedMessageInfo {
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut message_type = None;
+        let mut message = None;
+        let mut is_signed = false;
+        for field in Reader::new(data) {
+            let (number, value) = field?;
+            match number {
+                1 => message_type = value.as_u32().map(SignedMessageType::from),
+                2 => message = value.as_bytes(),
+                3 => is_signed = true,
+                _ => {}
+            }
+        }
+
+        let mut info = Self {
+            message_type,
+            is_signed,
+            license_request: None,
+            license_policy: None,
+        };
+        match (message_type, message) {
+            (Some(SignedMessageType::LicenseRequest), Some(message)) => {
+                info.license_request = Some(LicenseRequestInfo::decode(message)?)
+            }
+            (Some(SignedMessageType::License), Some(message)) => {
+                info.license_policy = Some(LicensePolicy::decode(message)?)
+            }
+            _ => {}
+        }
+        Ok(info)
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "message_type": self.message_type.map(|message_type| match message_type {
+                SignedMessageType::LicenseRequest => "license_request".to_string(),
+                SignedMessageType::License => "license".to_string(),
+                SignedMessageType::ErrorResponse => "error_response".to_string(),
+                SignedMessageType::ServiceCertificateRequest => "service_certificate_request".to_string(),
+                SignedMessageType::ServiceCertificate => "service_certificate".to_string(),
+                SignedMessageType::Unknown(value) => value.to_string(),
+            }),
+            "signed": self.is_signed,
+            "license_request": self.license_request.as_ref().map(LicenseRequestInfo::to_json),
+            "license_policy": self.license_policy.as_ref().map(LicensePolicy::to_json),
+        })
+    }
+
+    pub fn to_pretty_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_json()).unwrap()
+    }
+}
+
+fn license_type_name(license_type: LicenseType) -> String {
+    match license_type {
+        LicenseType::Streaming => "streaming".to_string(),
+        LicenseType::Offline => "offline".to_string(),
+        LicenseType::Automatic => "automatic".to_string(),
+        LicenseType::Unknown(value) => value.to_string(),
+    }
+}
+
+fn find_bytes(fields: &[(u32, Value)], number: u32) -> Vec<u8> {
+    fields
+        .iter()
+        .find(|(field, _)| *field == number)
+        .and_then(|(_, value)| value.as_bytes())
+        .unwrap_or_default()
+        .to_vec()
+}
+
+fn find_all_bytes(fields: &[(u32, Value)], number: u32) -> Vec<Vec<u8>> {
+    fields
+        .iter()
+        .filter(|(field, _)| *field == number)
+        .filter_map(|(_, value)| value.as_bytes())
+        .map(|bytes| bytes.to_vec())
+        .collect()
+}
+
+fn find_u32(fields: &[(u32, Value)], number: u32) -> Option<u32> {
+    fields
+        .iter()
+        .find(|(field, _)| *field == number)
+        .and_then(|(_, value)| value.as_u32())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn test_decode_license_request() {
+    use crate::protobuf::Writer;
+
+    let pssh_data = WidevinePsshData::new(b"inspected", &[&[0x12; 16]]).encode();
+    let mut content_id = Writer::default();
+    content_id.bytes(1, &pssh_data).varint(2, 1);
+    let mut wrapped_content_id = Writer::default();
+    wrapped_content_id.bytes(1, &content_id.into_bytes());
+    let mut request = Writer::default();
+    request
+        .bytes(1, b"client identification")
+        .bytes(2, &wrapped_content_id.into_bytes())
+        .varint(3, 1)
+        .varint(4, 1_600_000_000)
+        .varint(6, 21);
+    let mut message = Writer::default();
+    message
+        .varint(1, 1)
+        .bytes(2, &request.into_bytes())
+        .bytes(3, &[0x5a; 32]);
+
+    let info = SignedMessageInfo::decode(&message.into_bytes()).unwrap();
+    assert_eq!(info.message_type, Some(SignedMessageType::LicenseRequest));
+    assert!(info.is_signed);
+    let request = info.license_request.as_ref().unwrap();
+    assert_eq!(request.request_type, Some(RequestType::New));
+    assert_eq!(request.request_time, Some(1_600_000_000));
+    assert_eq!(
+        request.content_id,
+        Some(ContentId::WidevinePsshData {
+            pssh_data: vec![pssh_data],
+            license_type: Some(LicenseType::Streaming),
+        })
+    );
+
+    let json = info.to_json();
+    assert_eq!(json["license_request"]["protocol_version"], "2.1");
+    assert_eq!(json["license_request"]["client_id"], "clear");
+    assert_eq!(
+        json["license_request"]["content_id"]["pssh_data"][0]["key_ids"][0],
+        "12121212121212121212121212121212"
+    );
+}
+
+#[test]
+fn test_decode_license_policy() {
+    use crate::protobuf::Writer;
+
+    let mut policy = Writer::default();
+    policy
+        .varint(1, 1)
+        .varint(3, 1)
+        .varint(4, 48 * 3600)
+        .varint(6, 0)
+        .bytes(8, b"https://license.example.com/renew")
+        .varint(9, 300)
+        .varint(10, 15);
+    let mut license = Writer::default();
+    license
+        .bytes(2, &policy.into_bytes())
+        .bytes(3, b"key container")
+        .varint(4, 1_600_000_000);
+    let mut message = Writer::default();
+    message.varint(1, 2).bytes(2, &license.into_bytes());
+
+    let info = SignedMessageInfo::decode(&message.into_bytes()).unwrap();
+    let policy = info.license_policy.unwrap();
+    assert!(policy.can_play && policy.can_renew && !policy.can_persist);
+    assert_eq!(policy.rental_duration, Some(Duration::from_secs(48 * 3600)));
+    assert_eq!(policy.license_duration, None);
+    assert_eq!(policy.renewal_delay, Some(Duration::from_secs(300)));
+    assert_eq!(policy.renewal_retry_interval, Some(Duration::from_secs(15)));
+    assert_eq!(
+        policy.renewal_server_url.as_deref(),
+        Some("https://license.example.com/renew")
+    );
+    assert_eq!(policy.license_start_time, Some(1_600_000_000));
+}
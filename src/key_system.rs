@@ -0,0 +1,71 @@
+use crate::decryption::{InputBuffer, Status};
+use crate::init_data::InitData;
+use crate::types::{SessionEvent, SessionId, SessionType};
+use crate::{CdmError, CreateSessionError, WidevineAPI};
+use async_trait::async_trait;
+use std::sync::mpsc::Sender;
+
+/// The session and decryption operations shared by the key systems, so
+/// players can switch between Widevine and ClearKey.
+#[async_trait(?Send)]
+pub trait KeySystem {
+    /// The EME key system string, e.g. `com.widevine.alpha`.
+    fn key_system(&self) -> &str;
+
+    /// Creates a session and generates its license request, which is sent
+    /// to `sender` as a `LicenseRequest` message along with the session's
+    /// other events.
+    async fn create_session(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+        sender: Sender<SessionEvent>,
+    ) -> Result<SessionId, CreateSessionError>;
+
+    /// Applies a license server response to a session.
+    async fn update_session(
+        &mut self,
+        session_id: &SessionId,
+        response: &[u8],
+    ) -> Result<(), CdmError>;
+
+    async fn close_session(&mut self, session_id: &SessionId) -> Result<(), CdmError>;
+
+    fn decrypt(&mut self, input_buffer: InputBuffer) -> Result<Vec<u8>, Status>;
+}
+
+pub const WIDEVINE_KEY_SYSTEM: &str = "com.widevine.alpha";
+/// Chromium's test CDM, implementing ClearKey behind the CDM interface.
+pub const EXTERNAL_CLEARKEY_KEY_SYSTEM: &str = "org.chromium.externalclearkey";
+
+#[async_trait(?Send)]
+impl KeySystem for WidevineAPI {
+    fn key_system(&self) -> &str {
+        WidevineAPI::key_system(self)
+    }
+
+    async fn create_session(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+        sender: Sender<SessionEvent>,
+    ) -> Result<SessionId, CreateSessionError> {
+        WidevineAPI::create_session(self, session_type, init_data, sender).await
+    }
+
+    async fn update_session(
+        &mut self,
+        session_id: &SessionId,
+        response: &[u8],
+    ) -> Result<(), CdmError> {
+        WidevineAPI::update_session(self, session_id, response).await
+    }
+
+    async fn close_session(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        WidevineAPI::close_session(self, session_id).await
+    }
+
+    fn decrypt(&mut self, input_buffer: InputBuffer) -> Result<Vec<u8>, Status> {
+        WidevineAPI::decrypt(self, input_buffer)
+    }
+}
@@ -0,0 +1,110 @@
+// Checks the layouts asserted by the `check_type!` and `check_offset!` calls
+// of the Rust side against content_decryption_module.h. Built and run by
+// `abi::test_layouts_match_headers`; a mismatch either fails to compile or
+// makes the program exit with an error.
+
+#include <cstddef>
+#include <cstdio>
+#include <cstring>
+
+#include "content_decryption_module.h"
+
+// Alignments go with `CHECK_TYPE`'s sizes, and `INT64_ALIGN` is the alignment
+// of `int64_t` fields, which is only 4 bytes on some 32-bit targets.
+#define CHECK_LAYOUT(type, size_32, size_64, align_32, align_64)      \
+  CHECK_TYPE(type, size_32, size_64);                                 \
+  static_assert((sizeof(void*) == 4 && alignof(type) == align_32) ||  \
+                    (sizeof(void*) == 8 && alignof(type) == align_64), \
+                #type " alignment mismatch")
+#define CHECK_OFFSET(type, field, offset_32, offset_64)                      \
+  static_assert((sizeof(void*) == 4 && offsetof(type, field) == offset_32) || \
+                    (sizeof(void*) == 8 && offsetof(type, field) == offset_64), \
+                #type "::" #field " offset mismatch")
+
+struct Int64Field {
+  uint8_t padding;
+  int64_t value;
+};
+const size_t INT64_ALIGN = offsetof(Int64Field, value);
+
+CHECK_TYPE(cdm::EncryptionScheme, 4, 4);
+CHECK_TYPE(cdm::SessionType, 4, 4);
+CHECK_TYPE(cdm::InitDataType, 4, 4);
+CHECK_TYPE(cdm::FileIOClient::Status, 4, 4);
+
+CHECK_LAYOUT(cdm::SubsampleEntry, 8, 8, 4, 4);
+CHECK_OFFSET(cdm::SubsampleEntry, clear_bytes, 0, 0);
+CHECK_OFFSET(cdm::SubsampleEntry, cipher_bytes, 4, 4);
+
+CHECK_LAYOUT(cdm::Pattern, 8, 8, 4, 4);
+CHECK_OFFSET(cdm::Pattern, crypt_byte_block, 0, 0);
+CHECK_OFFSET(cdm::Pattern, skip_byte_block, 4, 4);
+
+CHECK_LAYOUT(cdm::InputBuffer_2, 64, 80, INT64_ALIGN, 8);
+CHECK_OFFSET(cdm::InputBuffer_2, data, 0, 0);
+CHECK_OFFSET(cdm::InputBuffer_2, data_size, 4, 8);
+CHECK_OFFSET(cdm::InputBuffer_2, encryption_scheme, 8, 12);
+CHECK_OFFSET(cdm::InputBuffer_2, key_id, 12, 16);
+CHECK_OFFSET(cdm::InputBuffer_2, key_id_size, 16, 24);
+CHECK_OFFSET(cdm::InputBuffer_2, iv, 24, 32);
+CHECK_OFFSET(cdm::InputBuffer_2, iv_size, 28, 40);
+CHECK_OFFSET(cdm::InputBuffer_2, subsamples, 36, 48);
+CHECK_OFFSET(cdm::InputBuffer_2, num_subsamples, 40, 56);
+CHECK_OFFSET(cdm::InputBuffer_2, pattern, 48, 64);
+CHECK_OFFSET(cdm::InputBuffer_2, timestamp, 56, 72);
+
+CHECK_LAYOUT(cdm::KeyInformation, 16, 24, 4, 8);
+CHECK_OFFSET(cdm::KeyInformation, key_id, 0, 0);
+CHECK_OFFSET(cdm::KeyInformation, key_id_size, 4, 8);
+CHECK_OFFSET(cdm::KeyInformation, status, 8, 12);
+CHECK_OFFSET(cdm::KeyInformation, system_code, 12, 16);
+
+// The Itanium C++ ABI stores a pointer to a virtual method as one plus the
+// offset of its slot in the vtable.
+template <typename Method>
+size_t Slot(Method method) {
+  ptrdiff_t value;
+  std::memcpy(&value, &method, sizeof(value));
+  return (value - 1) / sizeof(void*);
+}
+
+int failures = 0;
+
+// Slots are the offsets the Rust vtables check, divided by the pointer size.
+// The destructors, whose address can't be taken, come after the last method.
+#define CHECK_SLOT(type, method, slot)                              \
+  if (Slot(&type::method) != slot) {                                \
+    std::fprintf(stderr, #type "::" #method " is in slot %zu\n",    \
+                 Slot(&type::method));                              \
+    failures++;                                                     \
+  }
+
+int main() {
+  CHECK_SLOT(cdm::ContentDecryptionModule_10, Initialize, 0);
+  CHECK_SLOT(cdm::ContentDecryptionModule_10, SetServerCertificate, 2);
+  CHECK_SLOT(cdm::ContentDecryptionModule_10, TimerExpired, 8);
+  CHECK_SLOT(cdm::ContentDecryptionModule_10, Decrypt, 9);
+  CHECK_SLOT(cdm::ContentDecryptionModule_10, OnStorageId, 18);
+  CHECK_SLOT(cdm::ContentDecryptionModule_10, Destroy, 19);
+
+  CHECK_SLOT(cdm::Host_10, Allocate, 0);
+  CHECK_SLOT(cdm::Host_10, OnInitialized, 3);
+  CHECK_SLOT(cdm::Host_10, OnRejectPromise, 7);
+  CHECK_SLOT(cdm::Host_10, OnExpirationChange, 10);
+  CHECK_SLOT(cdm::Host_10, CreateFileIO, 16);
+  CHECK_SLOT(cdm::Host_10, RequestStorageId, 17);
+
+  CHECK_SLOT(cdm::Buffer, Destroy, 0);
+  CHECK_SLOT(cdm::Buffer, Size, 4);
+
+  CHECK_SLOT(cdm::DecryptedBlock, SetDecryptedBuffer, 0);
+  CHECK_SLOT(cdm::DecryptedBlock, Timestamp, 3);
+
+  CHECK_SLOT(cdm::FileIO, Open, 0);
+  CHECK_SLOT(cdm::FileIO, Close, 3);
+
+  CHECK_SLOT(cdm::FileIOClient, OnOpenComplete, 0);
+  CHECK_SLOT(cdm::FileIOClient, OnWriteComplete, 2);
+
+  return failures == 0 ? 0 : 1;
+}
@@ -1,134 +1,663 @@
+#[macro_use]
+mod abi;
+mod base64;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod buffer;
+mod byte_reader;
 mod cdm;
+pub mod certificate;
+pub mod clearkey;
+pub mod dash;
 pub mod decryption;
+mod expiry;
+mod file_io;
+pub mod hls;
 mod host;
+pub mod init_data;
+pub mod inspector;
+pub mod key_system;
 mod library;
+pub mod license;
+pub mod mp4;
+pub mod offline;
 mod promise_set;
-mod remote_buffer;
+mod protobuf;
+pub mod provisioning;
+pub mod pssh;
+pub mod renewal;
+pub mod session;
+mod storage;
+mod timeout;
 mod timer;
+pub mod ts;
 pub mod types;
+pub mod webm;
 
-use cdm::CDM;
+use cdm::{Cdm, CreationError};
 use decryption::{InputBuffer, Status};
 use host::Host;
+use init_data::InitData;
+use key_system::WIDEVINE_KEY_SYSTEM;
 use library::Library;
 use promise_set::{PromiseResultData, PromiseSet, RejectionInfo, INITIALIZED_PROMISE_ID};
+use provisioning::ProvisioningState;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
-use types::{InitDataType, SessionEvent, SessionType};
+use std::time::Duration;
+use storage::FileStorage;
+use types::{SessionEvent, SessionId, SessionType};
+
+#[derive(Clone, Debug)]
+pub enum InitializeError {
+    LibraryUnavailable,
+    /// The module doesn't support the key system.
+    KeySystemRejected(String),
+    CDMCreationFailed,
+}
+
+/// A failure of the host after which the CDM can't be used anymore, returned
+/// by every call from then on.
+#[derive(Clone, Debug)]
+pub enum HostError {
+    /// A callback from the CDM panicked. The panic was caught rather than
+    /// unwinding into the CDM.
+    CallbackPanicked {
+        callback: &'static str,
+        message: String,
+    },
+    /// The `WidevineAPI` was dropped with promises still pending.
+    Dropped,
+}
+
+#[derive(Clone, Debug)]
+pub enum CdmError {
+    Rejected(RejectionInfo),
+    HostFailed(HostError),
+    /// The CDM didn't settle the operation's promise in time.
+    TimedOut(Operation),
+}
+
+impl From<HostError> for CdmError {
+    fn from(error: HostError) -> Self {
+        CdmError::HostFailed(error)
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum InitializeCDMError {
     Failed,
     Rejected(RejectionInfo),
+    HostFailed(HostError),
+    TimedOut,
 }
 
 #[derive(Clone, Debug)]
 pub enum CreateSessionError {
     Failed, // TODO: this is a result of badly typing the promise system
     Rejected(RejectionInfo),
+    HostFailed(HostError),
+    TimedOut,
+}
+
+#[derive(Clone, Debug)]
+pub enum LoadSessionError {
+    NotFound,
+    Failed,
+    Rejected(RejectionInfo),
+    HostFailed(HostError),
+    TimedOut,
+}
+
+/// The calls to the CDM that are answered through a promise.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Initialize,
+    SetServerCertificate,
+    CreateSession,
+    LoadSession,
+    UpdateSession,
+    CloseSession,
+    RemoveSession,
+}
+
+const DEFAULT_PROMISE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the CDM to settle the promise of each operation,
+/// `None` meaning forever.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PromiseTimeouts {
+    pub initialize: Option<Duration>,
+    pub set_server_certificate: Option<Duration>,
+    pub create_session: Option<Duration>,
+    pub load_session: Option<Duration>,
+    pub update_session: Option<Duration>,
+    pub close_session: Option<Duration>,
+    pub remove_session: Option<Duration>,
+}
+
+impl Default for PromiseTimeouts {
+    fn default() -> Self {
+        let timeout = Some(DEFAULT_PROMISE_TIMEOUT);
+        Self {
+            initialize: timeout,
+            set_server_certificate: timeout,
+            create_session: timeout,
+            load_session: timeout,
+            update_session: timeout,
+            close_session: timeout,
+            remove_session: timeout,
+        }
+    }
+}
+
+impl PromiseTimeouts {
+    pub fn get(&self, operation: Operation) -> Option<Duration> {
+        match operation {
+            Operation::Initialize => self.initialize,
+            Operation::SetServerCertificate => self.set_server_certificate,
+            Operation::CreateSession => self.create_session,
+            Operation::LoadSession => self.load_session,
+            Operation::UpdateSession => self.update_session,
+            Operation::CloseSession => self.close_session,
+            Operation::RemoveSession => self.remove_session,
+        }
+    }
+}
+
+/// A promise the CDM hasn't settled, as listed by
+/// `WidevineAPI::unsettled_promises`.
+#[derive(Clone, Debug)]
+pub struct UnsettledPromise {
+    pub id: usize,
+    pub operation: Operation,
+    /// Time since the promise was handed to the CDM.
+    pub age: Duration,
+    /// Whether nobody waits for it anymore, e.g. after a timeout.
+    pub cancelled: bool,
 }
 
 pub struct WidevineAPI {
-    cdm: CDM,
+    cdm: Cdm,
     host: Box<Host>,
     #[allow(dead_code)]
     library: Library,
+    key_system: String,
     promise_set: PromiseSet,
+    timeouts: PromiseTimeouts,
+    provisioning_state: ProvisioningState,
 }
 
 impl WidevineAPI {
-    pub fn initialize() -> Result<Self, ()> {
-        let library = Library::initialize()?;
-        let host = Host::default().initialized()?;
-        let cdm = CDM::initialize(&library, &host)?;
+    /// Loads the CDM from the platform's `widevinecdm` library, like
+    /// `libwidevinecdm.so` on Linux, looked up the way `dlopen` does.
+    pub fn initialize() -> Result<Self, InitializeError> {
+        Self::initialize_with_library(format!("{}widevinecdm{}", DLL_PREFIX, DLL_SUFFIX))
+    }
+
+    /// Loads the CDM from a specific shared library.
+    pub fn initialize_with_library<P: AsRef<Path>>(path: P) -> Result<Self, InitializeError> {
+        Self::initialize_with_key_system(path, WIDEVINE_KEY_SYSTEM)
+    }
+
+    /// Loads a CDM speaking the Chromium CDM interface, such as the External
+    /// ClearKey one, for a key system other than Widevine.
+    pub fn initialize_with_key_system<P: AsRef<Path>>(
+        path: P,
+        key_system: &str,
+    ) -> Result<Self, InitializeError> {
+        let library =
+            Library::initialize(path.as_ref()).map_err(|_| InitializeError::LibraryUnavailable)?;
+        let mut host = Box::new(Host::default());
+        let cdm =
+            Cdm::initialize(&library, &mut host, key_system).map_err(|error| match error {
+                CreationError::KeySystemRejected => {
+                    InitializeError::KeySystemRejected(key_system.to_string())
+                }
+                CreationError::Failed => InitializeError::CDMCreationFailed,
+            })?;
         let promise_set = PromiseSet::default();
 
         Ok(Self {
             library,
             host,
             cdm,
+            key_system: key_system.to_string(),
             promise_set,
+            timeouts: PromiseTimeouts::default(),
+            provisioning_state: ProvisioningState::default(),
         })
     }
 
+    pub fn key_system(&self) -> &str {
+        &self.key_system
+    }
+
+    /// Whether the device has been individualized, as observed by a
+    /// `SessionDriver`.
+    pub fn provisioning_state(&self) -> ProvisioningState {
+        self.provisioning_state
+    }
+
+    pub(crate) fn set_provisioning_state(&mut self, state: ProvisioningState) {
+        self.provisioning_state = state;
+    }
+
+    /// Directory backing the CDM's persistent storage, where it keeps
+    /// persistent licenses among other things. Without one, the CDM can't
+    /// open files.
+    pub fn set_storage_directory<P: Into<PathBuf>>(&mut self, directory: P) {
+        self.host.set_storage(FileStorage::new(directory.into()));
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: PromiseTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// The promises the CDM hasn't settled yet, including the ones that
+    /// timed out. They're also logged when the API is dropped.
+    pub fn unsettled_promises(&self) -> Vec<UnsettledPromise> {
+        self.host.unsettled_promises()
+    }
+
+    /// The failure that made the host unusable, if any.
+    pub fn host_error(&self) -> Option<HostError> {
+        self.host.fatal_error()
+    }
+
+    fn check_host(&self) -> Result<(), HostError> {
+        self.host_error().map_or(Ok(()), Err)
+    }
+
     pub async fn initialize_cdm(&mut self) -> Result<(), InitializeCDMError> {
+        self.check_host().map_err(InitializeCDMError::HostFailed)?;
+        self.host
+            .register_promise(INITIALIZED_PROMISE_ID, Operation::Initialize);
         self.cdm.request_initialization();
         let result = self
-            .host
-            .get_future(INITIALIZED_PROMISE_ID)
-            .await
-            .as_result();
+            .settle(Operation::Initialize, INITIALIZED_PROMISE_ID)
+            .await;
         match result {
             Ok(PromiseResultData::Initialized(true)) => Ok(()),
-            Err(info) => Err(InitializeCDMError::Rejected(info)),
+            Err(CdmError::Rejected(info)) => Err(InitializeCDMError::Rejected(info)),
+            Err(CdmError::HostFailed(error)) => Err(InitializeCDMError::HostFailed(error)),
+            Err(CdmError::TimedOut(_)) => Err(InitializeCDMError::TimedOut),
             _ => Err(InitializeCDMError::Failed),
         }
     }
 
-    pub async fn set_server_certificate(
-        &mut self,
-        certificate: &[u8],
-    ) -> Result<(), RejectionInfo> {
-        let promise_id = self.promise_set.create();
+    pub async fn set_server_certificate(&mut self, certificate: &[u8]) -> Result<(), CdmError> {
+        self.check_host()?;
+        let promise_id = self.create_promise(Operation::SetServerCertificate);
         self.cdm.set_server_certificate(promise_id, certificate);
-        self.host.get_future(promise_id).await.as_result()?;
-        self.promise_set.pop(promise_id);
+        self.settle(Operation::SetServerCertificate, promise_id)
+            .await?;
         Ok(())
     }
 
     pub async fn create_session(
         &mut self,
         session_type: SessionType,
-        init_data_type: InitDataType,
-        init_data: Vec<u8>, // TODO: using slice instead gives E0700
+        init_data: InitData,
         sender: Sender<SessionEvent>,
-    ) -> Result<String, CreateSessionError> {
-        let promise_id = self.promise_set.create();
+    ) -> Result<SessionId, CreateSessionError> {
+        self.check_host().map_err(CreateSessionError::HostFailed)?;
+        let promise_id = self.create_promise(Operation::CreateSession);
         self.host.set_event_sender(sender);
-        self.cdm
-            .create_session(promise_id, session_type, init_data_type, &init_data);
-        let result = self.host.get_future(promise_id).await.as_result();
-        self.promise_set.pop(promise_id);
+        self.cdm.create_session(
+            promise_id,
+            session_type,
+            init_data.init_data_type(),
+            &init_data.to_bytes(),
+        );
+        let result = self.settle(Operation::CreateSession, promise_id).await;
 
         match result {
             Ok(PromiseResultData::NewSession(id)) => Ok(id),
-            Err(info) => Err(CreateSessionError::Rejected(info)),
+            Err(CdmError::Rejected(info)) => Err(CreateSessionError::Rejected(info)),
+            Err(CdmError::HostFailed(error)) => Err(CreateSessionError::HostFailed(error)),
+            Err(CdmError::TimedOut(_)) => Err(CreateSessionError::TimedOut),
             _ => Err(CreateSessionError::Failed),
         }
     }
 
-    // TODO: refactor promises, lots of repeated code here
+    /// Loads a persisted session, e.g. a `PersistentLicense` one.
+    pub async fn load_session(
+        &mut self,
+        session_type: SessionType,
+        session_id: &SessionId,
+        sender: Sender<SessionEvent>,
+    ) -> Result<(), LoadSessionError> {
+        self.check_host().map_err(LoadSessionError::HostFailed)?;
+        let promise_id = self.create_promise(Operation::LoadSession);
+        self.host.set_event_sender(sender);
+        self.cdm.load_session(promise_id, session_type, session_id);
+        let result = self.settle(Operation::LoadSession, promise_id).await;
+
+        match result {
+            Ok(PromiseResultData::NewSession(ref id)) if id.is_empty() => {
+                Err(LoadSessionError::NotFound)
+            }
+            Ok(PromiseResultData::NewSession(_)) => Ok(()),
+            Err(CdmError::Rejected(info)) => Err(LoadSessionError::Rejected(info)),
+            Err(CdmError::HostFailed(error)) => Err(LoadSessionError::HostFailed(error)),
+            Err(CdmError::TimedOut(_)) => Err(LoadSessionError::TimedOut),
+            _ => Err(LoadSessionError::Failed),
+        }
+    }
+
     pub async fn update_session(
         &mut self,
-        session_id: &str,
+        session_id: &SessionId,
         response: &[u8],
-    ) -> Result<(), RejectionInfo> {
-        let promise_id = self.promise_set.create();
+    ) -> Result<(), CdmError> {
+        self.check_host()?;
+        let promise_id = self.create_promise(Operation::UpdateSession);
         self.cdm.update_session(promise_id, session_id, response);
-        let _result = self.host.get_future(promise_id).await.as_result()?;
-        self.promise_set.pop(promise_id);
+        self.settle(Operation::UpdateSession, promise_id).await?;
         Ok(())
     }
 
+    pub async fn close_session(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        self.check_host()?;
+        let promise_id = self.create_promise(Operation::CloseSession);
+        self.cdm.close_session(promise_id, session_id);
+        self.settle(Operation::CloseSession, promise_id).await?;
+        Ok(())
+    }
+
+    /// Removes the license of a session. The CDM answers with a
+    /// `LicenseRelease` message that has to be sent to the license server.
+    pub async fn remove_session(&mut self, session_id: &SessionId) -> Result<(), CdmError> {
+        self.check_host()?;
+        let promise_id = self.create_promise(Operation::RemoveSession);
+        self.cdm.remove_session(promise_id, session_id);
+        self.settle(Operation::RemoveSession, promise_id).await?;
+        Ok(())
+    }
+
+    fn create_promise(&mut self, operation: Operation) -> usize {
+        let promise_id = self.promise_set.create();
+        self.host.register_promise(promise_id, operation);
+        promise_id
+    }
+
+    /// Waits for the CDM to settle a promise, for at most the operation's
+    /// timeout. A promise that timed out is forgotten: the CDM settling it
+    /// later has no effect.
+    async fn settle(
+        &mut self,
+        operation: Operation,
+        promise_id: usize,
+    ) -> Result<PromiseResultData, CdmError> {
+        self.complete_file_operations();
+        let future = self.host.get_future(promise_id);
+        let timeout = match self.timeouts.get(operation) {
+            Some(timeout) => timeout,
+            None => return future.await.into_result(),
+        };
+        match timeout::timeout(timeout, future).await {
+            Ok(result) => result.into_result(),
+            Err(_) => {
+                log::warn!("the CDM didn't settle {:?} in {:?}", operation, timeout);
+                Err(CdmError::TimedOut(operation))
+            }
+        }
+    }
+
     pub fn decrypt(&mut self, input_buffer: InputBuffer) -> Result<Vec<u8>, Status> {
         self.cdm.decrypt(input_buffer)
     }
 
     // TODO: delete this and outsource timer management to library users?
     pub fn update(&mut self) {
+        if self.host_error().is_some() {
+            return;
+        }
         for timer in self.host.timer_iter() {
             self.cdm.timer_expired(timer);
         }
+        self.complete_file_operations();
+    }
+
+    /// Calls the CDM back with the results of its file operations, which it
+    /// expects once its calls to `FileIO` have returned. The callbacks may
+    /// start further operations, completed in turn.
+    fn complete_file_operations(&mut self) {
+        while let Some(completion) = self.host.next_file_completion() {
+            unsafe { completion.deliver() };
+        }
+    }
+}
+
+#[cfg(test)]
+impl WidevineAPI {
+    /// Sets the failures the mock CDM injects, see its `Script`.
+    pub(crate) fn set_mock_script(&mut self, script: serde_json::Value) {
+        type SetScriptFunc = unsafe extern "C" fn(*mut std::ffi::c_void, *const u8, u32) -> bool;
+        let set_script = unsafe {
+            self.library
+                .function::<SetScriptFunc>(b"MockCdmSetScript\0")
+        }
+        .expect("not the mock CDM");
+        let script = script.to_string();
+        let valid = unsafe { set_script(self.cdm.as_ptr(), script.as_ptr(), script.len() as u32) };
+        assert!(valid, "invalid script {}", script);
+    }
+}
+
+impl Drop for WidevineAPI {
+    fn drop(&mut self) {
+        let unsettled = self.unsettled_promises();
+        if !unsettled.is_empty() {
+            log::debug!("promises never settled by the CDM: {:?}", unsettled);
+        }
+        self.host.fail(HostError::Dropped);
     }
 }
 
 #[test]
 fn test_widevine_api() {
-    WidevineAPI::initialize().unwrap();
+    let api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    assert_eq!(api.key_system(), WIDEVINE_KEY_SYSTEM);
+
+    let api = WidevineAPI::initialize_with_key_system(library::mock_cdm(), "org.w3.clearkey");
+    assert_eq!(api.unwrap().key_system(), "org.w3.clearkey");
+    let result = WidevineAPI::initialize_with_key_system(library::mock_cdm(), "com.example");
+    assert!(matches!(
+        result,
+        Err(InitializeError::KeySystemRejected(ref key_system)) if key_system == "com.example"
+    ));
 }
 
+#[cfg(test)]
 #[tokio::test]
 async fn test_cdm_initialization() {
-    let mut api = WidevineAPI::initialize().unwrap();
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
     let result = api.initialize_cdm().await;
     assert!(result.is_ok())
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_host_failure() {
+    use init_data::KeyIds;
+    use std::sync::mpsc::channel;
+
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.initialize_cdm().await.unwrap();
+    // Sending the license request to a dropped receiver only drops it.
+    let (sender, _) = channel();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x11; 16]));
+    let session_id = api
+        .create_session(SessionType::Temporary, init_data, sender)
+        .await
+        .unwrap();
+    assert!(api.host_error().is_none());
+
+    let host = &mut *api.host as *mut Host;
+    host::guarded(host, "on_session_message", (), || {
+        panic!("callback failure")
+    });
+    assert!(matches!(
+        api.host_error(),
+        Some(HostError::CallbackPanicked {
+            callback: "on_session_message",
+            ..
+        })
+    ));
+    assert!(matches!(
+        api.close_session(&session_id).await,
+        Err(CdmError::HostFailed(_))
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_promise_timeout() {
+    use init_data::KeyIds;
+    use serde_json::json;
+    use std::sync::mpsc::channel;
+
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.set_timeouts(PromiseTimeouts {
+        close_session: Some(Duration::from_millis(50)),
+        ..PromiseTimeouts::default()
+    });
+    api.initialize_cdm().await.unwrap();
+    api.set_mock_script(json!({ "script": [{ "call": "close_session", "drop": true }] }));
+    // The script leaves the certificate to the CDM.
+    api.set_server_certificate(b"certificate").await.unwrap();
+    assert!(api.set_server_certificate(b"").await.is_err());
+    let (sender, _receiver) = channel();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x11; 16]));
+    let session_id = api
+        .create_session(SessionType::Temporary, init_data, sender)
+        .await
+        .unwrap();
+    assert!(api.unsettled_promises().is_empty());
+
+    assert!(matches!(
+        api.close_session(&session_id).await,
+        Err(CdmError::TimedOut(Operation::CloseSession))
+    ));
+    match api.unsettled_promises().as_slice() {
+        [promise] => {
+            assert_eq!(promise.operation, Operation::CloseSession);
+            assert!(promise.cancelled);
+        }
+        promises => panic!("unexpected promises {:?}", promises),
+    }
+    // The next call isn't held up by the abandoned promise.
+    api.close_session(&session_id).await.unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_mock_cdm_session() {
+    use decryption::{EncryptionScheme, Pattern};
+    use init_data::KeyIds;
+    use serde_json::{json, Value};
+    use std::sync::mpsc::channel;
+    use std::time::{Duration, Instant};
+    use types::{KeyStatus, MessageType, SessionEventType};
+
+    // NIST SP 800-38A, F.5.1.
+    let key = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    let iv = [
+        0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe,
+        0xff,
+    ];
+    let ciphertext = [
+        0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6,
+        0xce,
+    ];
+    let plaintext = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a,
+    ];
+    let key_id = [0x11; 16];
+    let input = |key_id| InputBuffer {
+        data: &ciphertext,
+        encryption_scheme: EncryptionScheme::Cenc,
+        key_id,
+        iv: &iv,
+        subsamples: Vec::new(),
+        pattern: Pattern::default(),
+        timestamp: 0,
+    };
+
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.initialize_cdm().await.unwrap();
+    let (sender, receiver) = channel();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&key_id));
+    let session_id = api
+        .create_session(SessionType::Temporary, init_data, sender)
+        .await
+        .unwrap();
+
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.session_id, session_id);
+    let request: Value = match event.data {
+        SessionEventType::Message(message) => {
+            assert_eq!(message.message_type, MessageType::LicenseRequest);
+            serde_json::from_slice(&message.content).unwrap()
+        }
+        data => panic!("unexpected event {:?}", data),
+    };
+    assert_eq!(request["kids"][0], base64::encode_url(&key_id));
+
+    let license = json!({
+        "keys": [{ "kty": "oct", "kid": base64::encode_url(&key_id), "k": base64::encode_url(&key) }],
+        "renew_after_ms": 10,
+    });
+    api.update_session(&session_id, license.to_string().as_bytes())
+        .await
+        .unwrap();
+    match receiver.try_recv().unwrap().data {
+        SessionEventType::KeysChange(change) => {
+            assert_eq!(change.keys_info[0].key_id, key_id);
+            assert!(matches!(change.keys_info[0].status, KeyStatus::Usable));
+        }
+        data => panic!("unexpected event {:?}", data),
+    }
+    assert_eq!(api.decrypt(input(&key_id)).unwrap(), plaintext);
+    assert!(matches!(
+        api.decrypt(input(&[0x22; 16])),
+        Err(Status::NoKey)
+    ));
+
+    // The renewal is sent from a CDM timer.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let renewal = loop {
+        api.update();
+        if let Ok(event) = receiver.try_recv() {
+            break event;
+        }
+        assert!(Instant::now() < deadline, "no renewal");
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    assert!(matches!(
+        renewal.data,
+        SessionEventType::Message(ref message) if message.message_type == MessageType::LicenseRenewal
+    ));
+
+    api.set_mock_script(json!({ "script": [
+        { "call": "decrypt", "status": "decrypt_error" },
+        { "call": "close_session", "reject": "invalid_state", "system_code": 3 },
+    ] }));
+    assert!(matches!(
+        api.decrypt(input(&key_id)),
+        Err(Status::DecryptError)
+    ));
+    assert_eq!(api.decrypt(input(&key_id)).unwrap(), plaintext);
+    match api.close_session(&session_id).await {
+        Err(CdmError::Rejected(info)) => assert_eq!(info.system_code, 3),
+        result => panic!("unexpected result {:?}", result),
+    }
+    api.close_session(&session_id).await.unwrap();
+}
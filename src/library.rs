@@ -1,32 +1,115 @@
-use std::os::raw::c_void;
-use std::ptr;
+use libc::{dlclose, dlopen, dlsym, RTLD_LAZY};
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+#[cfg(test)]
+use std::path::PathBuf;
 
-extern "C" {
-    fn GetLibraryHandle() -> *mut c_void;
-    fn DeinitializeLibrary(library: *mut c_void);
-}
+/// Called by `CreateCdmInstance` with the host interface version it wants.
+pub type GetCdmHostFunc = extern "C" fn(c_int, *mut c_void) -> *mut c_void;
+
+type InitializeModuleFunc = extern "C" fn();
+type CreateCdmInstanceFunc =
+    extern "C" fn(c_int, *const c_char, u32, GetCdmHostFunc, *mut c_void) -> *mut c_void;
+type GetCdmVersionFunc = extern "C" fn() -> *const c_char;
 
-pub struct Library(*mut c_void);
+/// A CDM shared library, initialized for as long as it's loaded.
+pub struct Library {
+    handle: *mut c_void,
+    deinitialize_module: InitializeModuleFunc,
+    pub create_cdm_instance: CreateCdmInstanceFunc,
+}
 
 impl Library {
-    pub fn initialize() -> Result<Self, ()> {
-        let library = unsafe { GetLibraryHandle() };
-        if library == ptr::null_mut() {
-            Err(())
-        } else {
-            Ok(Self(library))
+    pub fn initialize(path: &Path) -> Result<Self, ()> {
+        let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| ())?;
+        let handle = unsafe { dlopen(path.as_ptr(), RTLD_LAZY) };
+        if handle.is_null() {
+            return Err(());
+        }
+
+        let symbols = unsafe {
+            (
+                symbol::<InitializeModuleFunc>(handle, b"InitializeCdmModule_4\0"),
+                symbol::<InitializeModuleFunc>(handle, b"DeinitializeCdmModule\0"),
+                symbol::<CreateCdmInstanceFunc>(handle, b"CreateCdmInstance\0"),
+                symbol::<GetCdmVersionFunc>(handle, b"GetCdmVersion\0"),
+            )
+        };
+        match symbols {
+            (
+                Some(initialize_module),
+                Some(deinitialize_module),
+                Some(create_cdm_instance),
+                Some(_),
+            ) => {
+                initialize_module();
+                Ok(Self {
+                    handle,
+                    deinitialize_module,
+                    create_cdm_instance,
+                })
+            }
+            _ => {
+                unsafe { dlclose(handle) };
+                Err(())
+            }
         }
     }
+}
 
-    pub fn pointer(&self) -> *mut c_void {
-        self.0
+#[cfg(test)]
+impl Library {
+    /// Looks up a function the library exports besides the CDM interface.
+    /// `name` is NUL-terminated.
+    pub(crate) unsafe fn function<T: Copy>(&self, name: &[u8]) -> Option<T> {
+        symbol(self.handle, name)
     }
 }
 
 impl Drop for Library {
     fn drop(&mut self) {
+        (self.deinitialize_module)();
         unsafe {
-            DeinitializeLibrary(self.0);
+            dlclose(self.handle);
         }
     }
 }
+
+/// Looks up a function of the library. `name` is NUL-terminated.
+unsafe fn symbol<T: Copy>(handle: *mut c_void, name: &[u8]) -> Option<T> {
+    let symbol = dlsym(handle, name.as_ptr() as *const c_char);
+    if symbol.is_null() {
+        None
+    } else {
+        Some(mem::transmute_copy(&symbol))
+    }
+}
+
+/// Builds the workspace's mock CDM once per test run and returns its path.
+#[cfg(test)]
+pub(crate) fn mock_cdm() -> PathBuf {
+    use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+    use std::process::Command;
+    use std::sync::Once;
+
+    static BUILD: Once = Once::new();
+    let manifest_directory = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // In a target directory of its own, to not contend with the build
+    // running the tests.
+    let target_directory = manifest_directory.join("target").join("mock_cdm");
+    BUILD.call_once(|| {
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "--package", "mock_cdm", "--target-dir"])
+            .arg(&target_directory)
+            .current_dir(manifest_directory)
+            .status()
+            .unwrap();
+        assert!(status.success(), "failed to build the mock CDM");
+    });
+    target_directory
+        .join("debug")
+        .join(format!("{}mock_cdm{}", DLL_PREFIX, DLL_SUFFIX))
+}
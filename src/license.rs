@@ -0,0 +1,287 @@
+#[cfg(feature = "http")]
+use crate::base64;
+use crate::types::SessionMessage;
+use async_trait::async_trait;
+#[cfg(feature = "http")]
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+#[cfg(feature = "http")]
+use reqwest::Client;
+#[cfg(feature = "http")]
+use serde_json::Value;
+use std::future::Future;
+#[cfg(feature = "http")]
+use std::sync::OnceLock;
+#[cfg(feature = "http")]
+use std::time::Duration;
+#[cfg(feature = "http")]
+use tokio::runtime::{Builder, Runtime};
+
+#[cfg(feature = "http")]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransportError {
+    Timeout,
+    Network(String),
+    HttpStatus(u16),
+    InvalidHeader(String),
+    InvalidResponse(String),
+}
+
+/// Carries the messages of a session to a license server and returns the
+/// server's response, which is then passed to `update_session`.
+#[async_trait]
+pub trait LicenseTransport: Send + Sync {
+    async fn exchange(&self, message: SessionMessage) -> Result<Vec<u8>, TransportError>;
+}
+
+#[async_trait]
+impl<T: LicenseTransport + ?Sized> LicenseTransport for Box<T> {
+    async fn exchange(&self, message: SessionMessage) -> Result<Vec<u8>, TransportError> {
+        (**self).exchange(message).await
+    }
+}
+
+/// A transport backed by an async function.
+pub struct CallbackTransport<F>(F);
+
+impl<F> CallbackTransport<F> {
+    pub fn new(callback: F) -> Self {
+        Self(callback)
+    }
+}
+
+#[async_trait]
+impl<F, R> LicenseTransport for CallbackTransport<F>
+where
+    F: Fn(SessionMessage) -> R + Send + Sync,
+    R: Future<Output = Result<Vec<u8>, TransportError>> + Send,
+{
+    async fn exchange(&self, message: SessionMessage) -> Result<Vec<u8>, TransportError> {
+        (self.0)(message).await
+    }
+}
+
+#[cfg(feature = "http")]
+/// Turns a CDM message into the body of the HTTP request.
+pub type RequestWrapper =
+    Box<dyn Fn(&SessionMessage) -> Result<Vec<u8>, TransportError> + Send + Sync>;
+#[cfg(feature = "http")]
+/// Extracts the license from the body of the HTTP response.
+pub type ResponseUnwrapper = Box<dyn Fn(Vec<u8>) -> Result<Vec<u8>, TransportError> + Send + Sync>;
+
+#[cfg(feature = "http")]
+/// POSTs messages to a license server. By default the raw message is sent
+/// and the raw response body is returned.
+///
+/// reqwest needs a tokio 0.2 runtime, so the requests run on one shared by
+/// all transports, started on first use. The futures of `exchange` can then
+/// be awaited from any executor, or from `blocking::block_on`.
+pub struct HttpTransport {
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    wrap_request: Option<RequestWrapper>,
+    unwrap_response: Option<ResponseUnwrapper>,
+    client: Client,
+}
+
+#[cfg(feature = "http")]
+impl HttpTransport {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            headers: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+            wrap_request: None,
+            unwrap_response: None,
+            client: Client::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_request_wrapper(mut self, wrapper: RequestWrapper) -> Self {
+        self.wrap_request = Some(wrapper);
+        self
+    }
+
+    pub fn with_response_unwrapper(mut self, unwrapper: ResponseUnwrapper) -> Self {
+        self.unwrap_response = Some(unwrapper);
+        self
+    }
+
+    /// Sends `{"<challenge_field>": "<base64 message>"}` and reads the license
+    /// from the base64 `license_field` of the JSON response.
+    pub fn with_json_envelope(self, challenge_field: &str, license_field: &str) -> Self {
+        let challenge_field = challenge_field.to_string();
+        let license_field = license_field.to_string();
+        self.with_header(CONTENT_TYPE.as_str(), "application/json")
+            .with_request_wrapper(Box::new(move |message| {
+                let mut envelope = serde_json::Map::new();
+                envelope.insert(
+                    challenge_field.clone(),
+                    Value::String(base64::encode(&message.content)),
+                );
+                Ok(Value::Object(envelope).to_string().into_bytes())
+            }))
+            .with_response_unwrapper(Box::new(move |body| {
+                let invalid = || TransportError::InvalidResponse(license_field.clone());
+                let envelope: Value = serde_json::from_slice(&body).map_err(|_| invalid())?;
+                let license = envelope[&license_field].as_str().ok_or_else(invalid)?;
+                base64::decode(license).map_err(|_| invalid())
+            }))
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl LicenseTransport for HttpTransport {
+    async fn exchange(&self, message: SessionMessage) -> Result<Vec<u8>, TransportError> {
+        let body = match self.wrap_request {
+            Some(ref wrap) => wrap(&message)?,
+            None => message.content,
+        };
+
+        let mut request = self.client.post(&self.url).timeout(self.timeout).body(body);
+        for (name, value) in &self.headers {
+            let invalid = || TransportError::InvalidHeader(name.clone());
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+            let value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+            request = request.header(name, value);
+        }
+
+        let body = runtime()
+            .spawn(async move {
+                let response = request.send().await.map_err(map_error)?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(TransportError::HttpStatus(status.as_u16()));
+                }
+                Ok(response.bytes().await.map_err(map_error)?.to_vec())
+            })
+            .await
+            .map_err(|error| TransportError::Network(error.to_string()))??;
+
+        match self.unwrap_response {
+            Some(ref unwrap) => unwrap(body),
+            None => Ok(body),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .thread_name("widevine-http")
+            .enable_all()
+            .build()
+            .expect("failed to start the HTTP runtime")
+    })
+}
+
+#[cfg(feature = "http")]
+fn map_error(error: reqwest::Error) -> TransportError {
+    if error.is_timeout() {
+        TransportError::Timeout
+    } else {
+        TransportError::Network(error.to_string())
+    }
+}
+
+/// Answers a single HTTP request on `listener` and returns its head and body.
+#[cfg(all(test, feature = "http"))]
+async fn serve_once(
+    listener: &mut tokio::net::TcpListener,
+    status: &str,
+    body: &[u8],
+) -> (String, Vec<u8>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    let head_end = loop {
+        let read = stream.read(&mut buffer).await.unwrap();
+        request.extend_from_slice(&buffer[..read]);
+        if let Some(index) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break index + 4;
+        }
+    };
+    let head = String::from_utf8(request[..head_end].to_vec()).unwrap();
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.eq_ignore_ascii_case("content-length") {
+                value.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .unwrap_or(0);
+    while request.len() < head_end + content_length {
+        let read = stream.read(&mut buffer).await.unwrap();
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    (head, request[head_end..].to_vec())
+}
+
+#[cfg(all(test, feature = "http"))]
+#[tokio::test]
+async fn test_http_transport() {
+    use crate::types::MessageType;
+
+    let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/license", listener.local_addr().unwrap());
+    let transport = HttpTransport::new(&url)
+        .with_header("X-Token", "secret")
+        .with_json_envelope("challenge", "license");
+    let message = SessionMessage {
+        message_type: MessageType::LicenseRequest,
+        content: b"challenge bytes".to_vec(),
+    };
+
+    let server = tokio::spawn(async move {
+        let license = format!(r#"{{"license": "{}"}}"#, base64::encode(b"license bytes"));
+        let request = serve_once(&mut listener, "200 OK", license.as_bytes()).await;
+        serve_once(&mut listener, "403 Forbidden", b"").await;
+        request
+    });
+
+    let license = transport.exchange(message.clone()).await.unwrap();
+    assert_eq!(license, b"license bytes");
+    assert_eq!(
+        transport.exchange(message).await,
+        Err(TransportError::HttpStatus(403))
+    );
+
+    let (head, body) = server.await.unwrap();
+    assert!(head.starts_with("POST /license "));
+    assert!(head.to_ascii_lowercase().contains("x-token: secret"));
+    let envelope: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        envelope["challenge"].as_str(),
+        Some(base64::encode(b"challenge bytes").as_str())
+    );
+}
@@ -0,0 +1,953 @@
+use crate::byte_reader::{ByteReader, UnexpectedEnd};
+use crate::decryption::{EncryptionScheme, Pattern, Sample, SubsampleEntry};
+use crate::pssh::{PsshBox, PsshError};
+use std::convert::TryInto;
+
+const SEIG: &[u8; 4] = b"seig";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mp4Error {
+    UnexpectedEnd,
+    InvalidBoxSize([u8; 4]),
+    MissingBox([u8; 4]),
+    UnknownTrack(u32),
+    UnsupportedScheme([u8; 4]),
+    InvalidSampleGroup(u32),
+    MissingEncryptionInfo,
+    SampleOutOfBounds,
+    /// An offset or time computed from the file doesn't fit in 64 bits.
+    Overflow,
+    InvalidPssh(PsshError),
+}
+
+impl From<UnexpectedEnd> for Mp4Error {
+    fn from(_: UnexpectedEnd) -> Self {
+        Mp4Error::UnexpectedEnd
+    }
+}
+
+impl From<PsshError> for Mp4Error {
+    fn from(error: PsshError) -> Self {
+        Mp4Error::InvalidPssh(error)
+    }
+}
+
+struct Mp4Box<'a> {
+    box_type: [u8; 4],
+    /// Offset of the box header from the start of the parent's payload.
+    offset: usize,
+    payload: &'a [u8],
+}
+
+struct BoxIter<'a> {
+    data: &'a [u8],
+    reader: ByteReader<'a>,
+}
+
+impl<'a> BoxIter<'a> {
+    fn read_box(&mut self) -> Result<Mp4Box<'a>, Mp4Error> {
+        let offset = self.reader.position();
+        let mut size = u64::from(self.reader.read_u32()?);
+        let box_type = self.reader.read_array()?;
+        let mut header_size = 8;
+        if size == 1 {
+            size = self.reader.read_u64()?;
+            header_size = 16;
+        } else if size == 0 {
+            size = (self.data.len() - offset) as u64;
+        }
+
+        let payload_size = size
+            .checked_sub(header_size)
+            .and_then(|size| size.try_into().ok())
+            .ok_or(Mp4Error::InvalidBoxSize(box_type))?;
+        let payload = self
+            .reader
+            .read(payload_size)
+            .map_err(|_| Mp4Error::InvalidBoxSize(box_type))?;
+
+        Ok(Mp4Box {
+            box_type,
+            offset,
+            payload,
+        })
+    }
+}
+
+impl<'a> Iterator for BoxIter<'a> {
+    type Item = Result<Mp4Box<'a>, Mp4Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_empty() {
+            return None;
+        }
+        let item = self.read_box();
+        if item.is_err() {
+            self.reader.rest();
+        }
+        Some(item)
+    }
+}
+
+fn boxes(data: &[u8]) -> BoxIter<'_> {
+    BoxIter {
+        data,
+        reader: ByteReader::new(data),
+    }
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Result<Option<Mp4Box<'a>>, Mp4Error> {
+    for child in boxes(data) {
+        let child = child?;
+        if &child.box_type == box_type {
+            return Ok(Some(child));
+        }
+    }
+    Ok(None)
+}
+
+fn require_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Result<Mp4Box<'a>, Mp4Error> {
+    find_box(data, box_type)?.ok_or(Mp4Error::MissingBox(*box_type))
+}
+
+/// Reads the version and flags of a full box, returning a reader positioned
+/// right after them.
+fn full_box(payload: &[u8]) -> Result<(u8, u32, ByteReader<'_>), Mp4Error> {
+    let mut reader = ByteReader::new(payload);
+    let version = reader.read_u8()?;
+    let flags = reader.read_u24()?;
+    Ok((version, flags, reader))
+}
+
+/// Reads an entry count, checking that `count` entries of at least
+/// `entry_size` bytes fit in what's left of the box.
+fn read_count(reader: &mut ByteReader, entry_size: usize) -> Result<u32, Mp4Error> {
+    let count = reader.read_u32()?;
+    if count as usize > reader.remaining() / entry_size {
+        return Err(Mp4Error::UnexpectedEnd);
+    }
+    Ok(count)
+}
+
+/// Encryption parameters shared by `tenc` boxes and `seig` sample group
+/// entries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncryptionParameters {
+    pub is_protected: bool,
+    pub pattern: Pattern,
+    pub per_sample_iv_size: u8,
+    pub key_id: [u8; 16],
+    pub constant_iv: Option<Vec<u8>>,
+}
+
+impl EncryptionParameters {
+    /// Parses the fields following the version and flags of a `tenc` box,
+    /// which is also the layout of a `seig` entry.
+    fn parse(reader: &mut ByteReader) -> Result<Self, Mp4Error> {
+        reader.skip(1)?;
+        let pattern = reader.read_u8()?;
+        let is_protected = reader.read_u8()? == 1;
+        let per_sample_iv_size = reader.read_u8()?;
+        let key_id = reader.read_array()?;
+
+        let constant_iv = if is_protected && per_sample_iv_size == 0 {
+            let size = reader.read_u8()?;
+            Some(reader.read(size.into())?.to_vec())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            is_protected,
+            pattern: Pattern {
+                crypt_byte_block: (pattern >> 4).into(),
+                skip_byte_block: (pattern & 0x0f).into(),
+            },
+            per_sample_iv_size,
+            key_id,
+            constant_iv,
+        })
+    }
+}
+
+fn parse_seig_entries(payload: &[u8]) -> Result<Option<Vec<EncryptionParameters>>, Mp4Error> {
+    let (version, _, mut reader) = full_box(payload)?;
+    if &reader.read_array::<4>()? != SEIG {
+        return Ok(None);
+    }
+
+    let default_length = if version >= 1 { reader.read_u32()? } else { 0 };
+    if version >= 2 {
+        reader.skip(4)?;
+    }
+
+    // Each entry has at least the fields up to the key ID.
+    let count = read_count(&mut reader, 20)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        if version >= 1 && default_length == 0 {
+            reader.skip(4)?;
+        }
+        entries.push(EncryptionParameters::parse(&mut reader)?);
+    }
+    Ok(Some(entries))
+}
+
+/// Runs of `(sample_count, group_description_index)` from a `seig` `sbgp`.
+fn parse_seig_mapping(payload: &[u8]) -> Result<Option<Vec<(u32, u32)>>, Mp4Error> {
+    let (version, _, mut reader) = full_box(payload)?;
+    if &reader.read_array::<4>()? != SEIG {
+        return Ok(None);
+    }
+    if version == 1 {
+        reader.skip(4)?;
+    }
+
+    let count = read_count(&mut reader, 8)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        entries.push((reader.read_u32()?, reader.read_u32()?));
+    }
+    Ok(Some(entries))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackProtection {
+    pub original_format: [u8; 4],
+    pub scheme: EncryptionScheme,
+    pub default: EncryptionParameters,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct SampleDefaults {
+    duration: u32,
+    size: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    pub track_id: u32,
+    pub timescale: u32,
+    pub protection: Option<TrackProtection>,
+    /// `seig` entries from the sample table, referenced by fragment sample
+    /// groups for key rotation.
+    pub sample_groups: Vec<EncryptionParameters>,
+    defaults: SampleDefaults,
+}
+
+impl Track {
+    fn parse(trak: &[u8]) -> Result<Self, Mp4Error> {
+        let (version, _, mut tkhd) = full_box(require_box(trak, b"tkhd")?.payload)?;
+        tkhd.skip(if version == 1 { 16 } else { 8 })?;
+        let track_id = tkhd.read_u32()?;
+
+        let mdia = require_box(trak, b"mdia")?.payload;
+        let (version, _, mut mdhd) = full_box(require_box(mdia, b"mdhd")?.payload)?;
+        mdhd.skip(if version == 1 { 16 } else { 8 })?;
+        let timescale = mdhd.read_u32()?;
+
+        let minf = require_box(mdia, b"minf")?.payload;
+        let stbl = require_box(minf, b"stbl")?.payload;
+        let (_, _, mut stsd) = full_box(require_box(stbl, b"stsd")?.payload)?;
+        stsd.skip(4)?;
+
+        let mut protection = None;
+        for entry in boxes(stsd.rest()) {
+            let entry = entry?;
+            let header_size = match &entry.box_type {
+                b"encv" => 78,
+                b"enca" => 28,
+                _ => continue,
+            };
+            let children = entry
+                .payload
+                .get(header_size..)
+                .ok_or(Mp4Error::UnexpectedEnd)?;
+            if let Some(sinf) = find_box(children, b"sinf")? {
+                protection = Some(Self::parse_sinf(sinf.payload)?);
+                break;
+            }
+        }
+
+        let mut sample_groups = Vec::new();
+        for child in boxes(stbl) {
+            let child = child?;
+            if &child.box_type == b"sgpd" {
+                if let Some(entries) = parse_seig_entries(child.payload)? {
+                    sample_groups = entries;
+                }
+            }
+        }
+
+        Ok(Self {
+            track_id,
+            timescale,
+            protection,
+            sample_groups,
+            defaults: SampleDefaults::default(),
+        })
+    }
+
+    fn parse_sinf(sinf: &[u8]) -> Result<TrackProtection, Mp4Error> {
+        let original_format = ByteReader::new(require_box(sinf, b"frma")?.payload).read_array()?;
+        let (_, _, mut schm) = full_box(require_box(sinf, b"schm")?.payload)?;
+        let scheme_type: [u8; 4] = schm.read_array()?;
+        let scheme = match &scheme_type {
+            b"cenc" => EncryptionScheme::Cenc,
+            b"cbcs" => EncryptionScheme::Cbcs,
+            _ => return Err(Mp4Error::UnsupportedScheme(scheme_type)),
+        };
+
+        let schi = require_box(sinf, b"schi")?.payload;
+        let (_, _, mut tenc) = full_box(require_box(schi, b"tenc")?.payload)?;
+        let mut default = EncryptionParameters::parse(&mut tenc)?;
+        if scheme == EncryptionScheme::Cenc {
+            default.pattern = Pattern::default();
+        }
+
+        Ok(TrackProtection {
+            original_format,
+            scheme,
+            default,
+        })
+    }
+}
+
+/// The `moov` of a fragmented MP4 stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitSegment {
+    pub tracks: Vec<Track>,
+    pub pssh_boxes: Vec<PsshBox>,
+}
+
+impl InitSegment {
+    pub fn parse(data: &[u8]) -> Result<Self, Mp4Error> {
+        let moov = require_box(data, b"moov")?.payload;
+        let mut tracks = Vec::new();
+        let mut pssh_boxes = Vec::new();
+        let mut trex_boxes = Vec::new();
+
+        for child in boxes(moov) {
+            let child = child?;
+            match &child.box_type {
+                b"trak" => tracks.push(Track::parse(child.payload)?),
+                b"pssh" => pssh_boxes.push(PsshBox::parse(&moov[child.offset..])?.0),
+                b"mvex" => {
+                    for trex in boxes(child.payload) {
+                        let trex = trex?;
+                        if &trex.box_type == b"trex" {
+                            trex_boxes.push(trex.payload);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for trex in trex_boxes {
+            let (_, _, mut reader) = full_box(trex)?;
+            let track_id = reader.read_u32()?;
+            reader.skip(4)?;
+            let defaults = SampleDefaults {
+                duration: reader.read_u32()?,
+                size: reader.read_u32()?,
+            };
+            if let Some(track) = tracks.iter_mut().find(|track| track.track_id == track_id) {
+                track.defaults = defaults;
+            }
+        }
+
+        Ok(Self { tracks, pssh_boxes })
+    }
+
+    pub fn track(&self, track_id: u32) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.track_id == track_id)
+    }
+}
+
+#[derive(Default)]
+struct AuxiliaryInfo {
+    iv: Vec<u8>,
+    subsamples: Vec<SubsampleEntry>,
+}
+
+impl AuxiliaryInfo {
+    fn parse(reader: &mut ByteReader, iv_size: u8, has_subsamples: bool) -> Result<Self, Mp4Error> {
+        let iv = reader.read(iv_size.into())?.to_vec();
+        let mut subsamples = Vec::new();
+        if has_subsamples {
+            let count = reader.read_u16()?;
+            for _ in 0..count {
+                subsamples.push(SubsampleEntry {
+                    clear_bytes: reader.read_u16()?.into(),
+                    cipher_bytes: reader.read_u32()?,
+                });
+            }
+        }
+        Ok(Self { iv, subsamples })
+    }
+}
+
+struct RawSample {
+    offset: u64,
+    size: u32,
+    decode_time: u64,
+    composition_offset: i64,
+}
+
+/// A `moof`/`mdat` media segment, possibly containing several fragments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaSegment {
+    /// `pssh` boxes carried in the fragments, used with key rotation.
+    pub pssh_boxes: Vec<PsshBox>,
+    pub samples: Vec<Sample>,
+}
+
+impl MediaSegment {
+    pub fn parse(init: &InitSegment, data: &[u8]) -> Result<Self, Mp4Error> {
+        let mut segment = Self {
+            pssh_boxes: Vec::new(),
+            samples: Vec::new(),
+        };
+
+        for top_level in boxes(data) {
+            let top_level = top_level?;
+            if &top_level.box_type != b"moof" {
+                continue;
+            }
+            for child in boxes(top_level.payload) {
+                let child = child?;
+                match &child.box_type {
+                    b"traf" => {
+                        segment.parse_traf(init, data, top_level.offset, child.payload)?;
+                    }
+                    b"pssh" => {
+                        let pssh = PsshBox::parse(&top_level.payload[child.offset..])?.0;
+                        segment.pssh_boxes.push(pssh);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(segment)
+    }
+
+    fn parse_traf(
+        &mut self,
+        init: &InitSegment,
+        data: &[u8],
+        moof_offset: usize,
+        traf: &[u8],
+    ) -> Result<(), Mp4Error> {
+        let (_, flags, mut tfhd) = full_box(require_box(traf, b"tfhd")?.payload)?;
+        let track_id = tfhd.read_u32()?;
+        let track = init
+            .track(track_id)
+            .ok_or(Mp4Error::UnknownTrack(track_id))?;
+
+        let mut defaults = track.defaults;
+        let base_offset = if flags & 0x1 != 0 {
+            tfhd.read_u64()?
+        } else {
+            moof_offset as u64
+        };
+        if flags & 0x2 != 0 {
+            tfhd.skip(4)?;
+        }
+        if flags & 0x8 != 0 {
+            defaults.duration = tfhd.read_u32()?;
+        }
+        if flags & 0x10 != 0 {
+            defaults.size = tfhd.read_u32()?;
+        }
+
+        let mut decode_time = match find_box(traf, b"tfdt")? {
+            Some(tfdt) => {
+                let (version, _, mut reader) = full_box(tfdt.payload)?;
+                if version == 1 {
+                    reader.read_u64()?
+                } else {
+                    reader.read_u32()?.into()
+                }
+            }
+            None => 0,
+        };
+
+        let mut raw_samples = Vec::new();
+        let mut data_offset = base_offset;
+        let mut fragment_groups = Vec::new();
+        let mut group_mapping = Vec::new();
+        let mut senc = None;
+        let mut saiz = None;
+        let mut saio = None;
+
+        for child in boxes(traf) {
+            let child = child?;
+            match &child.box_type {
+                b"trun" => {
+                    let (version, flags, mut trun) = full_box(child.payload)?;
+                    let count = trun.read_u32()?;
+                    if flags & 0x1 != 0 {
+                        let relative = trun.read_u32()? as i32;
+                        data_offset = base_offset
+                            .checked_add_signed(relative.into())
+                            .ok_or(Mp4Error::Overflow)?;
+                    }
+                    if flags & 0x4 != 0 {
+                        trun.skip(4)?;
+                    }
+                    // Samples either have fields left in the box or, without
+                    // any, at least take up a byte of the segment.
+                    let sample_size = [0x100, 0x200, 0x400, 0x800]
+                        .iter()
+                        .filter(|&&flag| flags & flag != 0)
+                        .count()
+                        * 4;
+                    let limit = match sample_size {
+                        0 => data.len(),
+                        size => trun.remaining() / size,
+                    };
+                    if count as usize > limit {
+                        return Err(Mp4Error::SampleOutOfBounds);
+                    }
+                    for _ in 0..count {
+                        let duration = if flags & 0x100 != 0 {
+                            trun.read_u32()?
+                        } else {
+                            defaults.duration
+                        };
+                        let size = if flags & 0x200 != 0 {
+                            trun.read_u32()?
+                        } else {
+                            defaults.size
+                        };
+                        if flags & 0x400 != 0 {
+                            trun.skip(4)?;
+                        }
+                        let composition_offset = if flags & 0x800 == 0 {
+                            0
+                        } else if version == 0 {
+                            trun.read_u32()?.into()
+                        } else {
+                            i64::from(trun.read_u32()? as i32)
+                        };
+
+                        raw_samples.push(RawSample {
+                            offset: data_offset,
+                            size,
+                            decode_time,
+                            composition_offset,
+                        });
+                        data_offset = data_offset
+                            .checked_add(size.into())
+                            .ok_or(Mp4Error::Overflow)?;
+                        decode_time = decode_time
+                            .checked_add(duration.into())
+                            .ok_or(Mp4Error::Overflow)?;
+                    }
+                }
+                b"sgpd" => {
+                    if let Some(entries) = parse_seig_entries(child.payload)? {
+                        fragment_groups = entries;
+                    }
+                }
+                b"sbgp" => {
+                    if let Some(entries) = parse_seig_mapping(child.payload)? {
+                        group_mapping = entries;
+                    }
+                }
+                b"senc" => senc = Some(child.payload),
+                b"saiz" => saiz = Some(child.payload),
+                b"saio" => saio = Some(child.payload),
+                _ => {}
+            }
+        }
+
+        let protection = match track.protection {
+            Some(ref protection) => protection,
+            None => {
+                for raw in raw_samples {
+                    let sample = Sample {
+                        track_id,
+                        data: sample_data(data, &raw)?.to_vec(),
+                        timestamp: timestamp(&raw, track.timescale)?,
+                        encryption_scheme: EncryptionScheme::Unencrypted,
+                        key_id: Vec::new(),
+                        iv: Vec::new(),
+                        subsamples: Vec::new(),
+                        pattern: Pattern::default(),
+                    };
+                    self.samples.push(sample);
+                }
+                return Ok(());
+            }
+        };
+
+        let mut parameters = Vec::with_capacity(raw_samples.len());
+        let mut indices = group_mapping
+            .iter()
+            .flat_map(|&(count, index)| (0..count).map(move |_| index));
+        for _ in 0..raw_samples.len() {
+            let index = indices.next().unwrap_or(0);
+            let group = match index {
+                0 => &protection.default,
+                index if index > 0x10000 => fragment_groups
+                    .get((index - 0x10001) as usize)
+                    .ok_or(Mp4Error::InvalidSampleGroup(index))?,
+                index => track
+                    .sample_groups
+                    .get((index - 1) as usize)
+                    .ok_or(Mp4Error::InvalidSampleGroup(index))?,
+            };
+            parameters.push(group);
+        }
+
+        let auxiliary_info =
+            Self::parse_auxiliary_info(&parameters, senc, saiz, saio, data, base_offset)?;
+
+        for ((raw, parameters), info) in raw_samples.iter().zip(parameters).zip(auxiliary_info) {
+            let (encryption_scheme, key_id, iv, pattern) = if parameters.is_protected {
+                let iv = match parameters.constant_iv {
+                    Some(ref iv) if parameters.per_sample_iv_size == 0 => iv.clone(),
+                    _ => info.iv,
+                };
+                let pattern = if protection.scheme == EncryptionScheme::Cbcs {
+                    parameters.pattern
+                } else {
+                    Pattern::default()
+                };
+                (
+                    protection.scheme,
+                    parameters.key_id.to_vec(),
+                    pad_iv(iv),
+                    pattern,
+                )
+            } else {
+                (
+                    EncryptionScheme::Unencrypted,
+                    Vec::new(),
+                    Vec::new(),
+                    Pattern::default(),
+                )
+            };
+
+            self.samples.push(Sample {
+                track_id,
+                data: sample_data(data, raw)?.to_vec(),
+                timestamp: timestamp(raw, track.timescale)?,
+                encryption_scheme,
+                key_id,
+                iv,
+                subsamples: info.subsamples,
+                pattern,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reads the per-sample IVs and subsamples from `senc`, falling back to
+    /// the auxiliary information pointed to by `saiz`/`saio`.
+    fn parse_auxiliary_info(
+        parameters: &[&EncryptionParameters],
+        senc: Option<&[u8]>,
+        saiz: Option<&[u8]>,
+        saio: Option<&[u8]>,
+        data: &[u8],
+        base_offset: u64,
+    ) -> Result<Vec<AuxiliaryInfo>, Mp4Error> {
+        let mut infos = Vec::with_capacity(parameters.len());
+
+        if let Some(senc) = senc {
+            let (_, flags, mut reader) = full_box(senc)?;
+            let count = reader.read_u32()? as usize;
+            if count != parameters.len() {
+                return Err(Mp4Error::MissingEncryptionInfo);
+            }
+            for parameters in parameters {
+                let iv_size = if parameters.is_protected {
+                    parameters.per_sample_iv_size
+                } else {
+                    0
+                };
+                infos.push(AuxiliaryInfo::parse(
+                    &mut reader,
+                    iv_size,
+                    flags & 0x2 != 0,
+                )?);
+            }
+            return Ok(infos);
+        }
+
+        if let (Some(saiz), Some(saio)) = (saiz, saio) {
+            let (_, flags, mut saiz) = full_box(saiz)?;
+            if flags & 0x1 != 0 {
+                saiz.skip(8)?;
+            }
+            let default_size = saiz.read_u8()?;
+            let count = saiz.read_u32()? as usize;
+            if count != parameters.len() {
+                return Err(Mp4Error::MissingEncryptionInfo);
+            }
+
+            let (version, flags, mut saio) = full_box(saio)?;
+            if flags & 0x1 != 0 {
+                saio.skip(8)?;
+            }
+            if saio.read_u32()? == 0 {
+                return Err(Mp4Error::MissingEncryptionInfo);
+            }
+            let offset = if version == 0 {
+                saio.read_u32()?.into()
+            } else {
+                saio.read_u64()?
+            };
+
+            let start = base_offset
+                .checked_add(offset)
+                .and_then(|start| start.try_into().ok())
+                .ok_or(Mp4Error::Overflow)?;
+            let mut reader = ByteReader::new(data.get(start..).ok_or(Mp4Error::UnexpectedEnd)?);
+            for parameters in parameters {
+                let size = if default_size == 0 {
+                    saiz.read_u8()?
+                } else {
+                    default_size
+                };
+                let iv_size = if parameters.is_protected {
+                    parameters.per_sample_iv_size
+                } else {
+                    0
+                };
+                let mut sample_reader = ByteReader::new(reader.read(size.into())?);
+                infos.push(AuxiliaryInfo::parse(
+                    &mut sample_reader,
+                    iv_size,
+                    size > iv_size,
+                )?);
+            }
+            return Ok(infos);
+        }
+
+        // Without auxiliary information every protected sample must use a
+        // constant IV and be fully encrypted.
+        for parameters in parameters {
+            if parameters.is_protected && parameters.constant_iv.is_none() {
+                return Err(Mp4Error::MissingEncryptionInfo);
+            }
+            infos.push(AuxiliaryInfo::default());
+        }
+        Ok(infos)
+    }
+}
+
+fn sample_data<'a>(data: &'a [u8], raw: &RawSample) -> Result<&'a [u8], Mp4Error> {
+    let start: usize = raw
+        .offset
+        .try_into()
+        .map_err(|_| Mp4Error::SampleOutOfBounds)?;
+    let end = start
+        .checked_add(raw.size as usize)
+        .ok_or(Mp4Error::SampleOutOfBounds)?;
+    data.get(start..end).ok_or(Mp4Error::SampleOutOfBounds)
+}
+
+fn timestamp(raw: &RawSample, timescale: u32) -> Result<i64, Mp4Error> {
+    if timescale == 0 {
+        return Ok(0);
+    }
+    let time = i128::from(raw.decode_time) + i128::from(raw.composition_offset);
+    let time = time.max(0) * 1_000_000 / i128::from(timescale);
+    time.try_into().map_err(|_| Mp4Error::Overflow)
+}
+
+/// 8 byte IVs are extended with zeros to the 16 bytes the CDM expects.
+fn pad_iv(mut iv: Vec<u8>) -> Vec<u8> {
+    if iv.len() == 8 {
+        iv.resize(16, 0);
+    }
+    iv
+}
+
+#[cfg(test)]
+fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(box_type);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// A `moov` with a single encrypted video track 1, at 1000 ticks a second.
+#[cfg(test)]
+fn init_segment(scheme: &[u8; 4], tenc: &[u8]) -> Vec<u8> {
+    let schi = mp4_box(b"schi", &mp4_box(b"tenc", tenc));
+    let mut schm = vec![0; 4];
+    schm.extend_from_slice(scheme);
+    schm.extend_from_slice(&[0, 1, 0, 0]);
+    let sinf = [mp4_box(b"frma", b"avc1"), mp4_box(b"schm", &schm), schi].concat();
+    let mut encv = vec![0; 78];
+    encv.extend_from_slice(&mp4_box(b"sinf", &sinf));
+    let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+    stsd.extend_from_slice(&mp4_box(b"encv", &encv));
+    let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+    let mut mdhd = vec![0; 12];
+    mdhd.extend_from_slice(&1000u32.to_be_bytes());
+    mdhd.extend_from_slice(&[0; 8]);
+    let mdia = [mp4_box(b"mdhd", &mdhd), mp4_box(b"minf", &stbl)].concat();
+    let mut tkhd = vec![0; 12];
+    tkhd.extend_from_slice(&1u32.to_be_bytes());
+    let trak = [mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat();
+    mp4_box(b"moov", &mp4_box(b"trak", &trak))
+}
+
+#[test]
+fn test_cbcs_fragment_with_key_rotation() {
+    let key_id = [0x11; 16];
+    let rotated_key_id = [0x22; 16];
+    let iv = [0x33; 16];
+
+    let mut tenc = vec![1, 0, 0, 0, 0, 0x19, 1, 0];
+    tenc.extend_from_slice(&key_id);
+    tenc.push(16);
+    tenc.extend_from_slice(&iv);
+    let init = init_segment(b"cbcs", &tenc);
+
+    let tfhd = [0, 2, 0, 0, 0, 0, 0, 1];
+    let mut tfdt = vec![1, 0, 0, 0];
+    tfdt.extend_from_slice(&2000u64.to_be_bytes());
+    let mut sgpd = vec![1, 0, 0, 0];
+    sgpd.extend_from_slice(b"seig");
+    sgpd.extend_from_slice(&[0, 0, 0, 20, 0, 0, 0, 1, 0, 0x19, 1, 16]);
+    sgpd.extend_from_slice(&rotated_key_id);
+    let mut sbgp = vec![0, 0, 0, 0];
+    sbgp.extend_from_slice(b"seig");
+    sbgp.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 1]);
+    let mut senc = vec![0, 0, 0, 2, 0, 0, 0, 2, 0, 1, 0, 5, 0, 0, 0, 16];
+    senc.extend_from_slice(&[0x44; 16]);
+    senc.extend_from_slice(&[0, 1, 0, 3, 0, 0, 0, 16]);
+
+    let build_moof = |data_offset: u32| {
+        let mut trun = vec![0, 0, 3, 1, 0, 0, 0, 2];
+        trun.extend_from_slice(&data_offset.to_be_bytes());
+        trun.extend_from_slice(&[0, 0, 0, 10, 0, 0, 0, 21, 0, 0, 0, 10, 0, 0, 0, 19]);
+        let traf = [
+            mp4_box(b"tfhd", &tfhd),
+            mp4_box(b"tfdt", &tfdt),
+            mp4_box(b"trun", &trun),
+            mp4_box(b"sgpd", &sgpd),
+            mp4_box(b"sbgp", &sbgp),
+            mp4_box(b"senc", &senc),
+        ]
+        .concat();
+        mp4_box(b"moof", &mp4_box(b"traf", &traf))
+    };
+    let moof_size = build_moof(0).len() as u32;
+    let payload: Vec<u8> = (0..40).collect();
+    let media = [build_moof(moof_size + 8), mp4_box(b"mdat", &payload)].concat();
+
+    let init = InitSegment::parse(&init).unwrap();
+    let segment = MediaSegment::parse(&init, &media).unwrap();
+    assert_eq!(segment.samples.len(), 2);
+
+    let first = &segment.samples[0];
+    assert_eq!(first.data, &payload[..21]);
+    assert_eq!(first.timestamp, 2_000_000);
+    assert_eq!(first.encryption_scheme, EncryptionScheme::Cbcs);
+    assert_eq!(first.key_id, key_id);
+    assert_eq!(first.iv, iv);
+    assert_eq!(first.pattern.crypt_byte_block, 1);
+    assert_eq!(first.pattern.skip_byte_block, 9);
+    assert_eq!(
+        first.subsamples,
+        vec![SubsampleEntry {
+            clear_bytes: 5,
+            cipher_bytes: 16
+        }]
+    );
+
+    let second = &segment.samples[1];
+    assert_eq!(second.data, &payload[21..]);
+    assert_eq!(second.timestamp, 2_010_000);
+    assert_eq!(second.key_id, rotated_key_id);
+    assert_eq!(second.iv, [0x44; 16]);
+    assert_eq!(second.input_buffer().subsamples[0].clear_bytes, 3);
+}
+
+#[test]
+fn test_cenc_fragment_with_auxiliary_info() {
+    let key_id = [0x11; 16];
+    let mut tenc = vec![0, 0, 0, 0, 0, 0, 1, 8];
+    tenc.extend_from_slice(&key_id);
+    let init = InitSegment::parse(&init_segment(b"cenc", &tenc)).unwrap();
+
+    // One IV with a subsample, then an IV alone.
+    let mut auxiliary_info = vec![0x55; 8];
+    auxiliary_info.extend_from_slice(&[0, 1, 0, 2, 0, 0, 0, 10]);
+    auxiliary_info.extend_from_slice(&[0x66; 8]);
+    let payload: Vec<u8> = (0..32).collect();
+
+    let build_moof = |trun: &[u8], offset: u32| {
+        let tfhd = [0, 2, 0, 8, 0, 0, 0, 1, 0, 0, 0x03, 0xE8];
+        let saiz = [0, 0, 0, 0, 0, 0, 0, 0, 2, 16, 8];
+        let mut saio = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        saio.extend_from_slice(&offset.to_be_bytes());
+        let traf = [
+            mp4_box(b"tfhd", &tfhd),
+            mp4_box(b"trun", trun),
+            mp4_box(b"saiz", &saiz),
+            mp4_box(b"saio", &saio),
+        ]
+        .concat();
+        mp4_box(b"moof", &mp4_box(b"traf", &traf))
+    };
+    let trun = |data_offset: u32| {
+        let mut trun = vec![0, 0, 2, 1, 0, 0, 0, 2];
+        trun.extend_from_slice(&data_offset.to_be_bytes());
+        trun.extend_from_slice(&[0, 0, 0, 12, 0, 0, 0, 20]);
+        trun
+    };
+    let mdat_offset = build_moof(&trun(0), 0).len() as u32 + 8;
+    let moof = build_moof(&trun(mdat_offset + 24), mdat_offset);
+    let media = [
+        moof,
+        mp4_box(b"mdat", &[auxiliary_info, payload.clone()].concat()),
+    ]
+    .concat();
+
+    let segment = MediaSegment::parse(&init, &media).unwrap();
+    assert_eq!(segment.samples.len(), 2);
+    let first = &segment.samples[0];
+    assert_eq!(first.data, &payload[..12]);
+    assert_eq!(first.encryption_scheme, EncryptionScheme::Cenc);
+    assert_eq!(first.key_id, key_id);
+    assert_eq!(first.iv, [&[0x55; 8][..], &[0; 8]].concat());
+    assert_eq!(
+        first.subsamples,
+        vec![SubsampleEntry {
+            clear_bytes: 2,
+            cipher_bytes: 10
+        }]
+    );
+    let second = &segment.samples[1];
+    assert_eq!(second.data, &payload[12..]);
+    assert_eq!(second.timestamp, 1_000_000);
+    assert_eq!(second.iv, [&[0x66; 8][..], &[0; 8]].concat());
+    assert!(second.subsamples.is_empty());
+
+    // Four billion samples, none of which reads anything from the box.
+    let moof = build_moof(&[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF], mdat_offset);
+    assert_eq!(
+        MediaSegment::parse(&init, &moof).unwrap_err(),
+        Mp4Error::SampleOutOfBounds
+    );
+    // A data offset before the start of the file.
+    let moof = build_moof(
+        &[0, 0, 0, 1, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF],
+        mdat_offset,
+    );
+    assert_eq!(
+        MediaSegment::parse(&init, &moof).unwrap_err(),
+        Mp4Error::Overflow
+    );
+}
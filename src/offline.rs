@@ -0,0 +1,326 @@
+use crate::init_data::InitData;
+use crate::license::LicenseTransport;
+use crate::session::{SessionDriver, SessionError};
+use crate::types::{Expiration, InitDataType, SessionId, SessionType};
+use crate::{base64, LoadSessionError};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Kept next to the CDM's files. Names starting with an underscore are
+/// reserved for the host, so the CDM can't overwrite it.
+const INDEX_FILE: &str = "_offline_licenses.json";
+
+#[derive(Clone, Debug)]
+pub enum OfflineError {
+    Io(String),
+    InvalidIndex,
+    UnknownLicense(SessionId),
+    Session(SessionError),
+}
+
+impl From<SessionError> for OfflineError {
+    fn from(error: SessionError) -> Self {
+        OfflineError::Session(error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OfflineLicense {
+    pub session_id: SessionId,
+    pub init_data: InitData,
+    /// As of the last time the license was acquired or loaded.
+    pub expiration: Expiration,
+    /// Application data stored along the license.
+    pub metadata: Value,
+}
+
+impl OfflineLicense {
+    fn to_json(&self) -> Value {
+        let init_data_type = match self.init_data.init_data_type() {
+            InitDataType::Cenc => "cenc",
+            InitDataType::KeyIds => "keyids",
+            InitDataType::WebM => "webm",
+        };
+        let expiration = match self.expiration {
+            Expiration::Never => None,
+            Expiration::At(time) => time
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|duration| duration.as_secs()),
+        };
+        // Session IDs are stored in their display form, which is the ID
+        // itself for the ASCII ones CDMs use in practice.
+        json!({
+            "session_id": self.session_id.to_string(),
+            "init_data_type": init_data_type,
+            "init_data": base64::encode(&self.init_data.to_bytes()),
+            "expiration": expiration,
+            "metadata": self.metadata,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let init_data_type = match value["init_data_type"].as_str()? {
+            "cenc" => InitDataType::Cenc,
+            "keyids" => InitDataType::KeyIds,
+            "webm" => InitDataType::WebM,
+            _ => return None,
+        };
+        let init_data = base64::decode(value["init_data"].as_str()?).ok()?;
+        let expiration = match value["expiration"].as_u64() {
+            Some(seconds) => Expiration::At(UNIX_EPOCH.checked_add(Duration::from_secs(seconds))?),
+            None => Expiration::Never,
+        };
+        Some(Self {
+            session_id: value["session_id"].as_str()?.parse().ok()?,
+            init_data: InitData::parse(init_data_type, &init_data).ok()?,
+            expiration,
+            metadata: value["metadata"].clone(),
+        })
+    }
+}
+
+/// Acquires, lists, reloads and releases persistent licenses, keeping an
+/// index of their sessions in the CDM's storage directory.
+pub struct OfflineLicenseManager {
+    directory: PathBuf,
+    licenses: Vec<OfflineLicense>,
+}
+
+impl OfflineLicenseManager {
+    /// Opens the index of a storage directory. The directory is also given to
+    /// the CDM of every driver the manager is used with.
+    pub fn open<P: Into<PathBuf>>(directory: P) -> Result<Self, OfflineError> {
+        let directory = directory.into();
+        let licenses = match fs::read(directory.join(INDEX_FILE)) {
+            Ok(index) => {
+                let index: Value =
+                    serde_json::from_slice(&index).map_err(|_| OfflineError::InvalidIndex)?;
+                index
+                    .as_array()
+                    .ok_or(OfflineError::InvalidIndex)?
+                    .iter()
+                    .map(|license| {
+                        OfflineLicense::from_json(license).ok_or(OfflineError::InvalidIndex)
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(OfflineError::Io(error.to_string())),
+        };
+        Ok(Self {
+            directory,
+            licenses,
+        })
+    }
+
+    pub fn licenses(&self) -> &[OfflineLicense] {
+        &self.licenses
+    }
+
+    pub fn license(&self, session_id: &SessionId) -> Option<&OfflineLicense> {
+        self.licenses
+            .iter()
+            .find(|license| license.session_id == *session_id)
+    }
+
+    /// Requests a persistent license, records it and closes its session.
+    pub async fn acquire<T: LicenseTransport>(
+        &mut self,
+        driver: &mut SessionDriver<T>,
+        init_data: InitData,
+        metadata: Value,
+    ) -> Result<&OfflineLicense, OfflineError> {
+        driver.api().set_storage_directory(self.directory.clone());
+        let session_id = driver
+            .open_session(SessionType::PersistentLicense, init_data.clone())
+            .await?;
+        let license = OfflineLicense {
+            expiration: driver.expiration(&session_id).unwrap_or(Expiration::Never),
+            session_id,
+            init_data,
+            metadata,
+        };
+        driver.close_session(&license.session_id).await?;
+
+        self.licenses.push(license);
+        self.save()?;
+        Ok(self.licenses.last().unwrap())
+    }
+
+    /// Loads a stored license for playback. Its session stays open on the
+    /// driver.
+    pub async fn load<T: LicenseTransport>(
+        &mut self,
+        driver: &mut SessionDriver<T>,
+        session_id: &SessionId,
+    ) -> Result<(), OfflineError> {
+        if self.license(session_id).is_none() {
+            return Err(OfflineError::UnknownLicense(session_id.clone()));
+        }
+
+        driver.api().set_storage_directory(self.directory.clone());
+        let result = driver
+            .load_session(SessionType::PersistentLicense, session_id)
+            .await;
+        match result {
+            Ok(()) => {
+                let expiration = driver.expiration(session_id);
+                if let Some(license) = self.find_mut(session_id) {
+                    license.expiration = expiration.unwrap_or(license.expiration);
+                }
+                self.save()
+            }
+            // The CDM lost the license, so should the index.
+            Err(SessionError::LoadSession(LoadSessionError::NotFound)) => {
+                self.forget(session_id)?;
+                Err(OfflineError::UnknownLicense(session_id.clone()))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Releases a stored license with the license server and forgets it.
+    pub async fn release<T: LicenseTransport>(
+        &mut self,
+        driver: &mut SessionDriver<T>,
+        session_id: &SessionId,
+    ) -> Result<(), OfflineError> {
+        if self.license(session_id).is_none() {
+            return Err(OfflineError::UnknownLicense(session_id.clone()));
+        }
+        match self.load(driver, session_id).await {
+            Ok(()) => {}
+            // The CDM no longer has it, nothing left to release.
+            Err(OfflineError::UnknownLicense(_)) => return Ok(()),
+            Err(error) => return Err(error),
+        }
+        driver.release_session(session_id).await?;
+        self.forget(session_id)
+    }
+
+    fn find_mut(&mut self, session_id: &SessionId) -> Option<&mut OfflineLicense> {
+        self.licenses
+            .iter_mut()
+            .find(|license| license.session_id == *session_id)
+    }
+
+    fn forget(&mut self, session_id: &SessionId) -> Result<(), OfflineError> {
+        self.licenses
+            .retain(|license| license.session_id != *session_id);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), OfflineError> {
+        let io_error = |error: std::io::Error| OfflineError::Io(error.to_string());
+        let index: Vec<Value> = self.licenses.iter().map(OfflineLicense::to_json).collect();
+        let temporary = self.directory.join(format!("{}.tmp", INDEX_FILE));
+        fs::create_dir_all(&self.directory).map_err(io_error)?;
+        fs::write(&temporary, Value::Array(index).to_string()).map_err(io_error)?;
+        fs::rename(temporary, self.directory.join(INDEX_FILE)).map_err(io_error)
+    }
+}
+
+#[test]
+fn test_index_survives_reopening() {
+    let directory =
+        std::env::temp_dir().join(format!("widevine_rs-offline-{}", std::process::id()));
+    let expiration = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+    let mut manager = OfflineLicenseManager::open(&directory).unwrap();
+    assert!(manager.licenses().is_empty());
+    manager.licenses.push(OfflineLicense {
+        session_id: "persistent-1".into(),
+        init_data: InitData::webm(&[0x33; 16]),
+        expiration: Expiration::At(expiration),
+        metadata: json!({ "title": "Downloaded movie" }),
+    });
+    manager.save().unwrap();
+    // An expiration past what `SystemTime` holds makes the entry invalid.
+    let mut entry = manager.licenses[0].to_json();
+    entry["expiration"] = json!(u64::MAX);
+    assert!(OfflineLicense::from_json(&entry).is_none());
+
+    let mut reopened = OfflineLicenseManager::open(&directory).unwrap();
+    assert_eq!(reopened.licenses(), manager.licenses());
+    let license = reopened.license(&"persistent-1".into()).unwrap();
+    assert_eq!(license.metadata["title"], "Downloaded movie");
+    assert_eq!(license.expiration, Expiration::At(expiration));
+
+    reopened.forget(&"persistent-1".into()).unwrap();
+    assert!(OfflineLicenseManager::open(&directory)
+        .unwrap()
+        .licenses()
+        .is_empty());
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_offline_license_lifecycle() {
+    use crate::init_data::KeyIds;
+    use crate::license::CallbackTransport;
+    use crate::types::SessionMessage;
+    use crate::WidevineAPI;
+
+    let directory =
+        std::env::temp_dir().join(format!("widevine_rs-offline-mock-{}", std::process::id()));
+    let driver = || {
+        let api = WidevineAPI::initialize_with_library(crate::library::mock_cdm()).unwrap();
+        SessionDriver::new(
+            api,
+            CallbackTransport::new(|message: SessionMessage| async move {
+                let request: Value = serde_json::from_slice(&message.content).unwrap();
+                let keys: Vec<Value> = request["kids"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|key_id| json!({ "kty": "oct", "kid": key_id, "k": base64::encode_url(&[0x44; 16]) }))
+                    .collect();
+                let license = json!({ "keys": keys, "expiration": 4_000_000_000u64 });
+                Ok(license.to_string().into_bytes())
+            }),
+        )
+    };
+
+    let mut driver_1 = driver();
+    driver_1.api().initialize_cdm().await.unwrap();
+    let mut manager = OfflineLicenseManager::open(&directory).unwrap();
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x33; 16]));
+    let session_id = manager
+        .acquire(
+            &mut driver_1,
+            init_data,
+            json!({ "title": "Downloaded movie" }),
+        )
+        .await
+        .unwrap()
+        .session_id
+        .clone();
+    let expiration = UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+    assert_eq!(
+        manager.license(&session_id).unwrap().expiration,
+        Expiration::At(expiration)
+    );
+
+    // Loaded by another CDM instance, from storage.
+    let mut driver_2 = driver();
+    driver_2.api().initialize_cdm().await.unwrap();
+    let mut manager = OfflineLicenseManager::open(&directory).unwrap();
+    manager.load(&mut driver_2, &session_id).await.unwrap();
+    assert_eq!(
+        driver_2.keys(&session_id).unwrap()[0].key_id,
+        vec![0x33; 16]
+    );
+    driver_2.close_session(&session_id).await.unwrap();
+
+    manager.release(&mut driver_2, &session_id).await.unwrap();
+    assert!(manager.licenses().is_empty());
+    assert!(matches!(
+        manager.load(&mut driver_2, &session_id).await,
+        Err(OfflineError::UnknownLicense(_))
+    ));
+    fs::remove_dir_all(directory).unwrap();
+}
@@ -1,22 +1,26 @@
-use crate::types::Exception;
+use crate::types::{Exception, SessionId};
+use crate::{CdmError, HostError, Operation, UnsettledPromise};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::task::{Context, Poll, Waker};
+use std::time::Instant;
 
 #[derive(Clone, Debug)]
 pub enum PromiseResult {
     Resolved(PromiseResultData),
     Rejected(RejectionInfo),
+    Failed(HostError),
 }
 
 impl PromiseResult {
-    pub fn as_result(self) -> Result<PromiseResultData, RejectionInfo> {
+    pub fn into_result(self) -> Result<PromiseResultData, CdmError> {
         match self {
             PromiseResult::Resolved(data) => Ok(data),
-            PromiseResult::Rejected(info) => Err(info),
+            PromiseResult::Rejected(info) => Err(CdmError::Rejected(info)),
+            PromiseResult::Failed(error) => Err(CdmError::HostFailed(error)),
         }
     }
 }
@@ -25,7 +29,7 @@ impl PromiseResult {
 pub enum PromiseResultData {
     None,
     Initialized(bool),
-    NewSession(String),
+    NewSession(SessionId),
 }
 
 #[derive(Clone, Debug)]
@@ -39,44 +43,107 @@ pub const INITIALIZED_PROMISE_ID: usize = 0;
 
 pub struct PromiseSet {
     next: usize,
-    stored: HashSet<usize>,
 }
 
 // TODO: starting at one for init promise, kinda hackish
 impl Default for PromiseSet {
     fn default() -> Self {
-        Self {
-            next: 1,
-            stored: HashSet::new(),
-        }
+        Self { next: 1 }
     }
 }
 
 impl PromiseSet {
     pub fn create(&mut self) -> usize {
         let id = self.next;
-        self.stored.insert(id);
         self.next += 1;
         id
     }
+}
 
-    pub fn pop(&mut self, id: usize) -> bool {
-        self.stored.remove(&id)
-    }
+#[derive(Debug)]
+struct PendingPromise {
+    operation: Operation,
+    created: Instant,
 }
 
 #[derive(Default, Debug)]
 pub struct PromiseManager {
     pub finished_promises: HashMap<usize, PromiseResult>,
     pub on_finished_promises: HashMap<usize, Waker>,
+    /// Settles every promise left, as the CDM may never get to them.
+    pub fatal_error: Option<HostError>,
+    /// Promises the CDM hasn't settled yet.
+    pending: HashMap<usize, PendingPromise>,
+    /// Pending promises nobody waits for anymore, whose results are dropped.
+    cancelled: HashSet<usize>,
 }
 
 impl PromiseManager {
+    /// Tracks a promise until the CDM settles it. Has to be called before
+    /// the promise is handed to the CDM, which may settle it right away.
+    pub fn register(&mut self, id: usize, operation: Operation) {
+        let promise = PendingPromise {
+            operation,
+            created: Instant::now(),
+        };
+        self.pending.insert(id, promise);
+        self.cancelled.remove(&id);
+    }
+
+    /// Records the result of a promise and wakes its future.
+    pub fn settle(&mut self, id: usize, result: PromiseResult) {
+        self.pending.remove(&id);
+        if self.cancelled.remove(&id) {
+            return;
+        }
+        self.finished_promises.insert(id, result);
+        self.wake(id);
+    }
+
+    /// Forgets the future of a promise, e.g. after it timed out.
+    pub fn cancel(&mut self, id: usize) {
+        self.on_finished_promises.remove(&id);
+        if self.finished_promises.remove(&id).is_none() && self.pending.contains_key(&id) {
+            self.cancelled.insert(id);
+        }
+    }
+
     pub fn wake(&mut self, id: usize) {
         if let Some(waker) = self.on_finished_promises.remove(&id) {
             waker.wake();
         }
     }
+
+    /// The promises the CDM never settled, oldest first.
+    pub fn unsettled(&self) -> Vec<UnsettledPromise> {
+        let mut promises: Vec<UnsettledPromise> = self
+            .pending
+            .iter()
+            .map(|(&id, promise)| UnsettledPromise {
+                id,
+                operation: promise.operation,
+                age: promise.created.elapsed(),
+                cancelled: self.cancelled.contains(&id),
+            })
+            .collect();
+        promises.sort_by_key(|promise| promise.id);
+        promises
+    }
+
+    /// Records the first fatal error and wakes all the pending promises.
+    pub fn fail(&mut self, error: HostError) {
+        self.fatal_error.get_or_insert(error);
+        for (_, waker) in self.on_finished_promises.drain() {
+            waker.wake();
+        }
+    }
+}
+
+/// A panic while the lock was held is recorded as a fatal error by the
+/// callback that caught it, so the promises are settled rather than the
+/// poisoning propagated.
+pub fn lock(manager: &Mutex<PromiseManager>) -> MutexGuard<'_, PromiseManager> {
+    manager.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 pub struct FuturePromise {
@@ -84,13 +151,21 @@ pub struct FuturePromise {
     pub id: usize,
 }
 
+impl Drop for FuturePromise {
+    fn drop(&mut self) {
+        lock(&self.host).cancel(self.id);
+    }
+}
+
 impl Future for FuturePromise {
     type Output = PromiseResult;
 
     fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
-        let mut host = self.host.lock().unwrap();
+        let mut host = lock(&self.host);
         if let Some(value) = host.finished_promises.remove(&self.id) {
             Poll::Ready(value)
+        } else if let Some(ref error) = host.fatal_error {
+            Poll::Ready(PromiseResult::Failed(error.clone()))
         } else {
             host.on_finished_promises
                 .insert(self.id, context.waker().clone());
@@ -98,3 +173,37 @@ impl Future for FuturePromise {
         }
     }
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_promise_cleanup() {
+    use std::time::Duration;
+
+    let manager = Arc::new(Mutex::new(PromiseManager::default()));
+    let future = |id| FuturePromise {
+        host: manager.clone(),
+        id,
+    };
+    lock(&manager).register(1, Operation::CloseSession);
+    lock(&manager).register(2, Operation::UpdateSession);
+
+    // Polled once, then dropped before the CDM settles it.
+    let timeout = tokio::time::timeout(Duration::from_millis(1), future(1));
+    assert!(timeout.await.is_err());
+    lock(&manager).settle(1, PromiseResult::Resolved(PromiseResultData::None));
+    {
+        let manager = lock(&manager);
+        assert!(manager.on_finished_promises.is_empty());
+        assert!(manager.finished_promises.is_empty());
+    }
+
+    let unsettled = lock(&manager).unsettled();
+    assert_eq!(unsettled.len(), 1);
+    assert_eq!(unsettled[0].operation, Operation::UpdateSession);
+
+    lock(&manager).fail(HostError::Dropped);
+    assert!(matches!(
+        future(2).await,
+        PromiseResult::Failed(HostError::Dropped)
+    ));
+}
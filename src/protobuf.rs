@@ -0,0 +1,197 @@
+use std::convert::TryInto;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    VarintOverflow,
+    UnsupportedWireType(u8),
+    InvalidString,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WireType {
+    Varint,
+    Fixed64,
+    LengthDelimited,
+    Fixed32,
+}
+
+impl WireType {
+    fn from_raw(raw: u8) -> Result<Self, DecodeError> {
+        match raw {
+            0 => Ok(WireType::Varint),
+            1 => Ok(WireType::Fixed64),
+            2 => Ok(WireType::LengthDelimited),
+            5 => Ok(WireType::Fixed32),
+            _ => Err(DecodeError::UnsupportedWireType(raw)),
+        }
+    }
+
+    fn raw(self) -> u8 {
+        match self {
+            WireType::Varint => 0,
+            WireType::Fixed64 => 1,
+            WireType::LengthDelimited => 2,
+            WireType::Fixed32 => 5,
+        }
+    }
+}
+
+/// A single decoded field. Length-delimited values borrow from the input so
+/// nested messages can be decoded without copying.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Varint(value) | Value::Fixed64(value) => Some(value),
+            Value::Fixed32(value) => Some(value.into()),
+            Value::Bytes(_) => None,
+        }
+    }
+
+    /// `int64` fields, where negative values take ten bytes.
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_u64().map(|value| value as i64)
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        self.as_u64().and_then(|value| value.try_into().ok())
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Result<String, DecodeError> {
+        let bytes = self.as_bytes().ok_or(DecodeError::InvalidString)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+}
+
+/// Iterates over the top-level fields of a protobuf message.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut result: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or(DecodeError::UnexpectedEnd)?;
+            self.position += 1;
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(DecodeError::VarintOverflow)
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let slice = self
+            .data
+            .get(self.position..end)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_field(&mut self) -> Result<(u32, Value<'a>), DecodeError> {
+        let key = self.read_varint()?;
+        let wire_type = WireType::from_raw((key & 0x7) as u8)?;
+        let number = (key >> 3) as u32;
+        let value = match wire_type {
+            WireType::Varint => Value::Varint(self.read_varint()?),
+            WireType::Fixed64 => {
+                let bytes = self.read_slice(8)?;
+                Value::Fixed64(u64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            WireType::LengthDelimited => {
+                let length = self.read_varint()?;
+                let length = length.try_into().map_err(|_| DecodeError::UnexpectedEnd)?;
+                Value::Bytes(self.read_slice(length)?)
+            }
+            WireType::Fixed32 => {
+                let bytes = self.read_slice(4)?;
+                Value::Fixed32(u32::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        };
+        Ok((number, value))
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<(u32, Value<'a>), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.data.len() {
+            return None;
+        }
+        let field = self.read_field();
+        if field.is_err() {
+            // Stop after the first error, the rest of the input can't be trusted.
+            self.position = self.data.len();
+        }
+        Some(field)
+    }
+}
+
+/// Appends protobuf fields to a byte buffer. Fields are written in the order
+/// the methods are called in.
+#[derive(Default, Debug)]
+pub struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buffer.push(value as u8);
+    }
+
+    fn write_key(&mut self, number: u32, wire_type: WireType) {
+        self.write_varint(u64::from(number) << 3 | u64::from(wire_type.raw()));
+    }
+
+    pub fn varint(&mut self, number: u32, value: u64) -> &mut Self {
+        self.write_key(number, WireType::Varint);
+        self.write_varint(value);
+        self
+    }
+
+    pub fn bytes(&mut self, number: u32, value: &[u8]) -> &mut Self {
+        self.write_key(number, WireType::LengthDelimited);
+        self.write_varint(value.len() as u64);
+        self.buffer.extend_from_slice(value);
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(feature = "http")]
+use crate::license::HttpTransport;
+use crate::license::LicenseTransport;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ProvisioningState {
+    /// No session has produced a message yet.
+    #[default]
+    Unknown,
+    /// The CDM sent an individualization request that is being answered.
+    InProgress,
+    Provisioned,
+    Failed,
+}
+
+/// Answers the `IndividualizationRequest` messages a fresh CDM sends before
+/// its first license request.
+pub struct ProvisioningHandler {
+    transport: Box<dyn LicenseTransport>,
+}
+
+impl ProvisioningHandler {
+    pub fn new<T: LicenseTransport + 'static>(transport: T) -> Self {
+        Self {
+            transport: Box::new(transport),
+        }
+    }
+
+    /// POSTs the raw requests to a provisioning server.
+    #[cfg(feature = "http")]
+    pub fn with_url(url: &str) -> Self {
+        Self::new(HttpTransport::new(url))
+    }
+
+    pub(crate) fn transport(&self) -> &dyn LicenseTransport {
+        &*self.transport
+    }
+}
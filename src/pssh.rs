@@ -0,0 +1,333 @@
+use crate::byte_reader::{ByteReader, UnexpectedEnd};
+pub use crate::protobuf::DecodeError;
+use crate::protobuf::{Reader, Writer};
+
+/// `edef8ba9-79d6-4ace-a3c8-27dcd51d21ed`
+pub const WIDEVINE_SYSTEM_ID: [u8; 16] = [
+    0xed, 0xef, 0x8b, 0xa9, 0x79, 0xd6, 0x4a, 0xce, 0xa3, 0xc8, 0x27, 0xdc, 0xd5, 0x1d, 0x21, 0xed,
+];
+
+/// `1077efec-c0b2-4d02-ace3-3c1e52e2fb4b`, the W3C common system ID used for
+/// ClearKey.
+pub const CLEARKEY_SYSTEM_ID: [u8; 16] = [
+    0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02, 0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb, 0x4b,
+];
+
+const PSSH_BOX_TYPE: &[u8; 4] = b"pssh";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PsshError {
+    UnexpectedEnd,
+    InvalidBoxType([u8; 4]),
+    InvalidBoxSize(u32),
+    UnsupportedVersion(u8),
+    NotWidevine([u8; 16]),
+    InvalidData(DecodeError),
+}
+
+impl From<UnexpectedEnd> for PsshError {
+    fn from(_: UnexpectedEnd) -> Self {
+        PsshError::UnexpectedEnd
+    }
+}
+
+impl From<DecodeError> for PsshError {
+    fn from(error: DecodeError) -> Self {
+        PsshError::InvalidData(error)
+    }
+}
+
+/// A `pssh` box as found in ISO-BMFF init segments, DASH manifests and
+/// `cenc` init data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PsshBox {
+    pub version: u8,
+    pub system_id: [u8; 16],
+    /// Only serialized in version 1 boxes.
+    pub key_ids: Vec<[u8; 16]>,
+    pub data: Vec<u8>,
+}
+
+impl PsshBox {
+    /// Creates a version 0 Widevine `pssh` box carrying `data`.
+    pub fn widevine(data: &WidevinePsshData) -> Self {
+        Self {
+            version: 0,
+            system_id: WIDEVINE_SYSTEM_ID,
+            key_ids: Vec::new(),
+            data: data.encode(),
+        }
+    }
+
+    pub fn is_widevine(&self) -> bool {
+        self.system_id == WIDEVINE_SYSTEM_ID
+    }
+
+    /// Parses a single box, returning it along with the number of bytes read.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), PsshError> {
+        let mut reader = ByteReader::new(data);
+        let size = reader.read_u32()?;
+        let box_type: [u8; 4] = reader.read_array()?;
+        if &box_type != PSSH_BOX_TYPE {
+            return Err(PsshError::InvalidBoxType(box_type));
+        }
+        if (size as usize) > data.len() || size < 32 {
+            return Err(PsshError::InvalidBoxSize(size));
+        }
+
+        let mut reader = ByteReader::new(&data[8..size as usize]);
+        let version = reader.read_u8()?;
+        if version > 1 {
+            return Err(PsshError::UnsupportedVersion(version));
+        }
+        reader.skip(3)?;
+        let system_id = reader.read_array()?;
+
+        let mut key_ids = Vec::new();
+        if version == 1 {
+            let count = reader.read_u32()?;
+            for _ in 0..count {
+                key_ids.push(reader.read_array()?);
+            }
+        }
+
+        let data_size = reader.read_u32()? as usize;
+        let data = reader.read(data_size)?.to_vec();
+
+        let pssh = Self {
+            version,
+            system_id,
+            key_ids,
+            data,
+        };
+        Ok((pssh, size as usize))
+    }
+
+    /// Parses every box in a buffer of concatenated `pssh` boxes, which is
+    /// what `InitDataType::Cenc` init data is made of.
+    pub fn parse_all(mut data: &[u8]) -> Result<Vec<Self>, PsshError> {
+        let mut boxes = Vec::new();
+        while !data.is_empty() {
+            let (pssh, size) = Self::parse(data)?;
+            boxes.push(pssh);
+            data = &data[size..];
+        }
+        Ok(boxes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut size = 32 + self.data.len();
+        if self.version == 1 {
+            size += 4 + 16 * self.key_ids.len();
+        }
+
+        let mut bytes = Vec::with_capacity(size);
+        bytes.extend_from_slice(&(size as u32).to_be_bytes());
+        bytes.extend_from_slice(PSSH_BOX_TYPE);
+        bytes.extend_from_slice(&[self.version, 0, 0, 0]);
+        bytes.extend_from_slice(&self.system_id);
+        if self.version == 1 {
+            bytes.extend_from_slice(&(self.key_ids.len() as u32).to_be_bytes());
+            for key_id in &self.key_ids {
+                bytes.extend_from_slice(key_id);
+            }
+        }
+        bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn widevine_data(&self) -> Result<WidevinePsshData, PsshError> {
+        if !self.is_widevine() {
+            return Err(PsshError::NotWidevine(self.system_id));
+        }
+        WidevinePsshData::decode(&self.data)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Algorithm {
+    Unencrypted,
+    AesCtr,
+    Unknown(u32),
+}
+
+impl From<u32> for Algorithm {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Algorithm::Unencrypted,
+            1 => Algorithm::AesCtr,
+            _ => Algorithm::Unknown(value),
+        }
+    }
+}
+
+impl From<Algorithm> for u32 {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Unencrypted => 0,
+            Algorithm::AesCtr => 1,
+            Algorithm::Unknown(value) => value,
+        }
+    }
+}
+
+/// Protection scheme of the content, stored as a big-endian four character
+/// code in the PSSH data.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProtectionScheme {
+    Cenc,
+    Cbc1,
+    Cens,
+    Cbcs,
+    Unknown(u32),
+}
+
+impl From<u32> for ProtectionScheme {
+    fn from(value: u32) -> Self {
+        match &value.to_be_bytes() {
+            b"cenc" => ProtectionScheme::Cenc,
+            b"cbc1" => ProtectionScheme::Cbc1,
+            b"cens" => ProtectionScheme::Cens,
+            b"cbcs" => ProtectionScheme::Cbcs,
+            _ => ProtectionScheme::Unknown(value),
+        }
+    }
+}
+
+impl From<ProtectionScheme> for u32 {
+    fn from(scheme: ProtectionScheme) -> Self {
+        match scheme {
+            ProtectionScheme::Cenc => u32::from_be_bytes(*b"cenc"),
+            ProtectionScheme::Cbc1 => u32::from_be_bytes(*b"cbc1"),
+            ProtectionScheme::Cens => u32::from_be_bytes(*b"cens"),
+            ProtectionScheme::Cbcs => u32::from_be_bytes(*b"cbcs"),
+            ProtectionScheme::Unknown(value) => value,
+        }
+    }
+}
+
+/// The `WidevinePsshData` protobuf message carried in the data field of a
+/// Widevine `pssh` box. Unknown fields are skipped when decoding.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WidevinePsshData {
+    pub algorithm: Option<Algorithm>,
+    pub key_ids: Vec<Vec<u8>>,
+    pub provider: Option<String>,
+    pub content_id: Option<Vec<u8>>,
+    pub track_type: Option<String>,
+    pub policy: Option<String>,
+    pub crypto_period_index: Option<u32>,
+    pub grouped_license: Option<Vec<u8>>,
+    pub protection_scheme: Option<ProtectionScheme>,
+    pub crypto_period_seconds: Option<u32>,
+}
+
+impl WidevinePsshData {
+    /// Builds the PSSH data for a content ID and its key IDs, for manifests
+    /// that don't carry a PSSH of their own.
+    pub fn new(content_id: &[u8], key_ids: &[&[u8]]) -> Self {
+        Self {
+            key_ids: key_ids.iter().map(|key_id| key_id.to_vec()).collect(),
+            content_id: Some(content_id.to_vec()),
+            ..Self::default()
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, PsshError> {
+        let mut pssh_data = Self::default();
+        for field in Reader::new(data) {
+            let (number, value) = field?;
+            match number {
+                1 => pssh_data.algorithm = value.as_u32().map(Algorithm::from),
+                2 => pssh_data
+                    .key_ids
+                    .extend(value.as_bytes().map(|bytes| bytes.to_vec())),
+                3 => pssh_data.provider = Some(value.as_string()?),
+                4 => pssh_data.content_id = value.as_bytes().map(|bytes| bytes.to_vec()),
+                5 => pssh_data.track_type = Some(value.as_string()?),
+                6 => pssh_data.policy = Some(value.as_string()?),
+                7 => pssh_data.crypto_period_index = value.as_u32(),
+                8 => pssh_data.grouped_license = value.as_bytes().map(|bytes| bytes.to_vec()),
+                9 => pssh_data.protection_scheme = value.as_u32().map(ProtectionScheme::from),
+                10 => pssh_data.crypto_period_seconds = value.as_u32(),
+                _ => {}
+            }
+        }
+        Ok(pssh_data)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        if let Some(algorithm) = self.algorithm {
+            writer.varint(1, u32::from(algorithm).into());
+        }
+        for key_id in &self.key_ids {
+            writer.bytes(2, key_id);
+        }
+        if let Some(ref provider) = self.provider {
+            writer.bytes(3, provider.as_bytes());
+        }
+        if let Some(ref content_id) = self.content_id {
+            writer.bytes(4, content_id);
+        }
+        if let Some(ref track_type) = self.track_type {
+            writer.bytes(5, track_type.as_bytes());
+        }
+        if let Some(ref policy) = self.policy {
+            writer.bytes(6, policy.as_bytes());
+        }
+        if let Some(index) = self.crypto_period_index {
+            writer.varint(7, index.into());
+        }
+        if let Some(ref grouped_license) = self.grouped_license {
+            writer.bytes(8, grouped_license);
+        }
+        if let Some(scheme) = self.protection_scheme {
+            writer.varint(9, u32::from(scheme).into());
+        }
+        if let Some(seconds) = self.crypto_period_seconds {
+            writer.varint(10, seconds.into());
+        }
+        writer.into_bytes()
+    }
+
+    /// Wraps the data in a Widevine `pssh` box, ready to be used as
+    /// `InitDataType::Cenc` init data.
+    pub fn to_init_data(&self) -> Vec<u8> {
+        PsshBox::widevine(self).to_bytes()
+    }
+}
+
+#[test]
+fn test_widevine_pssh_round_trip() {
+    let mut data = WidevinePsshData::new(b"content-1", &[&[0x11; 16], &[0x22; 16]]);
+    data.provider = Some("widevine_test".to_string());
+    data.protection_scheme = Some(ProtectionScheme::Cbcs);
+
+    let init_data = data.to_init_data();
+    let boxes = PsshBox::parse_all(&init_data).unwrap();
+    assert_eq!(boxes.len(), 1);
+    assert!(boxes[0].is_widevine());
+    assert_eq!(boxes[0].widevine_data().unwrap(), data);
+}
+
+#[test]
+fn test_widevine_pssh_decode() {
+    // Version 0 box with one key ID, a provider and a content ID.
+    let pssh = [
+        0x00, 0x00, 0x00, 0x42, 0x70, 0x73, 0x73, 0x68, 0x00, 0x00, 0x00, 0x00, 0xed, 0xef, 0x8b,
+        0xa9, 0x79, 0xd6, 0x4a, 0xce, 0xa3, 0xc8, 0x27, 0xdc, 0xd5, 0x1d, 0x21, 0xed, 0x00, 0x00,
+        0x00, 0x22, 0x08, 0x01, 0x12, 0x10, 0xab, 0xba, 0x27, 0x1e, 0x8b, 0xcf, 0x55, 0x2b, 0xbd,
+        0x2e, 0x86, 0xa4, 0x34, 0xa9, 0xa5, 0xd9, 0x1a, 0x08, 0x77, 0x69, 0x64, 0x65, 0x76, 0x69,
+        0x6e, 0x65, 0x22, 0x02, 0x61, 0x62,
+    ];
+    let (pssh, size) = PsshBox::parse(&pssh).unwrap();
+    assert_eq!(size, 0x42);
+    let data = pssh.widevine_data().unwrap();
+    assert_eq!(data.algorithm, Some(Algorithm::AesCtr));
+    assert_eq!(data.key_ids.len(), 1);
+    assert_eq!(data.key_ids[0][0], 0xab);
+    assert_eq!(data.provider.as_deref(), Some("widevine"));
+    assert_eq!(data.content_id.as_deref(), Some(&b"ab"[..]));
+}
@@ -0,0 +1,289 @@
+use crate::inspector::LicensePolicy;
+use crate::license::{CallbackTransport, LicenseTransport, TransportError};
+use crate::promise_set::RejectionInfo;
+use crate::types::{Expiration, SessionId, SessionMessage};
+use crate::{CdmError, HostError, Operation};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Clone, Debug)]
+pub enum RenewalError {
+    Transport(TransportError),
+    Rejected(RejectionInfo),
+    HostFailed(HostError),
+    TimedOut(Operation),
+}
+
+impl From<CdmError> for RenewalError {
+    fn from(error: CdmError) -> Self {
+        match error {
+            CdmError::Rejected(info) => RenewalError::Rejected(info),
+            CdmError::HostFailed(error) => RenewalError::HostFailed(error),
+            CdmError::TimedOut(operation) => RenewalError::TimedOut(operation),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenewalPolicy {
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after each failed attempt. The
+    /// license's `renewal_retry_interval` takes precedence.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Renewal is given up this long before the license expires, so the
+    /// application still has time to react.
+    pub expiration_margin: Duration,
+}
+
+impl Default for RenewalPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            expiration_margin: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PendingRenewal {
+    pub session_id: SessionId,
+    pub message: SessionMessage,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Schedules the `LicenseRenewal` messages of a `SessionDriver`, retrying
+/// failed renewals with exponential backoff.
+pub struct RenewalManager {
+    policy: RenewalPolicy,
+    callback: Option<Box<dyn LicenseTransport>>,
+    pending: Vec<PendingRenewal>,
+    deadlines: HashMap<SessionId, Instant>,
+    license_deadlines: HashMap<SessionId, Instant>,
+    renewal_starts: HashMap<SessionId, Instant>,
+    retry_intervals: HashMap<SessionId, Duration>,
+}
+
+impl Default for RenewalManager {
+    fn default() -> Self {
+        Self::new(RenewalPolicy::default())
+    }
+}
+
+impl RenewalManager {
+    pub fn new(policy: RenewalPolicy) -> Self {
+        Self {
+            policy,
+            callback: None,
+            pending: Vec::new(),
+            deadlines: HashMap::new(),
+            license_deadlines: HashMap::new(),
+            renewal_starts: HashMap::new(),
+            retry_intervals: HashMap::new(),
+        }
+    }
+
+    /// Sends renewals through `callback` instead of the driver's transport.
+    pub fn with_callback<F, R>(mut self, callback: F) -> Self
+    where
+        F: Fn(SessionMessage) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<Vec<u8>, TransportError>> + Send + 'static,
+    {
+        self.callback = Some(Box::new(CallbackTransport::new(callback)));
+        self
+    }
+
+    pub(crate) fn callback(&self) -> Option<&dyn LicenseTransport> {
+        self.callback.as_deref()
+    }
+
+    /// Queues a renewal, replacing any renewal still pending for the session.
+    /// It is sent right away unless the license's `renewal_delay` hasn't
+    /// passed yet.
+    pub(crate) fn schedule(
+        &mut self,
+        session_id: &SessionId,
+        message: SessionMessage,
+        now: Instant,
+    ) {
+        self.pending
+            .retain(|renewal| renewal.session_id != *session_id);
+        self.pending.push(PendingRenewal {
+            session_id: session_id.clone(),
+            message,
+            attempts: 0,
+            next_attempt: self
+                .renewal_starts
+                .get(session_id)
+                .map_or(now, |start| now.max(*start)),
+        });
+    }
+
+    pub(crate) fn set_expiration(&mut self, session_id: &SessionId, expiration: Expiration) {
+        let time = match expiration {
+            Expiration::At(time) => time,
+            Expiration::Never => {
+                self.deadlines.remove(session_id);
+                return;
+            }
+        };
+
+        let remaining = time.duration_since(SystemTime::now()).unwrap_or_default();
+        let deadline = Instant::now() + remaining.saturating_sub(self.policy.expiration_margin);
+        self.deadlines.insert(session_id.clone(), deadline);
+    }
+
+    /// Plans the session's renewals from the policy of a license applied at
+    /// `now`: renewals wait for its `renewal_delay`, are retried every
+    /// `renewal_retry_interval` and are given up before its
+    /// `license_duration` runs out, whichever of that and the CDM's
+    /// expiration comes first.
+    pub(crate) fn set_policy(
+        &mut self,
+        session_id: &SessionId,
+        policy: &LicensePolicy,
+        now: Instant,
+    ) {
+        match policy.renewal_retry_interval {
+            Some(interval) => self.retry_intervals.insert(session_id.clone(), interval),
+            None => self.retry_intervals.remove(session_id),
+        };
+        match policy.renewal_delay {
+            Some(delay) => self.renewal_starts.insert(session_id.clone(), now + delay),
+            None => self.renewal_starts.remove(session_id),
+        };
+        match policy.license_duration {
+            Some(duration) => self.license_deadlines.insert(
+                session_id.clone(),
+                now + duration.saturating_sub(self.policy.expiration_margin),
+            ),
+            None => self.license_deadlines.remove(session_id),
+        };
+    }
+
+    pub(crate) fn remove(&mut self, session_id: &SessionId) {
+        self.pending
+            .retain(|renewal| renewal.session_id != *session_id);
+        self.deadlines.remove(session_id);
+        self.license_deadlines.remove(session_id);
+        self.renewal_starts.remove(session_id);
+        self.retry_intervals.remove(session_id);
+    }
+
+    /// Takes the renewals whose next attempt is due.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<PendingRenewal> {
+        let (due, pending) = self
+            .pending
+            .drain(..)
+            .partition(|renewal| renewal.next_attempt <= now);
+        self.pending = pending;
+        due
+    }
+
+    /// Reschedules a failed renewal. Returns `false` when it was given up,
+    /// either after too many attempts or because the next one would come
+    /// too close to the expiration.
+    pub(crate) fn retry(&mut self, mut renewal: PendingRenewal, now: Instant) -> bool {
+        renewal.attempts += 1;
+        if renewal.attempts >= self.policy.max_attempts {
+            return false;
+        }
+
+        let exponent = (renewal.attempts - 1).min(16);
+        let backoff = self
+            .retry_intervals
+            .get(&renewal.session_id)
+            .unwrap_or(&self.policy.initial_backoff)
+            .saturating_mul(1 << exponent)
+            .min(self.policy.max_backoff);
+        renewal.next_attempt = now + backoff;
+        let mut deadlines = (self.deadlines.get(&renewal.session_id).into_iter())
+            .chain(self.license_deadlines.get(&renewal.session_id));
+        if deadlines.any(|deadline| renewal.next_attempt > *deadline) {
+            return false;
+        }
+
+        self.pending.push(renewal);
+        true
+    }
+}
+
+#[test]
+fn test_backoff_stops_before_expiration() {
+    use crate::types::MessageType;
+
+    let mut manager = RenewalManager::default();
+    let message = SessionMessage {
+        message_type: MessageType::LicenseRenewal,
+        content: vec![1, 2, 3],
+    };
+    let expiration = Expiration::At(SystemTime::now() + Duration::from_secs(40));
+    manager.set_expiration(&"session".into(), expiration);
+
+    let start = Instant::now();
+    manager.schedule(&"session".into(), message, start);
+    let mut now = start;
+    let mut attempts = Vec::new();
+    loop {
+        let mut due = manager.take_due(now);
+        assert!(manager.take_due(now).is_empty());
+        match due.pop() {
+            Some(renewal) => {
+                attempts.push(now - start);
+                if !manager.retry(renewal, now) {
+                    break;
+                }
+            }
+            None => now += Duration::from_millis(500),
+        }
+    }
+
+    // Retries after 1, 2 and 4 seconds; the next one would land after the
+    // 10 second deadline left by the 30 second margin.
+    let seconds: Vec<u64> = attempts.iter().map(Duration::as_secs).collect();
+    assert_eq!(seconds, vec![0, 1, 3, 7]);
+}
+
+#[test]
+fn test_policy_plans_renewals() {
+    use crate::types::MessageType;
+
+    let mut manager = RenewalManager::default();
+    let message = SessionMessage {
+        message_type: MessageType::LicenseRenewal,
+        content: vec![1, 2, 3],
+    };
+    let policy = LicensePolicy {
+        license_duration: Some(Duration::from_secs(50)),
+        renewal_delay: Some(Duration::from_secs(10)),
+        renewal_retry_interval: Some(Duration::from_secs(2)),
+        ..LicensePolicy::default()
+    };
+    let start = Instant::now();
+    manager.set_policy(&"session".into(), &policy, start);
+
+    manager.schedule(&"session".into(), message, start);
+    let mut now = start;
+    let mut attempts = Vec::new();
+    loop {
+        match manager.take_due(now).pop() {
+            Some(renewal) => {
+                attempts.push(now - start);
+                if !manager.retry(renewal, now) {
+                    break;
+                }
+            }
+            None => now += Duration::from_millis(500),
+        }
+    }
+
+    // The first attempt waits for the 10 second renewal delay and retries
+    // back off from the 2 second interval; the next one would land after
+    // the 20 second deadline the margin leaves of the license duration.
+    let seconds: Vec<u64> = attempts.iter().map(Duration::as_secs).collect();
+    assert_eq!(seconds, vec![10, 12, 16]);
+}
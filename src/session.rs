@@ -0,0 +1,498 @@
+use crate::expiry::ExpiryWatcher;
+use crate::init_data::InitData;
+use crate::inspector::{LicensePolicy, SignedMessageInfo};
+use crate::license::{LicenseTransport, TransportError};
+use crate::promise_set::RejectionInfo;
+use crate::provisioning::{ProvisioningHandler, ProvisioningState};
+use crate::renewal::{RenewalError, RenewalManager};
+use crate::types::{
+    Expiration, KeyInformation, KeyStatus, KeysChange, MessageType, SessionEvent, SessionEventType,
+    SessionId, SessionMessage, SessionType,
+};
+use crate::{CdmError, CreateSessionError, HostError, LoadSessionError, Operation, WidevineAPI};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Instant, SystemTime};
+
+#[derive(Clone, Debug)]
+pub enum SessionError {
+    CreateSession(CreateSessionError),
+    LoadSession(LoadSessionError),
+    Transport {
+        session_id: SessionId,
+        message_type: MessageType,
+        error: TransportError,
+    },
+    Rejected(RejectionInfo),
+    HostFailed(HostError),
+    TimedOut(Operation),
+}
+
+impl From<CdmError> for SessionError {
+    fn from(error: CdmError) -> Self {
+        match error {
+            CdmError::Rejected(info) => SessionError::Rejected(info),
+            CdmError::HostFailed(error) => SessionError::HostFailed(error),
+            CdmError::TimedOut(operation) => SessionError::TimedOut(operation),
+        }
+    }
+}
+
+/// Owns a `WidevineAPI` and answers the messages of its sessions: license
+/// requests, renewals and releases go through the transport and the
+/// responses are applied with `update_session`.
+///
+/// Individualization requests go to the `ProvisioningHandler` when one is
+/// set, after which the CDM carries on with the license request. Renewals are
+/// retried by a `RenewalManager`; when one is given up, a
+/// `RenewalFailed` event is emitted. Other events are forwarded to the sender
+/// given to `with_event_sender`.
+pub struct SessionDriver<T> {
+    api: WidevineAPI,
+    transport: T,
+    renewal: RenewalManager,
+    provisioning: Option<ProvisioningHandler>,
+    policies: HashMap<SessionId, LicensePolicy>,
+    expiry: ExpiryWatcher,
+    keys: HashMap<SessionId, Vec<KeyInformation>>,
+    sender: Sender<SessionEvent>,
+    receiver: Receiver<SessionEvent>,
+    event_sender: Option<Sender<SessionEvent>>,
+}
+
+impl<T: LicenseTransport> SessionDriver<T> {
+    pub fn new(api: WidevineAPI, transport: T) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            api,
+            transport,
+            renewal: RenewalManager::default(),
+            provisioning: None,
+            policies: HashMap::new(),
+            expiry: ExpiryWatcher::default(),
+            keys: HashMap::new(),
+            sender,
+            receiver,
+            event_sender: None,
+        }
+    }
+
+    pub fn with_event_sender(mut self, sender: Sender<SessionEvent>) -> Self {
+        self.event_sender = Some(sender);
+        self
+    }
+
+    pub fn with_renewal_manager(mut self, renewal: RenewalManager) -> Self {
+        self.renewal = renewal;
+        self
+    }
+
+    pub fn with_provisioning_handler(mut self, provisioning: ProvisioningHandler) -> Self {
+        self.provisioning = Some(provisioning);
+        self
+    }
+
+    /// The underlying API, for decryption. Sessions created directly on it
+    /// aren't driven.
+    pub fn api(&mut self) -> &mut WidevineAPI {
+        &mut self.api
+    }
+
+    /// The policy of the last license or renewal applied to a session.
+    pub fn license_policy(&self, session_id: &SessionId) -> Option<&LicensePolicy> {
+        self.policies.get(session_id)
+    }
+
+    pub fn expiration(&self, session_id: &SessionId) -> Option<Expiration> {
+        self.expiry.get(session_id)
+    }
+
+    /// The key statuses last reported for a session.
+    pub fn keys(&self, session_id: &SessionId) -> Option<&[KeyInformation]> {
+        self.keys.get(session_id).map(Vec::as_slice)
+    }
+
+    /// Creates a session and completes its license request.
+    pub async fn open_session(
+        &mut self,
+        session_type: SessionType,
+        init_data: InitData,
+    ) -> Result<SessionId, SessionError> {
+        let session_id = self
+            .api
+            .create_session(session_type, init_data, self.sender.clone())
+            .await
+            .map_err(SessionError::CreateSession)?;
+        self.handle_events().await?;
+        Ok(session_id)
+    }
+
+    /// Loads a persisted session, answering the messages it produces.
+    pub async fn load_session(
+        &mut self,
+        session_type: SessionType,
+        session_id: &SessionId,
+    ) -> Result<(), SessionError> {
+        self.api
+            .load_session(session_type, session_id, self.sender.clone())
+            .await
+            .map_err(SessionError::LoadSession)?;
+        self.handle_events().await
+    }
+
+    /// Closes a session without releasing its license, which persistent
+    /// sessions keep in storage.
+    pub async fn close_session(&mut self, session_id: &SessionId) -> Result<(), SessionError> {
+        self.forget(session_id);
+        self.api
+            .close_session(session_id)
+            .await
+            .map_err(SessionError::from)
+    }
+
+    /// Fires the expired CDM timers, answers the messages they produced,
+    /// retries the failed renewals that are due and expires the sessions
+    /// whose expiration has passed. Should be called regularly.
+    pub async fn process(&mut self) -> Result<(), SessionError> {
+        self.api.update();
+        self.handle_events().await?;
+        self.renew().await;
+        self.expire_sessions();
+        Ok(())
+    }
+
+    /// Releases the license of a session with the license server, then
+    /// closes it.
+    pub async fn release_session(&mut self, session_id: &SessionId) -> Result<(), SessionError> {
+        self.api
+            .remove_session(session_id)
+            .await
+            .map_err(SessionError::from)?;
+        self.handle_events().await?;
+        self.close_session(session_id).await
+    }
+
+    async fn handle_events(&mut self) -> Result<(), SessionError> {
+        while let Ok(event) = self.receiver.try_recv() {
+            match event.data {
+                SessionEventType::Message(message) => {
+                    if message.message_type == MessageType::LicenseRenewal {
+                        self.renewal
+                            .schedule(&event.session_id, message, Instant::now());
+                        self.renew().await;
+                    } else {
+                        self.exchange(&event.session_id, message).await?;
+                    }
+                }
+                SessionEventType::ExpirationChange(expiration) => {
+                    self.renewal.set_expiration(&event.session_id, expiration);
+                    self.expiry.set(&event.session_id, expiration);
+                    self.forward(event);
+                }
+                SessionEventType::KeysChange(ref change) => {
+                    self.keys
+                        .insert(event.session_id.clone(), change.keys_info.clone());
+                    self.forward(event);
+                }
+                _ => self.forward(event),
+            }
+        }
+        Ok(())
+    }
+
+    async fn renew(&mut self) {
+        for renewal in self.renewal.take_due(Instant::now()) {
+            let transport = match self.renewal.callback() {
+                Some(callback) => callback,
+                None => &self.transport,
+            };
+            let error = match transport.exchange(renewal.message.clone()).await {
+                Ok(response) => match self
+                    .api
+                    .update_session(&renewal.session_id, &response)
+                    .await
+                {
+                    Ok(()) => {
+                        self.record_policy(&renewal.session_id, &response);
+                        continue;
+                    }
+                    Err(error) => RenewalError::from(error),
+                },
+                Err(error) => RenewalError::Transport(error),
+            };
+
+            let session_id = renewal.session_id.clone();
+            if !self.renewal.retry(renewal, Instant::now()) {
+                self.forward(SessionEvent {
+                    session_id,
+                    data: SessionEventType::RenewalFailed(error),
+                });
+            }
+        }
+    }
+
+    async fn exchange(
+        &mut self,
+        session_id: &SessionId,
+        message: SessionMessage,
+    ) -> Result<(), SessionError> {
+        let message_type = message.message_type;
+        let transport = match (message_type, &self.provisioning) {
+            (MessageType::IndividualizationRequest, Some(provisioning)) => provisioning.transport(),
+            _ => &self.transport,
+        };
+        let is_provisioning = message_type == MessageType::IndividualizationRequest;
+        if is_provisioning {
+            self.api
+                .set_provisioning_state(ProvisioningState::InProgress);
+        } else if self.api.provisioning_state() == ProvisioningState::Unknown {
+            // A license request without individualization means the device
+            // already is.
+            self.api
+                .set_provisioning_state(ProvisioningState::Provisioned);
+        }
+
+        let result = match transport.exchange(message).await {
+            Ok(response) => {
+                let result = self.api.update_session(session_id, &response).await;
+                if result.is_ok() && !is_provisioning {
+                    self.record_policy(session_id, &response);
+                }
+                result.map_err(SessionError::from)
+            }
+            Err(error) => Err(SessionError::Transport {
+                session_id: session_id.clone(),
+                message_type,
+                error,
+            }),
+        };
+        if is_provisioning {
+            self.api.set_provisioning_state(match result {
+                Ok(()) => ProvisioningState::Provisioned,
+                Err(_) => ProvisioningState::Failed,
+            });
+        }
+        result
+    }
+
+    fn forget(&mut self, session_id: &SessionId) {
+        self.renewal.remove(session_id);
+        self.policies.remove(session_id);
+        self.expiry.remove(session_id);
+        self.keys.remove(session_id);
+    }
+
+    fn expire_sessions(&mut self) {
+        for session_id in self.expiry.take_expired(SystemTime::now()) {
+            if let Some(keys) = self.keys.get_mut(&session_id) {
+                for key in keys.iter_mut() {
+                    key.status = KeyStatus::Expired;
+                }
+                let change = KeysChange {
+                    has_additional_usable_key: false,
+                    keys_info: keys.clone(),
+                };
+                self.forward(SessionEvent {
+                    session_id: session_id.clone(),
+                    data: SessionEventType::KeysChange(change),
+                });
+            }
+            self.forward(SessionEvent {
+                session_id,
+                data: SessionEventType::SessionExpired,
+            });
+        }
+    }
+
+    /// Keeps the policy of a license response. Responses that aren't signed
+    /// Widevine licenses are ignored.
+    fn record_policy(&mut self, session_id: &SessionId, response: &[u8]) {
+        let policy = SignedMessageInfo::decode(response)
+            .ok()
+            .and_then(|info| info.license_policy);
+        if let Some(policy) = policy {
+            self.renewal.set_policy(session_id, &policy, Instant::now());
+            self.policies.insert(session_id.clone(), policy);
+        }
+    }
+
+    fn forward(&self, event: SessionEvent) {
+        if let Some(ref sender) = self.event_sender {
+            // The application dropping its receiver isn't our concern.
+            let _ = sender.send(event);
+        }
+    }
+}
+
+/// A license server handing out the requested keys, recording the type of
+/// each message it gets. Licenses ask for a renewal right away.
+#[cfg(test)]
+fn license_server(
+    messages: std::sync::Arc<std::sync::Mutex<Vec<MessageType>>>,
+) -> impl LicenseTransport {
+    use crate::base64;
+    use crate::license::CallbackTransport;
+    use serde_json::{json, Value};
+
+    CallbackTransport::new(move |message: SessionMessage| {
+        messages.lock().unwrap().push(message.message_type);
+        async move {
+            let request: Value = serde_json::from_slice(&message.content).unwrap();
+            let keys: Vec<Value> = request["kids"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|key_id| json!({ "kty": "oct", "kid": key_id, "k": base64::encode_url(&[0x44; 16]) }))
+                .collect();
+            let mut license = json!({ "keys": keys });
+            if message.message_type == MessageType::LicenseRequest {
+                license["renew_after_ms"] = json!(1);
+            }
+            Ok(license.to_string().into_bytes())
+        }
+    })
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_session_driver() {
+    use crate::init_data::KeyIds;
+    use crate::library;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let directory =
+        std::env::temp_dir().join(format!("widevine_rs-session-{}", std::process::id()));
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.set_storage_directory(&directory);
+    api.initialize_cdm().await.unwrap();
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let mut driver = SessionDriver::new(api, license_server(messages.clone()));
+
+    // The license is applied by the time the session is open.
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x55; 16]));
+    let session_id = driver
+        .open_session(SessionType::PersistentLicense, init_data)
+        .await
+        .unwrap();
+    assert_eq!(*messages.lock().unwrap(), [MessageType::LicenseRequest]);
+    let keys = driver.keys(&session_id).unwrap();
+    assert_eq!(keys[0].key_id, vec![0x55; 16]);
+    assert!(matches!(keys[0].status, KeyStatus::Usable));
+
+    // The CDM's renewal timer fires from `process`.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while messages.lock().unwrap().len() < 2 {
+        assert!(Instant::now() < deadline, "no renewal");
+        std::thread::sleep(Duration::from_millis(5));
+        driver.process().await.unwrap();
+    }
+    assert_eq!(messages.lock().unwrap()[1], MessageType::LicenseRenewal);
+
+    driver.release_session(&session_id).await.unwrap();
+    assert_eq!(
+        *messages.lock().unwrap(),
+        [
+            MessageType::LicenseRequest,
+            MessageType::LicenseRenewal,
+            MessageType::LicenseRelease
+        ]
+    );
+    assert!(driver.keys(&session_id).is_none());
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_session_driver_transport_error() {
+    use crate::init_data::KeyIds;
+    use crate::library;
+    use crate::license::CallbackTransport;
+
+    let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+    api.initialize_cdm().await.unwrap();
+    let transport = CallbackTransport::new(|_| async { Err(TransportError::HttpStatus(500)) });
+    let mut driver = SessionDriver::new(api, transport);
+
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x55; 16]));
+    let result = driver.open_session(SessionType::Temporary, init_data).await;
+    assert!(matches!(
+        result,
+        Err(SessionError::Transport {
+            message_type: MessageType::LicenseRequest,
+            error: TransportError::HttpStatus(500),
+            ..
+        })
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_provisioning() {
+    use crate::init_data::KeyIds;
+    use crate::library;
+    use crate::license::CallbackTransport;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let driver = |provisioning: ProvisioningHandler| async {
+        let mut api = WidevineAPI::initialize_with_library(library::mock_cdm()).unwrap();
+        api.initialize_cdm().await.unwrap();
+        api.set_mock_script(
+            json!({ "script": [{ "call": "create_session", "individualize": true }] }),
+        );
+        SessionDriver::new(api, license_server(messages.clone()))
+            .with_provisioning_handler(provisioning)
+    };
+    let init_data = InitData::KeyIds(KeyIds::new().with_key_id(&[0x55; 16]));
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let provisioning_requests = requests.clone();
+    let provisioning = CallbackTransport::new(move |message: SessionMessage| {
+        provisioning_requests.lock().unwrap().push(message);
+        async { Ok(b"device certificate".to_vec()) }
+    });
+    let mut driver_1 = driver(ProvisioningHandler::new(provisioning)).await;
+    assert_eq!(
+        driver_1.api().provisioning_state(),
+        ProvisioningState::Unknown
+    );
+    // The license request follows the provisioning response on its own.
+    let session_id = driver_1
+        .open_session(SessionType::Temporary, init_data.clone())
+        .await
+        .unwrap();
+    match requests.lock().unwrap().as_slice() {
+        [request] => {
+            assert_eq!(request.message_type, MessageType::IndividualizationRequest);
+            assert_eq!(request.content, b"individualization request");
+        }
+        requests => panic!("unexpected provisioning requests {:?}", requests),
+    }
+    assert_eq!(*messages.lock().unwrap(), [MessageType::LicenseRequest]);
+    assert!(driver_1.keys(&session_id).is_some());
+    assert_eq!(
+        driver_1.api().provisioning_state(),
+        ProvisioningState::Provisioned
+    );
+
+    messages.lock().unwrap().clear();
+    let provisioning = CallbackTransport::new(|_| async { Err(TransportError::HttpStatus(503)) });
+    let mut driver_2 = driver(ProvisioningHandler::new(provisioning)).await;
+    let result = driver_2
+        .open_session(SessionType::Temporary, init_data)
+        .await;
+    assert!(matches!(
+        result,
+        Err(SessionError::Transport {
+            message_type: MessageType::IndividualizationRequest,
+            ..
+        })
+    ));
+    assert!(messages.lock().unwrap().is_empty());
+    assert_eq!(
+        driver_2.api().provisioning_state(),
+        ProvisioningState::Failed
+    );
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+/// `cdm::FileIOClient::Status`.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FileStatus {
+    Success,
+    InUse,
+    Error,
+}
+
+check_type!(FileStatus, 4, 4, 4, 4);
+
+/// Backs the CDM's `FileIO` with the files of a directory.
+#[derive(Debug)]
+pub struct FileStorage {
+    directory: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Names are limited to `A-Za-z0-9._-` and can't start with an
+    /// underscore, which leaves those to the host.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= 256
+            && !name.starts_with('_')
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"._-".contains(&byte))
+    }
+
+    /// Reads a file, a missing one reading as empty.
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        match fs::read(self.directory.join(name)) {
+            Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            result => result,
+        }
+    }
+
+    /// Replaces the contents of a file. Writing nothing deletes it.
+    pub fn write(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.directory.join(name);
+        if data.is_empty() {
+            return match fs::remove_file(path) {
+                Err(ref error) if error.kind() == ErrorKind::NotFound => Ok(()),
+                result => result,
+            };
+        }
+
+        fs::create_dir_all(&self.directory)?;
+        // Written aside then renamed, so a crash can't leave a truncated file.
+        let temporary = self.directory.join(format!("_{}.tmp", name));
+        fs::write(&temporary, data)?;
+        fs::rename(temporary, path)
+    }
+}
+
+#[test]
+fn test_file_storage() {
+    let directory =
+        std::env::temp_dir().join(format!("widevine_rs-storage-{}", std::process::id()));
+    let storage = FileStorage::new(directory.clone());
+
+    assert!(FileStorage::is_valid_name("license-1.lic"));
+    assert!(!FileStorage::is_valid_name("_index"));
+    assert!(!FileStorage::is_valid_name("../escape"));
+
+    assert_eq!(storage.read("missing").unwrap(), b"");
+    storage.write("cert.bin", b"certificate").unwrap();
+    assert_eq!(storage.read("cert.bin").unwrap(), b"certificate");
+    storage.write("cert.bin", b"").unwrap();
+    assert_eq!(storage.read("cert.bin").unwrap(), b"");
+
+    fs::remove_dir_all(directory).unwrap();
+}
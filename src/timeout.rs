@@ -0,0 +1,191 @@
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The deadline of a `Timeout` passed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Elapsed;
+
+/// Limits the time a future gets to complete. The deadlines are kept by a
+/// timer thread, like the CDM's timers, so any executor can drive it.
+pub fn timeout<F: Future + Unpin>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        duration,
+        deadline: None,
+    }
+}
+
+pub struct Timeout<F> {
+    future: F,
+    duration: Duration,
+    /// Started on the first poll.
+    deadline: Option<Arc<Deadline>>,
+}
+
+#[derive(Default)]
+struct Deadline {
+    expired: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Deadline {
+    fn start(duration: Duration) -> Arc<Self> {
+        let deadline = Arc::new(Deadline::default());
+        // Too far out to represent, it never passes.
+        if let Some(instant) = Instant::now().checked_add(duration) {
+            let entry = Entry {
+                instant,
+                deadline: Arc::downgrade(&deadline),
+            };
+            let _ = timer().lock().unwrap().send(entry);
+        }
+        deadline
+    }
+
+    fn expire(&self) {
+        self.expired.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A deadline waiting in the timer thread, which only holds a weak reference
+/// so dropping the `Timeout` frees it.
+struct Entry {
+    instant: Instant,
+    deadline: Weak<Deadline>,
+}
+
+// Ordered by instant only, the earliest first in a `BinaryHeap`.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        other.instant.cmp(&self.instant)
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.instant == other.instant
+    }
+}
+
+impl Eq for Entry {}
+
+/// The thread expiring the deadlines of all timeouts, started on first use.
+fn timer() -> &'static Mutex<Sender<Entry>> {
+    static TIMER: OnceLock<Mutex<Sender<Entry>>> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let (sender, receiver) = channel();
+        thread::Builder::new()
+            .name("widevine-timer".to_string())
+            .spawn(move || run_timer(receiver))
+            .expect("failed to start the timer thread");
+        Mutex::new(sender)
+    })
+}
+
+fn run_timer(receiver: Receiver<Entry>) {
+    let mut entries = BinaryHeap::new();
+    loop {
+        let received = match entries.peek() {
+            Some(Entry { instant, .. }) => {
+                receiver.recv_timeout(instant.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(entry) => entries.push(entry),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        while entries.peek().is_some_and(|entry| entry.instant <= now) {
+            let entry = entries.pop().unwrap();
+            if let Some(deadline) = entry.deadline.upgrade() {
+                deadline.expire();
+            }
+        }
+    }
+}
+
+impl<F: Future + Unpin> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = Pin::new(&mut self.future).poll(context) {
+            return Poll::Ready(Ok(output));
+        }
+
+        let duration = self.duration;
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Deadline::start(duration));
+        // Stored before checking, so an expiry in between still wakes us.
+        *deadline.waker.lock().unwrap() = Some(context.waker().clone());
+        if deadline.expired.load(Ordering::SeqCst) {
+            Poll::Ready(Err(Elapsed))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_timeout() {
+    use std::future;
+
+    let result = timeout(Duration::from_secs(60), future::ready(1)).await;
+    assert_eq!(result, Ok(1));
+    let result = timeout(Duration::from_millis(10), future::pending::<()>()).await;
+    assert_eq!(result, Err(Elapsed));
+}
+
+/// Driven by parking the thread, without any runtime.
+#[test]
+fn test_timeout_without_runtime() {
+    use std::future;
+    use std::task::Wake;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+
+    let mut early = timeout(Duration::from_secs(60), future::pending::<()>());
+    assert!(Pin::new(&mut early).poll(&mut context).is_pending());
+    let deadline = Arc::downgrade(early.deadline.as_ref().unwrap());
+    drop(early);
+    assert!(deadline.upgrade().is_none());
+
+    let mut late = timeout(Duration::from_millis(10), future::pending::<()>());
+    let result = loop {
+        match Pin::new(&mut late).poll(&mut context) {
+            Poll::Ready(result) => break result,
+            Poll::Pending => thread::park(),
+        }
+    };
+    assert_eq!(result, Err(Elapsed));
+}
@@ -35,7 +35,7 @@ impl TimerManager {
         });
     }
 
-    pub fn try_iter(&mut self) -> TryIter<Timer> {
+    pub fn try_iter(&mut self) -> TryIter<'_, Timer> {
         self.receiver.try_iter()
     }
 }
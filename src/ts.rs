@@ -0,0 +1,641 @@
+use crate::byte_reader::{ByteReader, UnexpectedEnd};
+use crate::decryption::{EncryptionScheme, Pattern, Sample, SubsampleEntry};
+use std::collections::HashMap;
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+
+/// Video NAL units keep their first 32 bytes in the clear, as do NAL units
+/// too short to hold an encrypted block past those.
+const VIDEO_CLEAR_LEADER: usize = 32;
+const VIDEO_MIN_ENCRYPTED_NAL: usize = 48;
+/// ADTS frames keep the 16 bytes following their header in the clear.
+const AUDIO_CLEAR_LEADER: usize = 16;
+const BLOCK_SIZE: usize = 16;
+const AAC_SAMPLES_PER_FRAME: u64 = 1024;
+const AAC_SAMPLE_RATES: [u64; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TsError {
+    UnexpectedEnd,
+    InvalidSyncByte(usize),
+    InvalidSection(u16),
+    InvalidPes(u16),
+    InvalidAdts(u16),
+}
+
+impl From<UnexpectedEnd> for TsError {
+    fn from(_: UnexpectedEnd) -> Self {
+        TsError::UnexpectedEnd
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StreamType {
+    H264,
+    Hevc,
+    Aac,
+}
+
+impl StreamType {
+    fn from_raw(stream_type: u8) -> Option<Self> {
+        match stream_type {
+            0x1B | 0xDB => Some(StreamType::H264),
+            0x24 => Some(StreamType::Hevc),
+            0x0F | 0xCF => Some(StreamType::Aac),
+            _ => None,
+        }
+    }
+
+    fn is_video(self) -> bool {
+        self != StreamType::Aac
+    }
+}
+
+/// Key ID and IV of a segment, as given by the playlist's key tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentKey {
+    pub key_id: Vec<u8>,
+    pub iv: Vec<u8>,
+}
+
+struct PesBuffer {
+    stream_type: StreamType,
+    data: Vec<u8>,
+}
+
+/// Demuxes H.264, HEVC and AAC elementary streams out of an MPEG-2
+/// transport stream, producing one sample per video PES packet and one
+/// sample per ADTS frame.
+pub struct Demuxer {
+    key: Option<SegmentKey>,
+    pmt_pid: Option<u16>,
+    /// PSI sections spanning several packets, by PID.
+    sections: HashMap<u16, Vec<u8>>,
+    streams: HashMap<u16, PesBuffer>,
+    samples: Vec<Sample>,
+}
+
+impl Demuxer {
+    /// Samples are `cbcs` encrypted with `key` when one is given, and
+    /// unencrypted otherwise.
+    pub fn new(key: Option<SegmentKey>) -> Self {
+        Self {
+            key,
+            pmt_pid: None,
+            sections: HashMap::new(),
+            streams: HashMap::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Demuxes a whole segment.
+    pub fn demux(data: &[u8], key: Option<SegmentKey>) -> Result<Vec<Sample>, TsError> {
+        let mut demuxer = Self::new(key);
+        let mut samples = demuxer.push(data)?;
+        samples.extend(demuxer.flush()?);
+        Ok(samples)
+    }
+
+    /// Feeds whole transport stream packets, returning the samples of every
+    /// PES packet completed by them.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Sample>, TsError> {
+        for (index, packet) in data.chunks(PACKET_SIZE).enumerate() {
+            if packet.len() != PACKET_SIZE || packet[0] != SYNC_BYTE {
+                return Err(TsError::InvalidSyncByte(index * PACKET_SIZE));
+            }
+            self.parse_packet(packet)?;
+        }
+        Ok(self.samples.drain(..).collect())
+    }
+
+    /// Emits the samples of the PES packets still being reassembled.
+    pub fn flush(&mut self) -> Result<Vec<Sample>, TsError> {
+        let mut pids: Vec<u16> = self.streams.keys().copied().collect();
+        pids.sort_unstable();
+        for pid in pids {
+            self.finish_pes(pid)?;
+        }
+        Ok(self.samples.drain(..).collect())
+    }
+
+    fn parse_packet(&mut self, packet: &[u8]) -> Result<(), TsError> {
+        let mut reader = ByteReader::new(&packet[1..]);
+        let header = reader.read_u16()?;
+        let payload_unit_start = header & 0x4000 != 0;
+        let pid = header & 0x1fff;
+        let control = reader.read_u8()?;
+
+        if control & 0x20 != 0 {
+            let length = reader.read_u8()?;
+            reader.skip(length.into())?;
+        }
+        if control & 0x10 == 0 {
+            return Ok(());
+        }
+        let payload = reader.rest();
+
+        if pid == PAT_PID || Some(pid) == self.pmt_pid {
+            for section in self.push_section(pid, payload, payload_unit_start)? {
+                if pid == PAT_PID {
+                    self.parse_pat(&section)?;
+                } else {
+                    self.parse_pmt(&section)?;
+                }
+            }
+            Ok(())
+        } else if self.streams.contains_key(&pid) {
+            if payload_unit_start {
+                self.finish_pes(pid)?;
+            }
+            if let Some(stream) = self.streams.get_mut(&pid) {
+                stream.data.extend_from_slice(payload);
+            }
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reassembles the PSI sections of a PID, returning the bodies of the
+    /// ones completed by the packet.
+    fn push_section(
+        &mut self,
+        pid: u16,
+        payload: &[u8],
+        payload_unit_start: bool,
+    ) -> Result<Vec<Vec<u8>>, TsError> {
+        let buffer = self.sections.entry(pid).or_default();
+        if !payload_unit_start {
+            // Nothing to continue when the start was missed.
+            if !buffer.is_empty() {
+                buffer.extend_from_slice(payload);
+            }
+            return complete_sections(pid, buffer);
+        }
+
+        let mut reader = ByteReader::new(payload);
+        let pointer = reader.read_u8()?;
+        // The pointer skips the end of the previous section.
+        buffer.extend_from_slice(reader.read(pointer.into())?);
+        let mut sections = complete_sections(pid, buffer)?;
+        buffer.clear();
+        buffer.extend_from_slice(reader.rest());
+        sections.extend(complete_sections(pid, buffer)?);
+        Ok(sections)
+    }
+
+    fn parse_pat(&mut self, section: &[u8]) -> Result<(), TsError> {
+        let mut reader = ByteReader::new(section);
+        while !reader.is_empty() {
+            let program_number = reader.read_u16()?;
+            let program_pid = reader.read_u16()? & 0x1fff;
+            if program_number != 0 {
+                self.pmt_pid = Some(program_pid);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_pmt(&mut self, section: &[u8]) -> Result<(), TsError> {
+        let mut reader = ByteReader::new(section);
+        reader.skip(2)?;
+        let info_length = reader.read_u16()? & 0x0fff;
+        reader.skip(info_length.into())?;
+
+        while !reader.is_empty() {
+            let stream_type = reader.read_u8()?;
+            let stream_pid = reader.read_u16()? & 0x1fff;
+            let info_length = reader.read_u16()? & 0x0fff;
+            reader.skip(info_length.into())?;
+
+            if let Some(stream_type) = StreamType::from_raw(stream_type) {
+                self.streams.entry(stream_pid).or_insert(PesBuffer {
+                    stream_type,
+                    data: Vec::new(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_pes(&mut self, pid: u16) -> Result<(), TsError> {
+        let (stream_type, data) = match self.streams.get_mut(&pid) {
+            Some(stream) if !stream.data.is_empty() => {
+                (stream.stream_type, std::mem::take(&mut stream.data))
+            }
+            _ => return Ok(()),
+        };
+
+        let mut reader = ByteReader::new(&data);
+        if reader.read_u24()? != 0x000001 {
+            return Err(TsError::InvalidPes(pid));
+        }
+        reader.skip(3)?;
+        let flags = reader.read_u16()?;
+        let header_length = reader.read_u8()?;
+        let header = reader.read(header_length.into())?;
+        let pts = if flags & 0x0080 != 0 {
+            parse_timestamp(header).ok_or(TsError::InvalidPes(pid))?
+        } else {
+            0
+        };
+        let payload = reader.rest();
+
+        if stream_type.is_video() {
+            let (data, subsamples) = match self.key {
+                Some(_) => encrypted_video(stream_type, payload),
+                None => (payload.to_vec(), Vec::new()),
+            };
+            let sample = self.sample(pid, &data, pts_to_microseconds(pts), subsamples, true);
+            self.samples.push(sample);
+        } else {
+            self.push_adts_frames(pid, payload, pts)?;
+        }
+        Ok(())
+    }
+
+    fn push_adts_frames(&mut self, pid: u16, mut payload: &[u8], pts: u64) -> Result<(), TsError> {
+        let mut frame_index = 0;
+        while payload.len() >= 7 {
+            if payload[0] != 0xff || payload[1] & 0xf0 != 0xf0 {
+                return Err(TsError::InvalidAdts(pid));
+            }
+            let header_size = if payload[1] & 0x01 != 0 { 7 } else { 9 };
+            let sample_rate = AAC_SAMPLE_RATES
+                .get(usize::from(payload[2] >> 2 & 0x0f))
+                .ok_or(TsError::InvalidAdts(pid))?;
+            let frame_size = usize::from(payload[3] & 0x03) << 11
+                | usize::from(payload[4]) << 3
+                | usize::from(payload[5]) >> 5;
+            if frame_size < header_size || frame_size > payload.len() {
+                return Err(TsError::InvalidAdts(pid));
+            }
+
+            let (frame, rest) = payload.split_at(frame_size);
+            let clear = (header_size + AUDIO_CLEAR_LEADER).min(frame_size);
+            let subsamples = encrypted_range(clear, frame_size - clear);
+            let offset = frame_index * AAC_SAMPLES_PER_FRAME * 1_000_000 / sample_rate;
+            let timestamp = pts_to_microseconds(pts) + offset as i64;
+            let sample = self.sample(pid, frame, timestamp, subsamples, false);
+            self.samples.push(sample);
+
+            frame_index += 1;
+            payload = rest;
+        }
+        Ok(())
+    }
+
+    fn sample(
+        &self,
+        pid: u16,
+        data: &[u8],
+        timestamp: i64,
+        subsamples: Vec<SubsampleEntry>,
+        is_video: bool,
+    ) -> Sample {
+        let mut sample = Sample {
+            track_id: pid.into(),
+            data: data.to_vec(),
+            timestamp,
+            encryption_scheme: EncryptionScheme::Unencrypted,
+            key_id: Vec::new(),
+            iv: Vec::new(),
+            subsamples: Vec::new(),
+            pattern: Pattern::default(),
+        };
+
+        if let Some(ref key) = self.key {
+            sample.encryption_scheme = EncryptionScheme::Cbcs;
+            sample.key_id = key.key_id.clone();
+            sample.iv = key.iv.clone();
+            sample.subsamples = subsamples;
+            if is_video {
+                sample.pattern = Pattern {
+                    crypt_byte_block: 1,
+                    skip_byte_block: 9,
+                };
+            }
+        }
+        sample
+    }
+}
+
+/// Takes the complete sections out of `buffer`, returning their bodies
+/// without header and CRC. Stuffing bytes end the sections of a packet.
+fn complete_sections(pid: u16, buffer: &mut Vec<u8>) -> Result<Vec<Vec<u8>>, TsError> {
+    let mut sections = Vec::new();
+    while buffer.len() >= 3 {
+        if buffer[0] == 0xff {
+            buffer.clear();
+            break;
+        }
+        let length = (u16::from_be_bytes([buffer[1], buffer[2]]) & 0x0fff) as usize;
+        if length < 9 {
+            return Err(TsError::InvalidSection(pid));
+        }
+        if buffer.len() < 3 + length {
+            break;
+        }
+        sections.push(buffer[8..length - 1].to_vec());
+        buffer.drain(..3 + length);
+    }
+    Ok(sections)
+}
+
+fn parse_timestamp(header: &[u8]) -> Option<u64> {
+    let bytes = header.get(..5)?;
+    Some(
+        u64::from(bytes[0] >> 1 & 0x07) << 30
+            | u64::from(bytes[1]) << 22
+            | u64::from(bytes[2] >> 1) << 15
+            | u64::from(bytes[3]) << 7
+            | u64::from(bytes[4] >> 1),
+    )
+}
+
+/// PTS are 33 bits, so the result always fits.
+fn pts_to_microseconds(pts: u64) -> i64 {
+    (pts * 100 / 9) as i64
+}
+
+/// Splits `clear` leading bytes followed by `size` bytes of which only
+/// whole blocks are encrypted.
+fn encrypted_range(clear: usize, size: usize) -> Vec<SubsampleEntry> {
+    let cipher = size / BLOCK_SIZE * BLOCK_SIZE;
+    let mut subsamples = vec![SubsampleEntry {
+        clear_bytes: clear as u32,
+        cipher_bytes: cipher as u32,
+    }];
+    if size > cipher {
+        subsamples.push(SubsampleEntry {
+            clear_bytes: (size - cipher) as u32,
+            cipher_bytes: 0,
+        });
+    }
+    subsamples
+}
+
+/// Whether Sample-AES encrypts the NAL unit. For H.264 those are the coded
+/// slices, IDR or not, but not the data partitions.
+fn is_vcl(stream_type: StreamType, nal_header: u8) -> bool {
+    match stream_type {
+        StreamType::H264 => matches!(nal_header & 0x1f, 1 | 5),
+        StreamType::Hevc => (nal_header >> 1 & 0x3f) <= 31,
+        StreamType::Aac => false,
+    }
+}
+
+/// Returns `(start code offset, NAL unit offset)` for every Annex B start
+/// code in `data`.
+fn start_codes(data: &[u8]) -> Vec<(usize, usize)> {
+    let mut positions = Vec::new();
+    let mut index = 0;
+    while index + 3 <= data.len() {
+        if data[index..index + 3] == [0, 0, 1] {
+            let start = if index > 0 && data[index - 1] == 0 {
+                index - 1
+            } else {
+                index
+            };
+            positions.push((start, index + 3));
+            index += 3;
+        } else {
+            index += 1;
+        }
+    }
+    positions
+}
+
+/// Removes the emulation prevention bytes of a NAL unit, the `03` of each
+/// `00 00 03` sequence.
+fn unescape_nal(nal: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        unescaped.push(byte);
+    }
+    unescaped
+}
+
+/// Lays out an encrypted Annex B access unit as `cbcs`: start codes, non-VCL
+/// NAL units and the leading bytes of each slice stay in the clear, and the
+/// rest of each slice is encrypted in whole blocks with a 1:9 pattern.
+///
+/// Slices are encrypted with their emulation prevention bytes removed, so
+/// they're returned without them and have to be escaped again once
+/// decrypted.
+fn encrypted_video(stream_type: StreamType, data: &[u8]) -> (Vec<u8>, Vec<SubsampleEntry>) {
+    let positions = start_codes(data);
+    let mut output = Vec::with_capacity(data.len());
+    let mut subsamples = Vec::new();
+    let mut clear = positions.first().map_or(data.len(), |&(start, _)| start);
+    output.extend_from_slice(&data[..clear]);
+
+    for (index, &(start, nal_start)) in positions.iter().enumerate() {
+        let nal_end = positions
+            .get(index + 1)
+            .map_or(data.len(), |&(next, _)| next);
+        let nal = &data[nal_start..nal_end];
+        output.extend_from_slice(&data[start..nal_start]);
+        clear += nal_start - start;
+
+        let slice = match nal.first() {
+            Some(&header) if is_vcl(stream_type, header) => Some(unescape_nal(nal)),
+            _ => None,
+        };
+        match slice {
+            Some(slice) if slice.len() > VIDEO_MIN_ENCRYPTED_NAL => {
+                let encrypted = slice.len() - VIDEO_CLEAR_LEADER;
+                let cipher = encrypted / BLOCK_SIZE * BLOCK_SIZE;
+                subsamples.push(SubsampleEntry {
+                    clear_bytes: (clear + VIDEO_CLEAR_LEADER) as u32,
+                    cipher_bytes: cipher as u32,
+                });
+                clear = encrypted - cipher;
+                output.extend_from_slice(&slice);
+            }
+            _ => {
+                clear += nal.len();
+                output.extend_from_slice(nal);
+            }
+        }
+    }
+
+    if clear > 0 || subsamples.is_empty() {
+        subsamples.push(SubsampleEntry {
+            clear_bytes: clear as u32,
+            cipher_bytes: 0,
+        });
+    }
+    (output, subsamples)
+}
+
+#[cfg(test)]
+fn ts_packets(pid: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packets = Vec::new();
+    for (index, chunk) in payload.chunks(184).enumerate() {
+        let start = if index == 0 { 0x40 } else { 0 };
+        packets.extend_from_slice(&[SYNC_BYTE, start | (pid >> 8) as u8, pid as u8]);
+        if chunk.len() == 184 {
+            packets.push(0x10);
+        } else {
+            // Pad the last packet with an adaptation field.
+            let padding = 184 - chunk.len() - 1;
+            packets.push(0x30);
+            packets.push(padding as u8);
+            if padding > 0 {
+                packets.push(0);
+                packets.extend(std::iter::repeat_n(0xff, padding - 1));
+            }
+        }
+        packets.extend_from_slice(chunk);
+    }
+    packets
+}
+
+#[test]
+fn test_demux_cbcs_segment() {
+    let pat = [
+        0, 0x00, 0xB0, 13, 0, 1, 0xC1, 0, 0, 0, 1, 0xE1, 0x00, 0, 0, 0, 0,
+    ];
+    let pmt = [
+        0, 0x02, 0xB0, 23, 0, 1, 0xC1, 0, 0, 0xE1, 0x01, 0xF0, 0, 0x1B, 0xE1, 0x01, 0xF0, 0, 0x0F,
+        0xE1, 0x02, 0xF0, 0, 0, 0, 0, 0,
+    ];
+
+    let mut video = vec![0, 0, 1, 0xE0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 0x01, 0, 0x01];
+    video.extend_from_slice(&[0, 0, 0, 1, 0x67, 1, 2, 3]);
+    video.extend_from_slice(&[0, 0, 1, 0x65]);
+    video.extend_from_slice(&[0xAB; 99]);
+
+    let mut audio = vec![0, 0, 1, 0xC0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 0x01, 0, 0x01];
+    for _ in 0..2 {
+        // 7 byte header, 48 kHz, 50 byte frame.
+        audio.extend_from_slice(&[0xFF, 0xF1, 0x4C, 0x80, 0x06, 0x5F, 0xFC]);
+        audio.extend_from_slice(&[0xCD; 43]);
+    }
+
+    let segment = [
+        ts_packets(0, &pat),
+        ts_packets(0x100, &pmt),
+        ts_packets(0x101, &video),
+        ts_packets(0x102, &audio),
+    ]
+    .concat();
+
+    let key = SegmentKey {
+        key_id: vec![0x11; 16],
+        iv: vec![0x22; 16],
+    };
+    let samples = Demuxer::demux(&segment, Some(key)).unwrap();
+    assert_eq!(samples.len(), 3);
+
+    let video = samples
+        .iter()
+        .find(|sample| sample.track_id == 0x101)
+        .unwrap();
+    assert_eq!(video.timestamp, 0);
+    assert_eq!(video.encryption_scheme, EncryptionScheme::Cbcs);
+    assert_eq!(video.pattern.crypt_byte_block, 1);
+    assert_eq!(video.pattern.skip_byte_block, 9);
+    assert_eq!(
+        video.subsamples,
+        vec![
+            SubsampleEntry {
+                clear_bytes: 8 + 3 + 32,
+                cipher_bytes: 64
+            },
+            SubsampleEntry {
+                clear_bytes: 4,
+                cipher_bytes: 0
+            },
+        ]
+    );
+
+    let audio: Vec<&Sample> = samples
+        .iter()
+        .filter(|sample| sample.track_id == 0x102)
+        .collect();
+    assert_eq!(audio.len(), 2);
+    assert_eq!(audio[1].timestamp, 21_333);
+    assert_eq!(audio[0].pattern, Pattern::default());
+    assert_eq!(
+        audio[0].subsamples,
+        vec![
+            SubsampleEntry {
+                clear_bytes: 23,
+                cipher_bytes: 16
+            },
+            SubsampleEntry {
+                clear_bytes: 11,
+                cipher_bytes: 0
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_video_emulation_prevention() {
+    let mut slice = vec![0x65];
+    slice.extend_from_slice(&[0xAB; 40]);
+    slice.extend_from_slice(&[0, 0, 3, 1]);
+    slice.extend_from_slice(&[0xAB; 20]);
+    let data = [&[0, 0, 0, 1][..], &slice].concat();
+
+    let (output, subsamples) = encrypted_video(StreamType::H264, &data);
+    let mut expected = vec![0, 0, 0, 1, 0x65];
+    expected.extend_from_slice(&[0xAB; 40]);
+    expected.extend_from_slice(&[0, 0, 1]);
+    expected.extend_from_slice(&[0xAB; 20]);
+    assert_eq!(output, expected);
+    // 64 bytes once unescaped: 32 in the clear, then 2 whole blocks.
+    assert_eq!(
+        subsamples,
+        vec![SubsampleEntry {
+            clear_bytes: 4 + 32,
+            cipher_bytes: 32
+        }]
+    );
+
+    // Short enough to stay in the clear once unescaped, so left as is.
+    let short = [&[0, 0, 1, 0x65][..], &[0, 0, 3, 0].repeat(12)].concat();
+    let (output, subsamples) = encrypted_video(StreamType::H264, &short);
+    assert_eq!(output, short);
+    assert_eq!(subsamples[0].cipher_bytes, 0);
+    assert!(is_vcl(StreamType::H264, 0x41));
+    assert!(!is_vcl(StreamType::H264, 0x22));
+}
+
+#[test]
+fn test_demux_section_spanning_packets() {
+    let pat = [
+        0, 0x00, 0xB0, 13, 0, 1, 0xC1, 0, 0, 0, 1, 0xE1, 0x00, 0, 0, 0, 0,
+    ];
+    // 200 bytes of program descriptors push the stream past the first packet.
+    let mut pmt = vec![0, 0x02, 0xB0, 218, 0, 1, 0xC1, 0, 0, 0xE1, 0x01, 0xF0, 200];
+    pmt.extend(std::iter::repeat_n(0, 200));
+    pmt.extend_from_slice(&[0x1B, 0xE1, 0x01, 0xF0, 0, 0, 0, 0, 0]);
+    let mut video = vec![0, 0, 1, 0xE0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 0x01, 0, 0x01];
+    video.extend_from_slice(&[0, 0, 1, 0x65, 0xAB]);
+
+    let segment = [
+        ts_packets(0, &pat),
+        ts_packets(0x100, &pmt),
+        ts_packets(0x101, &video),
+    ]
+    .concat();
+    let samples = Demuxer::demux(&segment, None).unwrap();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].track_id, 0x101);
+    assert_eq!(samples[0].data, [0, 0, 1, 0x65, 0xAB]);
+}
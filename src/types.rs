@@ -1,15 +1,20 @@
-use std::os::raw::{c_uchar, c_uint};
-use std::slice;
+use crate::abi;
+use crate::renewal::RenewalError;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[repr(C)]
+#[repr(u32)]
 #[derive(Debug, Copy, Clone)]
 pub enum SessionType {
     Temporary,
     PersistentLicense,
-    PermanentKeyRelease,
+    PersistentKeyRelease,
 }
 
-#[repr(C)]
+check_type!(SessionType, 4, 4, 4, 4);
+
+#[repr(u32)]
 #[derive(Debug, Copy, Clone)]
 pub enum InitDataType {
     Cenc,
@@ -17,22 +22,26 @@ pub enum InitDataType {
     WebM,
 }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub enum Exception {
-    TypeError,
-    NotSupportedError,
-    InvalidStateError,
-    QuotaExceededError,
+check_type!(InitDataType, 4, 4, 4, 4);
+
+cdm_enum! {
+    #[derive(Debug, Copy, Clone)]
+    pub enum Exception {
+        TypeError = 0,
+        NotSupportedError = 1,
+        InvalidStateError = 2,
+        QuotaExceededError = 3,
+    }
 }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub enum MessageType {
-    LicenseRequest,
-    LicenseRenewal,
-    LicenseRelease,
-    IndividualizationRequest,
+cdm_enum! {
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub enum MessageType {
+        LicenseRequest = 0,
+        LicenseRenewal = 1,
+        LicenseRelease = 2,
+        IndividualizationRequest = 3,
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -41,36 +50,170 @@ pub struct SessionMessage {
     pub content: Vec<u8>,
 }
 
+/// A session ID, as chosen by the CDM. It is opaque bytes, which needn't be
+/// UTF-8.
+///
+/// It is displayed with printable ASCII as is and other bytes escaped as
+/// `\xNN` (backslashes as `\\`), which `from_str` parses back.
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionId(Vec<u8>);
+
+impl SessionId {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for SessionId {
+    fn from(bytes: Vec<u8>) -> Self {
+        SessionId(bytes)
+    }
+}
+
+impl From<&[u8]> for SessionId {
+    fn from(bytes: &[u8]) -> Self {
+        SessionId(bytes.to_vec())
+    }
+}
+
+impl From<&str> for SessionId {
+    fn from(id: &str) -> Self {
+        SessionId(id.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for SessionId {
+    fn from(id: String) -> Self {
+        SessionId(id.into_bytes())
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &byte in &self.0 {
+            match byte {
+                b'\\' => f.write_str("\\\\")?,
+                b' '..=b'~' => write!(f, "{}", byte as char)?,
+                _ => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SessionId(\"{}\")", self)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SessionIdError {
+    InvalidEscape,
+}
+
+impl FromStr for SessionId {
+    type Err = SessionIdError;
+
+    /// Parses the display form of a session ID. Unescaped characters outside
+    /// of ASCII are kept as UTF-8.
+    fn from_str(id: &str) -> Result<Self, SessionIdError> {
+        let mut bytes = Vec::with_capacity(id.len());
+        let mut rest = id.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            rest = tail;
+            if byte != b'\\' {
+                bytes.push(byte);
+                continue;
+            }
+            match rest {
+                [b'\\', tail @ ..] => {
+                    bytes.push(b'\\');
+                    rest = tail;
+                }
+                [b'x', high, low, tail @ ..]
+                    if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() =>
+                {
+                    let hex = [*high, *low];
+                    let hex = std::str::from_utf8(&hex).unwrap();
+                    bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                    rest = tail;
+                }
+                _ => return Err(SessionIdError::InvalidEscape),
+            }
+        }
+        Ok(SessionId(bytes))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionEvent {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub data: SessionEventType,
 }
 
 #[derive(Debug, Clone)]
 pub enum SessionEventType {
     Message(SessionMessage),
-    ExpirationChange(f64),
+    ExpirationChange(Expiration),
     KeysChange(KeysChange),
+    /// Emitted by `SessionDriver` when a license renewal was given up.
+    RenewalFailed(RenewalError),
+    /// Emitted by `SessionDriver` once the expiration has passed on the host
+    /// clock, after a `KeysChange` marking the keys as expired.
+    SessionExpired,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Expiration {
+    Never,
+    At(SystemTime),
+}
+
+impl From<f64> for Expiration {
+    /// `cdm::Time` is in seconds since the epoch, with NaN (or zero) meaning
+    /// no expiration. So do times too far out to represent.
+    fn from(time: f64) -> Self {
+        if time <= 0.0 {
+            return Expiration::Never;
+        }
+        Duration::try_from_secs_f64(time)
+            .ok()
+            .and_then(|duration| UNIX_EPOCH.checked_add(duration))
+            .map_or(Expiration::Never, Expiration::At)
+    }
 }
 
-#[repr(C)]
 #[derive(Debug, Clone)]
 pub struct KeysChange {
     pub has_additional_usable_key: bool,
     pub keys_info: Vec<KeyInformation>,
 }
 
+/// `cdm::KeyInformation`.
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub struct CDMKeyInformation {
-    pub key_id: *const c_uchar,
-    pub key_id_size: c_uint,
-    pub status: KeyStatus,
-    pub system_code: c_uint,
+    pub key_id: *const u8,
+    pub key_id_size: u32,
+    pub status: u32,
+    pub system_code: u32,
 }
 
-#[repr(C)]
+check_type!(CDMKeyInformation, 16, 24, 4, 8);
+check_offset!(CDMKeyInformation, key_id, 0, 0);
+check_offset!(CDMKeyInformation, key_id_size, 4, 8);
+check_offset!(CDMKeyInformation, status, 8, 12);
+check_offset!(CDMKeyInformation, system_code, 12, 16);
+
 #[derive(Debug, Clone)]
 pub struct KeyInformation {
     pub key_id: Vec<u8>,
@@ -78,27 +221,46 @@ pub struct KeyInformation {
     pub system_code: u32,
 }
 
-impl Into<KeyInformation> for CDMKeyInformation {
-    fn into(self) -> KeyInformation {
-        let key_id =
-            unsafe { slice::from_raw_parts(self.key_id, self.key_id_size as usize) }.to_vec();
+impl From<CDMKeyInformation> for KeyInformation {
+    fn from(info: CDMKeyInformation) -> Self {
+        let key_id = unsafe { abi::slice(info.key_id, info.key_id_size) }.to_vec();
 
         KeyInformation {
             key_id,
-            status: self.status,
-            system_code: self.system_code,
+            status: KeyStatus::from_cdm(info.status),
+            system_code: info.system_code,
         }
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub enum KeyStatus {
-    Usable,
-    InternalError,
-    Expired,
-    OutputRestricted,
-    OutputDownscaled,
-    StatusPending,
-    Released,
+cdm_enum! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum KeyStatus {
+        Usable = 0,
+        InternalError = 1,
+        Expired = 2,
+        OutputRestricted = 3,
+        OutputDownscaled = 4,
+        StatusPending = 5,
+        Released = 6,
+    }
+}
+
+#[test]
+fn test_session_id_display() {
+    let id = SessionId::from(b"id-\\\x00\xff".to_vec());
+    assert_eq!(id.to_string(), "id-\\\\\\x00\\xff");
+    assert_eq!(id.to_string().parse::<SessionId>().unwrap(), id);
+    assert_eq!("abc".parse::<SessionId>().unwrap(), SessionId::from("abc"));
+    assert!("\\x0".parse::<SessionId>().is_err());
+}
+
+#[test]
+fn test_expiration_from_cdm() {
+    let expiration = Expiration::from(2_000_000_000.5);
+    let expected = UNIX_EPOCH + Duration::from_millis(2_000_000_000_500);
+    assert_eq!(expiration, Expiration::At(expected));
+    for time in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e300] {
+        assert_eq!(Expiration::from(time), Expiration::Never);
+    }
 }
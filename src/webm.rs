@@ -0,0 +1,438 @@
+use crate::byte_reader::{ByteReader, UnexpectedEnd};
+use crate::decryption::{EncryptionScheme, Pattern, Sample, SubsampleEntry};
+use crate::init_data::InitData;
+use std::convert::{TryFrom, TryInto};
+
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const CONTENT_ENCODINGS: u32 = 0x6D80;
+const CONTENT_ENCODING: u32 = 0x6240;
+const CONTENT_ENCRYPTION: u32 = 0x5035;
+const CONTENT_ENC_KEY_ID: u32 = 0x47E2;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+const SIGNAL_ENCRYPTED: u8 = 0x01;
+const SIGNAL_PARTITIONED: u8 = 0x02;
+const IV_SIZE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebMError {
+    UnexpectedEnd,
+    InvalidVint,
+    InvalidElementSize(u32),
+    MissingElement(u32),
+    UnknownTrack(u64),
+    /// Track numbers are limited to 32 bits, like MP4 track IDs.
+    InvalidTrackNumber(u64),
+    TimecodeOverflow,
+    UnsupportedLacing,
+    InvalidPartitions,
+}
+
+impl From<UnexpectedEnd> for WebMError {
+    fn from(_: UnexpectedEnd) -> Self {
+        WebMError::UnexpectedEnd
+    }
+}
+
+/// Reads an EBML variable length integer. Element IDs keep their length
+/// marker, sizes don't. `None` is returned for sizes with every bit set,
+/// which EBML uses for unknown lengths.
+fn read_vint(reader: &mut ByteReader, keep_marker: bool) -> Result<Option<u64>, WebMError> {
+    let first = reader.read_u8()?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 {
+        return Err(WebMError::InvalidVint);
+    }
+
+    let mask = (0xffu32 >> length) as u8;
+    let marker = if keep_marker { 0xff } else { mask };
+    let mut value = u64::from(first & marker);
+    let mut all_ones = first & mask == mask;
+    for byte in reader.read(length - 1)? {
+        value = value << 8 | u64::from(*byte);
+        all_ones &= *byte == 0xff;
+    }
+
+    if all_ones && !keep_marker {
+        Ok(None)
+    } else {
+        Ok(Some(value))
+    }
+}
+
+struct Element<'a> {
+    id: u32,
+    data: &'a [u8],
+}
+
+struct ElementIter<'a> {
+    reader: ByteReader<'a>,
+}
+
+impl<'a> ElementIter<'a> {
+    fn read_element(&mut self) -> Result<Element<'a>, WebMError> {
+        let id = read_vint(&mut self.reader, true)?.ok_or(WebMError::InvalidVint)?;
+        let id = id.try_into().map_err(|_| WebMError::InvalidVint)?;
+        let data = match read_vint(&mut self.reader, false)? {
+            Some(size) => {
+                let size = size
+                    .try_into()
+                    .map_err(|_| WebMError::InvalidElementSize(id))?;
+                self.reader
+                    .read(size)
+                    .map_err(|_| WebMError::InvalidElementSize(id))?
+            }
+            // Unknown sizes are only used by master elements in live
+            // streams, which then extend to the end of their parent.
+            None => self.reader.rest(),
+        };
+        Ok(Element { id, data })
+    }
+}
+
+impl<'a> Iterator for ElementIter<'a> {
+    type Item = Result<Element<'a>, WebMError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_empty() {
+            return None;
+        }
+        let item = self.read_element();
+        if item.is_err() {
+            self.reader.rest();
+        }
+        Some(item)
+    }
+}
+
+fn elements(data: &[u8]) -> ElementIter<'_> {
+    ElementIter {
+        reader: ByteReader::new(data),
+    }
+}
+
+fn find_element(data: &[u8], id: u32) -> Result<Option<&[u8]>, WebMError> {
+    for element in elements(data) {
+        let element = element?;
+        if element.id == id {
+            return Ok(Some(element.data));
+        }
+    }
+    Ok(None)
+}
+
+fn read_uint(data: &[u8]) -> Result<u64, WebMError> {
+    if data.len() > 8 {
+        return Err(WebMError::InvalidVint);
+    }
+    Ok(data
+        .iter()
+        .fold(0, |value, byte| value << 8 | u64::from(*byte)))
+}
+
+/// Returns the payload of the `Segment` element if there is one, so parsing
+/// works both on whole files and on bare clusters.
+fn segment_payload(data: &[u8]) -> Result<&[u8], WebMError> {
+    for element in elements(data) {
+        let element = element?;
+        if element.id == SEGMENT {
+            return Ok(element.data);
+        }
+    }
+    Ok(data)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    pub track_number: u64,
+    /// `ContentEncKeyID` of the track, if it is encrypted.
+    pub key_id: Option<Vec<u8>>,
+}
+
+impl Track {
+    fn parse(data: &[u8]) -> Result<Self, WebMError> {
+        let track_number = find_element(data, TRACK_NUMBER)?
+            .ok_or(WebMError::MissingElement(TRACK_NUMBER))
+            .and_then(read_uint)?;
+
+        let mut key_id = None;
+        if let Some(encodings) = find_element(data, CONTENT_ENCODINGS)? {
+            for encoding in elements(encodings) {
+                let encoding = encoding?;
+                if encoding.id != CONTENT_ENCODING {
+                    continue;
+                }
+                if let Some(encryption) = find_element(encoding.data, CONTENT_ENCRYPTION)? {
+                    key_id = find_element(encryption, CONTENT_ENC_KEY_ID)?.map(<[u8]>::to_vec);
+                }
+            }
+        }
+
+        Ok(Self {
+            track_number,
+            key_id,
+        })
+    }
+
+    /// `InitDataType::WebM` init data for the track's key.
+    pub fn init_data(&self) -> Option<InitData> {
+        self.key_id.as_deref().map(InitData::webm)
+    }
+}
+
+/// The `Info` and `Tracks` elements of a WebM stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub timecode_scale: u64,
+    pub tracks: Vec<Track>,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, WebMError> {
+        let segment = segment_payload(data)?;
+
+        let timecode_scale = match find_element(segment, INFO)? {
+            Some(info) => match find_element(info, TIMECODE_SCALE)? {
+                Some(scale) => read_uint(scale)?,
+                None => DEFAULT_TIMECODE_SCALE,
+            },
+            None => DEFAULT_TIMECODE_SCALE,
+        };
+
+        let tracks_data =
+            find_element(segment, TRACKS)?.ok_or(WebMError::MissingElement(TRACKS))?;
+        let mut tracks = Vec::new();
+        for entry in elements(tracks_data) {
+            let entry = entry?;
+            if entry.id == TRACK_ENTRY {
+                tracks.push(Track::parse(entry.data)?);
+            }
+        }
+
+        Ok(Self {
+            timecode_scale,
+            tracks,
+        })
+    }
+
+    pub fn track(&self, track_number: u64) -> Option<&Track> {
+        self.tracks
+            .iter()
+            .find(|track| track.track_number == track_number)
+    }
+
+    /// Parses every `SimpleBlock` and `Block` in the clusters of `data`.
+    pub fn samples(&self, data: &[u8]) -> Result<Vec<Sample>, WebMError> {
+        let mut samples = Vec::new();
+        for element in elements(segment_payload(data)?) {
+            let element = element?;
+            if element.id == CLUSTER {
+                self.parse_cluster(element.data, &mut samples)?;
+            }
+        }
+        Ok(samples)
+    }
+
+    fn parse_cluster(&self, data: &[u8], samples: &mut Vec<Sample>) -> Result<(), WebMError> {
+        let mut timecode = 0;
+        for element in elements(data) {
+            let element = element?;
+            match element.id {
+                TIMECODE => timecode = read_uint(element.data)?,
+                SIMPLE_BLOCK => samples.push(self.parse_block(element.data, timecode)?),
+                BLOCK_GROUP => {
+                    if let Some(block) = find_element(element.data, BLOCK)? {
+                        samples.push(self.parse_block(block, timecode)?);
+                    }
+                }
+                // A cluster of unknown size runs into the next one.
+                CLUSTER => self.parse_cluster(element.data, samples)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_block(&self, data: &[u8], cluster_timecode: u64) -> Result<Sample, WebMError> {
+        let mut reader = ByteReader::new(data);
+        let track_number = read_vint(&mut reader, false)?.ok_or(WebMError::InvalidVint)?;
+        let relative_timecode = reader.read_u16()? as i16;
+        let flags = reader.read_u8()?;
+        if flags & 0x06 != 0 {
+            return Err(WebMError::UnsupportedLacing);
+        }
+
+        let track = self
+            .track(track_number)
+            .ok_or(WebMError::UnknownTrack(track_number))?;
+        let track_id =
+            u32::try_from(track_number).map_err(|_| WebMError::InvalidTrackNumber(track_number))?;
+        let ticks = i64::try_from(cluster_timecode)
+            .ok()
+            .and_then(|ticks| ticks.checked_add(i64::from(relative_timecode)))
+            .ok_or(WebMError::TimecodeOverflow)?;
+        let timestamp = i128::from(ticks.max(0)) * i128::from(self.timecode_scale) / 1000;
+        let timestamp = i64::try_from(timestamp).map_err(|_| WebMError::TimecodeOverflow)?;
+
+        let mut sample = Sample {
+            track_id,
+            data: Vec::new(),
+            timestamp,
+            encryption_scheme: EncryptionScheme::Unencrypted,
+            key_id: Vec::new(),
+            iv: Vec::new(),
+            subsamples: Vec::new(),
+            pattern: Pattern::default(),
+        };
+
+        let key_id = match track.key_id {
+            Some(ref key_id) => key_id,
+            None => {
+                sample.data = reader.rest().to_vec();
+                return Ok(sample);
+            }
+        };
+
+        let signal = reader.read_u8()?;
+        if signal & SIGNAL_ENCRYPTED == 0 {
+            sample.data = reader.rest().to_vec();
+            return Ok(sample);
+        }
+
+        let mut iv = reader.read(IV_SIZE)?.to_vec();
+        iv.resize(16, 0);
+
+        let mut partitions = Vec::new();
+        if signal & SIGNAL_PARTITIONED != 0 {
+            let count = reader.read_u8()?;
+            for _ in 0..count {
+                partitions.push(reader.read_u32()?);
+            }
+        }
+        let data = reader.rest();
+
+        sample.subsamples = partition_subsamples(&partitions, data.len())?;
+        sample.data = data.to_vec();
+        sample.encryption_scheme = EncryptionScheme::Cenc;
+        sample.key_id = key_id.clone();
+        sample.iv = iv;
+        Ok(sample)
+    }
+}
+
+/// Converts WebM partition offsets into CENC subsamples. Partitions
+/// alternate between clear and encrypted ranges, starting with a clear one.
+fn partition_subsamples(partitions: &[u32], size: usize) -> Result<Vec<SubsampleEntry>, WebMError> {
+    if partitions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut bounds = vec![0];
+    bounds.extend_from_slice(partitions);
+    bounds.push(size.try_into().map_err(|_| WebMError::InvalidPartitions)?);
+    if bounds.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err(WebMError::InvalidPartitions);
+    }
+
+    let ranges: Vec<u32> = bounds.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    Ok(ranges
+        .chunks(2)
+        .map(|pair| SubsampleEntry {
+            clear_bytes: pair[0],
+            cipher_bytes: pair.get(1).copied().unwrap_or(0),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+fn ebml_element(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend_from_slice(&(data.len() as u64 | 1 << 56).to_be_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+#[test]
+fn test_partitioned_simple_block() {
+    let key_id = [0x55; 16];
+    let encryption = ebml_element(&[0x50, 0x35], &ebml_element(&[0x47, 0xE2], &key_id));
+    let encodings = ebml_element(&[0x6D, 0x80], &ebml_element(&[0x62, 0x40], &encryption));
+    let track = [ebml_element(&[0xD7], &[1]), encodings].concat();
+    let tracks = ebml_element(&[0x16, 0x54, 0xAE, 0x6B], &ebml_element(&[0xAE], &track));
+
+    let mut block = vec![0x81, 0x00, 0x0A, 0x80, 0x03];
+    block.extend_from_slice(&[0x77; 8]);
+    block.extend_from_slice(&[2, 0, 0, 0, 4, 0, 0, 0, 20]);
+    block.extend_from_slice(&[0xAA; 24]);
+    let cluster = [
+        ebml_element(&[0xE7], &[0x03, 0xE8]),
+        ebml_element(&[0xA3], &block),
+    ]
+    .concat();
+    let segment = ebml_element(
+        &[0x18, 0x53, 0x80, 0x67],
+        &[tracks, ebml_element(&[0x1F, 0x43, 0xB6, 0x75], &cluster)].concat(),
+    );
+
+    let header = Header::parse(&segment).unwrap();
+    assert_eq!(header.tracks[0].init_data(), Some(InitData::webm(&key_id)));
+
+    let samples = header.samples(&segment).unwrap();
+    assert_eq!(samples.len(), 1);
+    let sample = &samples[0];
+    assert_eq!(sample.timestamp, 1_010_000);
+    assert_eq!(sample.encryption_scheme, EncryptionScheme::Cenc);
+    assert_eq!(sample.key_id, key_id);
+    assert_eq!(sample.iv, [&[0x77; 8][..], &[0; 8]].concat());
+    assert_eq!(sample.data.len(), 24);
+    assert_eq!(
+        sample.subsamples,
+        vec![
+            SubsampleEntry {
+                clear_bytes: 4,
+                cipher_bytes: 16
+            },
+            SubsampleEntry {
+                clear_bytes: 4,
+                cipher_bytes: 0
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_block_overflow() {
+    let tracks = [&[1][..], &[0x01, 0, 0, 0, 0]].map(|number| {
+        let track = ebml_element(&[0xAE], &ebml_element(&[0xD7], number));
+        ebml_element(&[0x16, 0x54, 0xAE, 0x6B], &track)
+    });
+    let header = Header::parse(&ebml_element(&[0x18, 0x53, 0x80, 0x67], &tracks[0])).unwrap();
+    let block = [0x81, 0x00, 0x01, 0x80];
+    assert_eq!(
+        header.parse_block(&block, i64::MAX as u64).unwrap_err(),
+        WebMError::TimecodeOverflow
+    );
+    assert_eq!(
+        header.parse_block(&block, u64::MAX).unwrap_err(),
+        WebMError::TimecodeOverflow
+    );
+
+    // Track number 2^32, as an 8 byte vint.
+    let header = Header::parse(&ebml_element(&[0x18, 0x53, 0x80, 0x67], &tracks[1])).unwrap();
+    let block = [0x01, 0, 0, 0x01, 0, 0, 0, 0, 0x00, 0x00, 0x80];
+    assert_eq!(
+        header.parse_block(&block, 0).unwrap_err(),
+        WebMError::InvalidTrackNumber(1 << 32)
+    );
+}